@@ -1,20 +1,48 @@
 use clap::CommandFactory;
+use clap_complete::Shell;
+use std::path::{Path, PathBuf};
 
 #[path = "src/cli.rs"]
 mod cli;
 
 fn man_gen() -> std::io::Result<()> {
-    let out_dir = std::path::PathBuf::from(
+    let out_dir = PathBuf::from(
         #[allow(clippy::unnecessary_lazy_evaluations)]
         std::env::var_os("OUT_DIR").ok_or_else(|| std::io::ErrorKind::NotFound)?,
     );
+    assets_gen(&out_dir)?;
+
+    // packages set TERES_ASSETS_DIR to get the man page and completions somewhere they can be
+    // installed from, OUT_DIR changes with every build
+    println!("cargo:rerun-if-env-changed=TERES_ASSETS_DIR");
+    if let Some(assets_dir) = std::env::var_os("TERES_ASSETS_DIR") {
+        let assets_dir = PathBuf::from(assets_dir);
+        std::fs::create_dir_all(&assets_dir)?;
+        assets_gen(&assets_dir)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the man page and the completion script of each shell to `dir`
+fn assets_gen(dir: &Path) -> std::io::Result<()> {
     let cmd = cli::Cli::command();
 
-    let man = clap_mangen::Man::new(cmd);
+    let man = clap_mangen::Man::new(cmd.clone());
     let mut buffer: Vec<u8> = Default::default();
     man.render(&mut buffer)?;
 
-    std::fs::write(out_dir.join("teres.1"), buffer)?;
+    std::fs::write(dir.join("teres.1"), buffer)?;
+
+    for shell in [
+        Shell::Bash,
+        Shell::Zsh,
+        Shell::Fish,
+        Shell::PowerShell,
+        Shell::Elvish,
+    ] {
+        clap_complete::generate_to(shell, &mut cmd.clone(), "teres", dir)?;
+    }
 
     Ok(())
 }
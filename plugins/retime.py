@@ -0,0 +1,28 @@
+import vapoursynth as vs
+from vapoursynth import core
+
+
+# plays each (start, end, speed) segment of the clip, in seconds, at its speed. an end of None
+# lasts until the end of the clip
+def Retime(clip, segments):
+    if not isinstance(clip, vs.VideoNode):
+        raise ValueError("This is not a clip")
+
+    fps = float(clip.fps)
+    length = clip.num_frames / fps
+
+    frames = []
+    out_start = 0.0
+    for start, end, speed in segments:
+        end = length if end is None else min(end, length)
+        if end <= start:
+            continue
+        out_end = out_start + (end - start) / speed
+        # rounding the running total keeps the video in sync with the audio segments
+        for n in range(round(out_start * fps), round(out_end * fps)):
+            source = (start + (n / fps - out_start) * speed) * fps
+            frames.append(min(int(source), clip.num_frames - 1))
+        out_start = out_end
+
+    blank = core.std.BlankClip(clip, length=max(len(frames), 1))
+    return core.std.FrameEval(blank, lambda n: clip[frames[n]])
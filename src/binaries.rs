@@ -0,0 +1,73 @@
+use crate::config::Config;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// `advanced.<program>_path` values of the loaded config, for lookups without the settings at hand
+static CONFIGURED: Mutex<Vec<(&str, String)>> = Mutex::new(Vec::new());
+
+/// Remembers the program paths of the config
+pub fn configure(settings: &Config) {
+    *CONFIGURED.lock().unwrap() = vec![
+        ("ffmpeg", settings.advanced.ffmpeg_path.clone()),
+        ("ffprobe", settings.advanced.ffprobe_path.clone()),
+        ("vspipe", settings.advanced.vspipe_path.clone()),
+    ];
+}
+
+pub fn ffmpeg() -> String {
+    find("ffmpeg", "ffmpeg")
+}
+
+pub fn ffprobe() -> String {
+    find("ffprobe", "ffmpeg")
+}
+
+pub fn vspipe() -> String {
+    find("vspipe", "vapoursynth")
+}
+
+/// The `lib` folder next to teres, where the windows installer and portable copies keep ffmpeg
+/// and VapourSynth
+fn lib_dir() -> Option<PathBuf> {
+    Some(std::env::current_exe().ok()?.parent()?.join("lib"))
+}
+
+/// A program in the lib folder, either in its own folder like `lib/ffmpeg/ffmpeg.exe` or
+/// directly in it
+pub fn bundled(name: &str, folder: &str) -> Option<PathBuf> {
+    let lib = lib_dir()?;
+    let exe = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+    [lib.join(folder).join(&exe), lib.join(&exe)]
+        .into_iter()
+        .find(|path| path.is_file())
+}
+
+/// Finds a program in the lib folder, the `TERES_<NAME>` environment variable, the config, and
+/// otherwise leaves it to the PATH, checking homebrew's folders first on macos
+fn find(name: &str, folder: &str) -> String {
+    if let Some(path) = bundled(name, folder) {
+        return path.display().to_string();
+    }
+    if let Ok(path) = std::env::var(format!("TERES_{}", name.to_uppercase())) {
+        if !path.is_empty() {
+            return path;
+        }
+    }
+    let configured = CONFIGURED.lock().unwrap();
+    if let Some((_, path)) = configured
+        .iter()
+        .find(|(program, path)| *program == name && !path.is_empty())
+    {
+        return path.clone();
+    }
+    // apps opened from finder don't get the shell's PATH with homebrew in it
+    if cfg!(target_os = "macos") {
+        for folder in ["/opt/homebrew/bin", "/usr/local/bin"] {
+            let path = PathBuf::from(folder).join(name);
+            if path.is_file() {
+                return path.display().to_string();
+            }
+        }
+    }
+    name.to_string()
+}
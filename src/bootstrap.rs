@@ -0,0 +1,120 @@
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+/// Whether a release asset is built for this platform
+pub fn for_platform(asset: &str) -> bool {
+    let asset = asset.to_lowercase();
+    let os = match std::env::consts::OS {
+        // darwin has win in it, ffms2 names its windows builds after the compiler
+        "windows" => (asset.contains("win") && !asset.contains("darwin")) || asset.contains("msvc"),
+        "macos" => ["mac", "darwin", "osx"].iter().any(|os| asset.contains(os)),
+        _ => asset.contains("linux"),
+    };
+    let arm = ["arm64", "aarch64"].iter().any(|arch| asset.contains(arch));
+    os && arm == (std::env::consts::ARCH == "aarch64")
+}
+
+/// The latest release of a GitHub repository, as returned by its API
+pub fn latest_release(repo: &str) -> Result<Value, String> {
+    ureq::get(&format!(
+        "https://api.github.com/repos/{}/releases/latest",
+        repo
+    ))
+    .set("User-Agent", "teres")
+    .timeout(Duration::from_secs(30))
+    .call()
+    .map_err(|error| error.to_string())?
+    .into_json()
+    .map_err(|error| error.to_string())
+}
+
+pub fn download(url: &str, path: &Path) -> Result<(), String> {
+    let mut file = std::fs::File::create(path).map_err(|error| error.to_string())?;
+    let response = ureq::get(url)
+        .set("User-Agent", "teres")
+        .call()
+        .map_err(|error| error.to_string())?;
+    std::io::copy(&mut response.into_reader(), &mut file).map_err(|error| error.to_string())?;
+    Ok(())
+}
+
+/// Unpacks an archive into `folder` with the tools the system has, returning the `wanted` file
+/// in it. A download that is no archive is returned as is
+pub fn unpack(
+    archive: &Path,
+    folder: &Path,
+    wanted: &dyn Fn(&Path) -> bool,
+) -> Result<PathBuf, String> {
+    let name = archive.to_string_lossy().to_lowercase();
+    let archives = [
+        ".7z", ".zip", ".tar", ".tar.gz", ".tgz", ".tar.xz", ".tar.bz2",
+    ];
+    if !archives.iter().any(|extension| name.ends_with(extension)) {
+        return Ok(archive.to_path_buf());
+    }
+    let unpacked = folder.join("unpacked");
+    let (program, args): (&str, Vec<String>) = if name.ends_with(".7z") {
+        (
+            "7z",
+            vec![
+                "x".to_string(),
+                "-y".to_string(),
+                format!("-o{}", unpacked.display()),
+                archive.display().to_string(),
+            ],
+        )
+    } else if name.ends_with(".zip") && cfg!(target_os = "linux") {
+        (
+            "unzip",
+            vec![
+                "-q".to_string(),
+                archive.display().to_string(),
+                "-d".to_string(),
+                unpacked.display().to_string(),
+            ],
+        )
+    } else {
+        // bsdtar on macos and windows reads zip files as well
+        (
+            "tar",
+            vec![
+                "-xf".to_string(),
+                archive.display().to_string(),
+                "-C".to_string(),
+                unpacked.display().to_string(),
+            ],
+        )
+    };
+    std::fs::create_dir_all(&unpacked).map_err(|error| error.to_string())?;
+    let output = Command::new(program)
+        .args(&args)
+        .output()
+        .map_err(|error| format!("could not run {} to unpack it: {}", program, error))?;
+    if !output.status.success() {
+        return Err(format!("{} could not unpack {}", program, name));
+    }
+
+    let mut files = vec![];
+    find_files(&unpacked, wanted, &mut files);
+    // archives with both builds keep the 64 bit one in a folder like win64 or x64
+    files.sort_by_key(|file| !file.to_string_lossy().contains("64"));
+    files
+        .into_iter()
+        .next()
+        .ok_or_else(|| String::from("the download has nothing for this platform"))
+}
+
+fn find_files(folder: &Path, wanted: &dyn Fn(&Path) -> bool, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(folder) else {
+        return;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            find_files(&path, wanted, files);
+        } else if wanted(&path) {
+            files.push(path);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::helpers::exec;
+use crate::rendering::{
+    hardware_device_args, video_codec_args, video_filter_args, CommandWithArgs, Render, Rendering,
+};
+use crate::sequence;
+use indicatif::{MultiProgress, ProgressBar};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+/// Renders a video as `advanced.chunks` frame ranges with a vspipe and ffmpeg pipeline each,
+/// then joins them without reencoding, spreading cpu bound encodes over more cores
+pub fn render(
+    render: &Render,
+    command: CommandWithArgs,
+    progress: ProgressBar,
+) -> Result<ExitStatus, std::io::Error> {
+    let settings = &render.settings;
+    let frames = match frame_count(&command.vspipe_exe, &render.script_path) {
+        Some(frames) if frames > 0 => frames,
+        _ => {
+            warn!(
+                "Could not count the frames of {}, rendering it in one piece",
+                render.video_path.display()
+            );
+            return Ok(exec(command, progress));
+        }
+    };
+    // loading the script for its frame count has ffms2 index every source once, so the chunks
+    // only read the index instead of all writing the same cachefile at once
+    let mut indexes = render
+        .joined
+        .iter()
+        .filter(|source| source.first_frame.is_none())
+        .map(|source| &source.index_path)
+        .chain((!sequence::is_sequence(&render.video_path)).then_some(&render.index_path));
+    if let Some(missing) = indexes.find(|index| !index.exists()) {
+        warn!(
+            "{} was not indexed, rendering {} in one piece",
+            missing.display(),
+            render.video_path.display()
+        );
+        return Ok(exec(command, progress));
+    }
+    let count = u64::from(settings.advanced.chunks).min(frames);
+    let size = frames.div_ceil(count);
+    debug!("Rendering {} frames in {} chunks", frames, count);
+
+    let bars = MultiProgress::new();
+    let style = progress.style();
+    progress.finish_and_clear();
+
+    let chunks: Vec<PathBuf> = (0..count)
+        .map(|index| {
+            render
+                .script_path
+                .with_extension(format!("chunk{}.mkv", index))
+        })
+        .collect();
+    let pipelines: Vec<_> = chunks
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let start = index as u64 * size;
+            let end = ((index as u64 + 1) * size).min(frames) - 1;
+
+            let mut chunk_command = command.clone();
+            chunk_command.vspipe_args = vec![
+                render.script_path.display().to_string(),
+                "-".to_string(),
+                "-p".to_string(),
+                "-c".to_string(),
+                "y4m".to_string(),
+                "-s".to_string(),
+                start.to_string(),
+                "-e".to_string(),
+                end.to_string(),
+            ];
+            chunk_command
+                .vspipe_args
+                .extend(settings.advanced.vspipe_args.iter().cloned());
+            let mut ffmpeg_args: Vec<String> =
+                ["-loglevel", "error", "-hide_banner", "-nostats", "-y"]
+                    .iter()
+                    .map(|arg| arg.to_string())
+                    .collect();
+            ffmpeg_args.append(&mut hardware_device_args(settings));
+            ffmpeg_args.extend(["-i", "-", "-an"].iter().map(|arg| arg.to_string()));
+            ffmpeg_args.append(&mut video_filter_args(settings));
+            ffmpeg_args.append(&mut video_codec_args(settings));
+            if settings.advanced.threads > 0 {
+                ffmpeg_args.push("-threads".to_string());
+                ffmpeg_args.push(settings.advanced.threads.to_string());
+            }
+            ffmpeg_args.extend(settings.encoding.extra_args.iter().cloned());
+            ffmpeg_args.push(chunk.display().to_string());
+            chunk_command.ffmpeg_args = ffmpeg_args;
+            chunk_command.output_filename = chunk.display().to_string();
+
+            let bar = bars.add(
+                ProgressBar::new(100)
+                    .with_style(style.clone())
+                    .with_message(progress.message()),
+            );
+            std::thread::spawn(move || exec(chunk_command, bar))
+        })
+        .collect();
+    let statuses: Vec<ExitStatus> = pipelines
+        .into_iter()
+        .map(|pipeline| pipeline.join().expect("Chunk render thread failed"))
+        .collect();
+
+    let list = render.script_path.with_extension("chunks.txt");
+    let result = match statuses.iter().find(|status| !status.success()) {
+        Some(failed) => Ok(*failed),
+        None => write_list(&list, &chunks).and_then(|_| {
+            let join = Rendering::build_ffmpeg_command(render, false, Some(&list), None)?;
+            Ok(exec(join, ProgressBar::hidden()))
+        }),
+    };
+    for file in chunks.iter().chain(std::iter::once(&list)) {
+        let _ = std::fs::remove_file(file);
+    }
+    result
+}
+
+/// Number of frames the script outputs, from `vspipe --info`
+fn frame_count(vspipe: &str, script: &Path) -> Option<u64> {
+    let output = Command::new(vspipe)
+        .args(["--info", script.to_str()?, "-"])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("Frames:"))
+        .and_then(|frames| frames.trim().parse().ok())
+}
+
+/// Lists the chunks for ffmpeg's concat demuxer
+fn write_list(list: &Path, chunks: &[PathBuf]) -> Result<(), std::io::Error> {
+    let contents: String = chunks
+        .iter()
+        .map(|chunk| {
+            format!(
+                "file '{}'\n",
+                chunk.display().to_string().replace('\'', "'\\''")
+            )
+        })
+        .collect();
+    std::fs::write(list, contents)
+}
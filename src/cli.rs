@@ -1,16 +1,248 @@
-use clap::Parser;
-use clap_verbosity_flag::Verbosity;
+use clap::{Parser, Subcommand, ValueHint};
+use clap_verbosity_flag::{InfoLevel, Verbosity};
+use std::path::PathBuf;
 
 /// Add motion blur to videos
 #[derive(Debug, Parser)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Commands>,
+
     /// Input file name(s) (space separated)
-    pub input: Vec<String>,
+    #[clap(value_hint = ValueHint::FilePath)]
+    pub input: Vec<PathBuf>,
     /// Disable user interface (CLI only)
     #[clap(short, long)]
     pub noui: bool,
+    /// Print information about the input video(s) without rendering
+    #[clap(short, long)]
+    pub info: bool,
+    /// Skip blurring and only remux the video(s) with the timescale and audio settings applied
+    #[clap(long)]
+    pub remux_only: bool,
+    /// Join the input videos in order into a single output, scaled to the resolution and frame
+    /// rate of the first
+    #[clap(long, conflicts_with_all = ["remux_only", "workers"])]
+    pub concat: bool,
+    /// Leave out the audio, same as audio.enabled = false
+    #[clap(long)]
+    pub no_audio: bool,
+    /// Render with all advanced settings disabled (software encoding, default interpolation, one thread)
+    #[clap(long)]
+    pub safe_mode: bool,
+    /// Render slowly at low priority on a quarter of the cores, to keep using the computer for
+    /// games or calls meanwhile
+    #[clap(long)]
+    pub background: bool,
+    /// Render the same video with the same settings to byte-identical video streams, same as
+    /// advanced.deterministic = true
+    #[clap(long, conflicts_with = "background")]
+    pub deterministic: bool,
+    /// Score each output against its input with vmaf, ssim and/or psnr, e.g. --metrics
+    /// vmaf,ssim, same as output.metrics
+    #[clap(long, value_delimiter = ',', value_name = "METRICS")]
+    pub metrics: Vec<String>,
+    /// Render every combination of values, e.g. --matrix blur=0.5,0.8,1.0 (also fps, weighting,
+    /// interpolated_fps or any config key)
+    #[clap(long, value_name = "KEY=VALUES")]
+    pub matrix: Vec<String>,
+    /// Render the original and the result next to each other: split (default) or stacked
+    #[clap(long, value_name = "LAYOUT", num_args = 0..=1, default_missing_value = "split")]
+    pub compare: Option<String>,
+    /// Pick the interpolated fps, output fps and blur amount for each video from its frame rate
+    #[clap(long)]
+    pub auto_settings: bool,
+    /// Continue with the remaining videos when one fails, exiting with an error at the end
+    #[clap(long)]
+    pub keep_going: bool,
+    /// Render on other machines running teres worker instead, e.g. --workers 192.168.1.2,pc2:7420
+    #[clap(long, value_name = "HOST[:PORT]", value_delimiter = ',')]
+    pub workers: Vec<String>,
+    /// Render videos added through a REST API on [ADDRESS:]PORT instead (localhost unless an
+    /// address is given)
+    #[clap(long, value_name = "PORT")]
+    pub serve: Option<String>,
+    /// Write a report of the rendered videos to a .json or .csv file
+    #[clap(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub report: Option<PathBuf>,
+    /// Action once all videos are rendered: shutdown, sleep or command:<command>
+    #[clap(long, value_name = "ACTION")]
+    pub when_done: Option<String>,
+    /// Override a configuration value, e.g. --set blending.amount=0.5
+    #[clap(long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+    /// Encode for uploading to a platform, picking the container, codec, bitrate and resolution
+    #[clap(long, value_name = "PLATFORM", value_parser = ["discord", "youtube", "tiktok"])]
+    pub export_for: Option<String>,
+    /// Apply a preset from the [presets] section of the config over the other settings
+    #[clap(short, long)]
+    pub preset: Option<String>,
+    /// Extra ffmpeg options added before the output, e.g. --ffmpeg-args="-tune film", same as
+    /// encoding.extra_args
+    #[clap(long, value_name = "ARGS", allow_hyphen_values = true)]
+    pub ffmpeg_args: Option<String>,
+    /// Print the resolved configuration and where each value comes from
+    #[clap(long)]
+    pub print_config: bool,
+    /// Wait for enter before closing, same as advanced.pause_on_exit = "always"
+    #[clap(long)]
+    pub pause: bool,
 
     #[clap(flatten)]
-    pub verbose: Verbosity,
+    pub verbose: Verbosity<InfoLevel>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// Rename already rendered videos to a new filename template
+    Rename {
+        /// Filename template, e.g. "{name}_blur-{output_fps}fps"
+        #[clap(short, long)]
+        template: String,
+        /// Rendered video file(s) or folder(s) containing them
+        #[clap(required = true, value_hint = ValueHint::AnyPath)]
+        paths: Vec<PathBuf>,
+        /// Only print what would be renamed
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Render a video with the same settings as an output rendered before, read from its
+    /// metadata or sidecar
+    Reproduce {
+        /// Output of an earlier render
+        #[clap(value_hint = ValueHint::FilePath)]
+        blurred: PathBuf,
+        /// Video to render with its settings
+        #[clap(value_hint = ValueHint::FilePath)]
+        input: PathBuf,
+    },
+    /// Pause the renders of other running teres processes
+    Pause,
+    /// Resume renders paused with the pause command
+    Resume,
+    /// Watch a folder and render new videos as they appear, resuming after crashes
+    Watch {
+        /// Folder to watch
+        #[clap(value_hint = ValueHint::DirPath)]
+        folder: PathBuf,
+        /// Seconds between checks for new videos
+        #[clap(long, default_value_t = 10)]
+        interval: u64,
+        /// Render with a preset from the [presets] section of the config
+        #[clap(short, long)]
+        preset: Option<String>,
+    },
+    /// Manage the configuration file
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Manage the render queue and its history
+    Queue {
+        #[clap(subcommand)]
+        command: QueueCommands,
+    },
+    /// Render videos sent by other teres instances started with --workers
+    Worker {
+        /// Port to listen on
+        #[clap(long, default_value_t = 7420)]
+        port: u16,
+    },
+    /// Run the watcher in the background on login as a systemd user unit or scheduled task
+    Service {
+        #[clap(subcommand)]
+        command: ServiceCommands,
+    },
+    /// Check that ffmpeg, VapourSynth and the plugins teres renders with are installed
+    Doctor,
+    /// Replace teres with the latest release, verified with its minisign signature. Builds made
+    /// without TERES_UPDATE_PUBLIC_KEY refuse to update
+    Update {
+        /// Only check whether a newer version is available
+        #[clap(long)]
+        check: bool,
+    },
+    /// Render a test clip with each builtin preset and compare the frames to the ones recorded
+    /// before on this machine, to catch plugin or ffmpeg updates that change the result
+    #[clap(hide = true)]
+    Selftest {
+        /// Record the current results as the goldens instead of comparing
+        #[clap(long)]
+        record: bool,
+    },
+    /// Print the completion script for a shell, e.g. teres completions bash >
+    /// /usr/share/bash-completion/completions/teres
+    Completions {
+        #[clap(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommands {
+    /// Create the config file by answering a few questions
+    Init {
+        /// Overwrite an existing config file without asking
+        #[clap(long)]
+        force: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum QueueCommands {
+    /// List the videos waiting in the queue
+    List,
+    /// Add videos to the queue to render later with queue run
+    Add {
+        /// Video file(s) to add
+        #[clap(required = true, value_hint = ValueHint::FilePath)]
+        paths: Vec<PathBuf>,
+        /// Override a configuration value for these videos, e.g. --set blending.amount=0.5
+        #[clap(long = "set", value_name = "KEY=VALUE")]
+        overrides: Vec<String>,
+        /// Render these videos with a preset from the [presets] section of the config
+        #[clap(short, long)]
+        preset: Option<String>,
+    },
+    /// Remove the video at a position shown by queue list
+    Remove { position: usize },
+    /// Remove every video from the queue
+    Clear,
+    /// Move a video to another position in the queue
+    Move { from: usize, to: usize },
+    /// Render every video in the queue, removing each once it is rendered
+    Run {
+        /// Continue with the remaining videos when one fails, leaving it in the queue
+        #[clap(long)]
+        keep_going: bool,
+    },
+    /// Export the history of processed renders
+    Export {
+        /// Export as csv instead of json lines
+        #[clap(long)]
+        csv: bool,
+        /// File to write to instead of stdout
+        #[clap(short, long, value_hint = ValueHint::FilePath)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ServiceCommands {
+    /// Install and start the watcher service for a folder
+    Install {
+        /// Folder to watch
+        #[clap(value_hint = ValueHint::DirPath)]
+        folder: PathBuf,
+        /// Seconds between checks for new videos
+        #[clap(long, default_value_t = 10)]
+        interval: u64,
+    },
+    /// Stop and remove the watcher service
+    Uninstall,
+    /// Start the installed watcher service
+    Start,
+    /// Stop the running watcher service
+    Stop,
 }
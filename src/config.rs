@@ -1,10 +1,198 @@
+use crate::binaries;
+use crate::container::Container;
+use crate::helpers;
+use crate::presets;
+use crate::schedule;
 use dirs::home_dir;
-use std::{fs, io::Read};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
+use toml::Value;
+
+/// Default configuration, also written out as the global config file on first run
+pub const DEFAULT_CONFIG: &str = r#"# Teres Configuration
+# For documentation for what each value means and the accecpted values see
+# https://animafps.github.io/teres/docs/configuration
+
+version = 2 # used to upgrade this file when the format changes, don't edit
+
+[blending]
+enabled = true
+amount = 1.0
+adaptive = false # blur fast motion fully, slow motion less and static parts not at all
+shutter_angle = 0.0 # blur as a shutter angle in degrees instead of amount, e.g. 180 is 0.5 (0 uses amount)
+weighting = "equal" # equal/gaussian/gaussian_sym/pyramid/pyramid_sym
+output_fps = 60
+
+[interpolation]
+enabled = true
+fps = 480.0
+quality = "balanced" # fast/balanced/quality, trades interpolation speed for fewer artifacts
+
+[encoding]
+quality = 18
+detailed_filename = false
+container = "mp4" # mp4/mkv/mov/gif/webp/png_sequence/... or source to keep the container of each input
+filename_template = "{name}_blur" # {name}/{fps}/{program}/{output_fps}/{amount}/{weighting}/{quality}
+animated_fps = 30 # frame rate of gif and webp outputs
+animated_width = 720 # gif and webp outputs wider than this are scaled down (0 keeps the size)
+codec = "h264" # h264/hevc/vp9, hevc makes smaller files that take longer to encode and play on fewer devices (webm is always vp9)
+preset = "default" # encoder speed, e.g. ultrafast..veryslow for software or p1..p7 for nvidia (default is superfast, or the slowest for gpus)
+pixel_format = "yuv420p" # yuv420p/yuv420p10le/yuv444p, 10-bit bands less and 4:4:4 keeps colored text and edges sharp
+extra_args = [] # ffmpeg options added as is before the output, e.g. ["-tune", "film"]
+max_bitrate_kbps = 0 # bitrate the software and vp9 encoders stay under while keeping the quality, for platforms with a limit (0 is off)
+target_size_mb = 0.0 # encode in two passes at the bitrate that fits this size, e.g. 10 for discord (0 is off)
+preserve_alpha = false # keeps the transparency of the input, needs a mov (ProRes 4444), webm or mkv (VP9) container
+
+[timescale]
+input = 1.0
+output = 1.0
+adjust_audio_pitch = false
+curve = [] # speed ramp keyframes of [seconds, speed], e.g. [[0, 1.0], [5.0, 0.25], [8.0, 1.0]]
+
+[filters]
+brightness = 1.0
+contrast = 1.0
+saturation = 1.0
+
+[mask]
+image = "" # black and white image the size of the video, blur is only applied where it is white
+rect = [] # only blur this region, [x, y, width, height] e.g. the gameplay but not the HUD
+invert = false # blur everything except the masked region instead
+
+[transform]
+reverse = false # play the video backwards
+pingpong = false # play the video forwards then backwards
+crop = [] # keep only this region before blurring, [x, y, width, height] (empty is off)
+scale = "" # resize to WxH before blurring, e.g. "1920x1080" (empty keeps the size)
+resizer = "bicubic" # bilinear/bicubic/lanczos/spline36/point
+rotate = 0 # 0/90/180/270 degrees clockwise
+flip = "" # horizontal/vertical/both (empty is off)
+
+[output]
+resolution = "" # scale the result to WxH or 720p/1080p/1440p/4k, add -vertical for e.g. 1080p-vertical (empty keeps the size)
+pad = "letterbox" # letterbox/blur/stretch, how a different aspect ratio is filled
+intro = "" # video joined before the result, with its audio (empty is off)
+outro = "" # video joined after the result, with its audio (empty is off)
+thumbnail = "" # middle or a timestamp in seconds to save that frame next to the output (empty is off)
+thumbnail_format = "jpg" # jpg/png
+verify = false # decode each output after rendering to catch corrupt or cut off files, rendering them once more
+metrics = [] # vmaf/ssim/psnr scores of each output against its input, shown and saved in the history and --report
+embed_settings = true # write the settings used into the metadata of the output
+sidecar = "toml" # toml/json/off, the settings file written next to the output
+
+[color]
+lut_path = "" # .cube LUT applied to the result, after the grade below
+saturation = 1.0 # 0.0 to 3.0
+contrast = 1.0 # -2.0 to 2.0
+gamma = 1.0 # 0.1 to 10.0
+
+[overlay]
+image_path = "" # png watermark composited over the result, transparency is kept (empty is off)
+position = "bottom-right" # top-left/top-right/bottom-left/bottom-right/center
+opacity = 1.0 # 0.0 to 1.0
+
+[vfr]
+mode = "auto" # auto/cfr/off (auto only converts when the input is variable frame rate)
+fps = 0.0 # frame rate to convert to (0 uses the average frame rate of the input)
+sequence_fps = 60.0 # frame rate of image sequence inputs like frames_%05d.png, which have none of their own
+
+[audio]
+enabled = true # false renders video-only outputs without any audio
+keep_original = false # also include the untouched audio as a second track (best with mkv)
+music_path = "" # audio file mixed under the original audio, looped to the length of the video
+music_volume = 0.3 # volume of the music, 1.0 keeps it as loud as the file
+normalize = false # even out the loudness to -16 LUFS (EBU R128 loudnorm, measured in a first pass)
+
+[notifications]
+desktop = true # show a desktop notification when each render finishes or fails
+webhook_url = "" # discord/slack compatible webhook to post render results to
+copy_path = false # copy the path of each finished render to the clipboard
+open_folder = false # open the folder of each finished render in the file manager
+
+[upload]
+provider = "" # youtube/s3/sftp, uploads each finished render (empty is off)
+public_url = "" # address the s3 or sftp destination is served from, to report links to uploads
+s3_bucket = ""
+s3_prefix = "" # folder in the bucket
+s3_endpoint = "" # for s3 compatible storage like R2 or MinIO (empty is AWS)
+sftp_destination = "" # user@host:folder to copy renders to
+youtube_client_id = "" # the secret and refresh token go in credentials.toml next to this file, or TERES_YOUTUBE_CLIENT_SECRET and TERES_YOUTUBE_REFRESH_TOKEN
+youtube_privacy = "unlisted" # public/unlisted/private
+
+[schedule]
+start_time = "" # HH:MM, queue run and watch only render from then, e.g. "01:00" (empty is any time)
+end_time = "" # HH:MM, a running render is paused from then until start_time, e.g. "07:00"
+
+[monitoring]
+enabled = false # show cpu and gpu load and temperatures next to the progress (the gpu needs nvidia-smi)
+max_temperature = 0 # stop the render when the cpu or gpu gets hotter than this many degrees celsius (0 is off)
+
+[debug]
+overlay_frame_numbers = false # write the source frame number on each output frame, marking interpolated ones
+compare = "" # split/stacked, render the original next to the result to compare settings (empty is off)
+
+[advanced]
+threads = 0 # threads used by vapoursynth and ffmpeg (0 is automatic)
+process_priority = "normal" # low/normal/high
+cpu_affinity = [] # cores vapoursynth and ffmpeg may run on, e.g. [0, 1, 2, 3] (empty is all)
+chunks = 0 # split each video into this many parts rendered in parallel, faster for cpu encoding on many cores (0 is off)
+disk_check = "refuse" # refuse/warn/off, what to do when a render likely won't fit on the drive
+ffmpeg_path = "" # ffmpeg to use when there is none in a lib folder next to teres or TERES_FFMPEG (empty searches the PATH)
+ffprobe_path = "" # same for ffprobe and TERES_FFPROBE
+vspipe_path = "" # same for vspipe and TERES_VSPIPE
+vspipe_args = [] # vspipe options added as is, e.g. ["--requests", "4"]
+pause_on_exit = "auto" # auto/always/never, wait for enter before closing (auto only when teres opened its own window)
+keep_index = false # keep the .ffindex of each video so rendering it again skips indexing
+index_cache = "" # folder kept indexes go in (empty is the teres folder in the user cache folder)
+deterministic = false # render the same video with the same settings to identical video streams, slower
+api_folders = [] # folders videos added through the --serve api may come from (empty refuses every video)
+
+[advanced.encoding]
+gpu = false
+gpu_type = "nvidia" # nvidia/intel/amd/apple/vaapi
+vaapi_device = "/dev/dri/renderD128" # render node used with gpu_type = "vaapi"
+deduplicate = false
+
+[advanced.performance]
+vs_threads = 0 # threads vapoursynth's core uses, to leave some to the encoder (0 uses advanced.threads)
+interpolation_gpu = "auto" # auto/on/off, whether svp interpolates on the gpu (auto follows advanced.encoding.gpu)
+encoder_gpu_index = -1 # gpu nvidia encoding runs on, e.g. a different one from rife_gpu_index (-1 is the default)
+max_cache_mb = 0 # memory vapoursynth caches frames in, lower it when large renders run out of memory (0 is vapoursynth's default)
+
+[advanced.blend_weighting]
+gaussian_std_dev = 2
+triangle_reverse = false
+bound = [0, 2]
+
+[advanced.interpolation]
+program = "svp" # svp/rife/rife-ncnn
+speed = "default" # medium/fast/faster/default (default follows interpolation.quality)
+tuning = "default" # film/animation/weak/smooth/default (default is smooth)
+algorithm = "default" # 2/13/23/default (default follows interpolation.quality)
+mask = 0.0 # keep static regions like a HUD from smearing, higher values treat more as static (0 is off)
+rife_model = "" # model used with rife ("4.6") or rife-ncnn (index, "9"), empty uses the plugin default
+rife_gpu_index = -1 # gpu rife runs on, e.g. a different one from encoding (-1 uses the plugin default)
+
+# Presets are named sets of values applied over this file with --preset <name>, teres also
+# ships builtin:classic, builtin:vegas and builtin:fragmovie. For example
+# [presets.smooth-120.blending]
+# output_fps = 120"#;
+
+/// Version of the config file format, bumped with a migration in `migrate` whenever keys are
+/// renamed or removed
+pub const CONFIG_VERSION: i64 = 2;
+
+/// Where each configuration value came from, keyed by its dotted path e.g. `blending.amount`
+pub type Provenance = BTreeMap<String, String>;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
+    #[serde(default)]
+    pub version: i64,
+
     pub blending: Blending,
     pub interpolation: Interpolation,
     pub encoding: Encoding,
@@ -12,6 +200,42 @@ pub struct Config {
 
     pub filters: Filters,
 
+    #[serde(default)]
+    pub mask: Mask,
+
+    #[serde(default)]
+    pub transform: Transform,
+
+    #[serde(default)]
+    pub output: Output,
+
+    #[serde(default)]
+    pub color: Color,
+
+    #[serde(default)]
+    pub overlay: Overlay,
+
+    #[serde(default)]
+    pub vfr: Vfr,
+
+    #[serde(default)]
+    pub audio: Audio,
+
+    #[serde(default)]
+    pub notifications: Notifications,
+
+    #[serde(default)]
+    pub upload: Upload,
+
+    #[serde(default)]
+    pub schedule: Schedule,
+
+    #[serde(default)]
+    pub monitoring: Monitoring,
+
+    #[serde(default)]
+    pub debug: DebugOptions,
+
     pub advanced: Advanced,
 }
 
@@ -19,21 +243,123 @@ pub struct Config {
 pub struct Blending {
     pub enabled: bool,
     pub amount: f32,
+    #[serde(default)]
+    pub shutter_angle: f32,
+    #[serde(default)]
+    pub adaptive: bool,
     pub weighting: String,
     pub output_fps: i32,
 }
 
+impl Blending {
+    /// The blur amount, from `shutter_angle` when it is set. A 360° shutter stays open for the
+    /// whole frame, which is an amount of 1
+    pub fn effective_amount(&self) -> f32 {
+        if self.shutter_angle > 0.0 {
+            self.shutter_angle / 360.0
+        } else {
+            self.amount
+        }
+    }
+
+    /// The weights of a custom `weighting` list like `[1, 2, 1]`, none when it isn't one
+    pub fn custom_weights(&self) -> Option<Vec<f64>> {
+        custom_weights(&self.weighting)
+    }
+}
+
+fn custom_weights(weighting: &str) -> Option<Vec<f64>> {
+    let list = weighting.trim().strip_prefix('[')?.strip_suffix(']')?;
+    list.split(',')
+        .map(|weight| weight.trim().parse::<f64>().ok())
+        .collect()
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Interpolation {
     pub enabled: bool,
     pub fps: f32,
+    #[serde(default = "default_interpolation_quality")]
+    pub quality: String,
+}
+
+fn default_interpolation_quality() -> String {
+    String::from("balanced")
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Encoding {
     pub quality: i32,
     pub detailed_filename: bool,
-    pub container: String
+    pub container: String,
+    #[serde(default = "default_filename_template")]
+    pub filename_template: String,
+    #[serde(default)]
+    pub animated_fps: u32,
+    #[serde(default)]
+    pub animated_width: u32,
+    #[serde(default = "default_codec")]
+    pub codec: String,
+    #[serde(default = "default_preset")]
+    pub preset: String,
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+    #[serde(default = "default_pixel_format")]
+    pub pixel_format: String,
+    #[serde(default)]
+    pub preserve_alpha: bool,
+    #[serde(default)]
+    pub max_bitrate_kbps: u32,
+    #[serde(default)]
+    pub target_size_mb: f32,
+}
+
+fn default_codec() -> String {
+    String::from("h264")
+}
+
+fn default_preset() -> String {
+    String::from("default")
+}
+
+fn default_pixel_format() -> String {
+    String::from("yuv420p")
+}
+
+impl Encoding {
+    /// Whether the video is encoded as vp9, for `codec = "vp9"` or webm outputs which hold
+    /// nothing else
+    pub fn vp9(&self) -> bool {
+        self.codec.eq_ignore_ascii_case("vp9")
+            || Container::named(&self.container) == Container::Webm
+    }
+
+    /// Chroma subsampling of `pixel_format`, as fmtc names it
+    pub fn chroma(&self) -> &'static str {
+        if self.pixel_format.eq_ignore_ascii_case("yuv444p") {
+            "444"
+        } else {
+            "420"
+        }
+    }
+
+    /// Whether `pixel_format` is 10-bit
+    pub fn ten_bit(&self) -> bool {
+        self.pixel_format.eq_ignore_ascii_case("yuv420p10le")
+    }
+
+    /// The configured encoder preset, or the encoder's `default` when it isn't set
+    pub fn preset_or(&self, default: &str) -> String {
+        if self.preset.eq_ignore_ascii_case("default") {
+            String::from(default)
+        } else {
+            self.preset.to_lowercase()
+        }
+    }
+}
+
+fn default_filename_template() -> String {
+    String::from("{name}_blur")
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -41,6 +367,49 @@ pub struct Timescale {
     pub input: f32,
     pub output: f32,
     pub adjust_audio_pitch: bool,
+    /// `[seconds, speed]` keyframes, the speed changes linearly between them
+    #[serde(default)]
+    pub curve: Vec<[f32; 2]>,
+}
+
+/// Length in seconds of the constant speed steps a speed ramp is split into
+const RAMP_STEP: f32 = 0.25;
+
+impl Timescale {
+    /// Splits the curve into `(start, end, speed)` segments of constant speed, the last one
+    /// without an end
+    pub fn segments(&self) -> Vec<(f32, Option<f32>, f32)> {
+        let (first, last) = match (self.curve.first(), self.curve.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return vec![],
+        };
+
+        let mut segments = vec![];
+        if first[0] > 0.0 {
+            segments.push((0.0, Some(first[0]), first[1]));
+        }
+        for pair in self.curve.windows(2) {
+            let ([start, from], [end, to]) = (pair[0], pair[1]);
+            if from == to {
+                segments.push((start, Some(end), from));
+                continue;
+            }
+            // ramps are approximated by short steps at the speed in their middle
+            let steps = ((end - start) / RAMP_STEP).ceil().max(1.0) as usize;
+            let step = (end - start) / steps as f32;
+            for i in 0..steps {
+                let step_start = start + step * i as f32;
+                let progress = (i as f32 + 0.5) / steps as f32;
+                segments.push((
+                    step_start,
+                    Some(step_start + step),
+                    from + (to - from) * progress,
+                ));
+            }
+        }
+        segments.push((last[0], None, last[1]));
+        segments
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -50,21 +419,456 @@ pub struct Filters {
     pub saturation: f32,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Mask {
+    pub image: String,
+    pub rect: Vec<u32>,
+    pub invert: bool,
+}
+
+impl Mask {
+    pub fn is_enabled(&self) -> bool {
+        !self.image.is_empty() || !self.rect.is_empty()
+    }
+
+    /// Checks that the rect lies inside frames of `width` by `height`
+    pub fn check_rect(&self, width: u32, height: u32) -> Result<(), String> {
+        if let [x, y, rect_width, rect_height] = self.rect[..] {
+            if x.saturating_add(rect_width) > width || y.saturating_add(rect_height) > height {
+                return Err(format!(
+                    "mask.rect {:?} goes outside the {}x{} frame",
+                    self.rect, width, height
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Transform {
+    pub reverse: bool,
+    pub pingpong: bool,
+    #[serde(default)]
+    pub crop: Vec<u32>,
+    #[serde(default)]
+    pub scale: String,
+    #[serde(default)]
+    pub resizer: String,
+    #[serde(default)]
+    pub rotate: u32,
+    #[serde(default)]
+    pub flip: String,
+}
+
+impl Transform {
+    /// Target `(width, height)` parsed from `WxH`
+    pub fn scale_size(&self) -> Option<(u32, u32)> {
+        let (width, height) = self
+            .scale
+            .to_lowercase()
+            .split_once('x')
+            .map(|(width, height)| (width.trim().parse().ok(), height.trim().parse().ok()))?;
+        Some((width?, height?))
+    }
+
+    /// Checks that the crop lies inside a video of `width` by `height`
+    pub fn check_crop(&self, width: u32, height: u32) -> Result<(), String> {
+        if let [x, y, crop_width, crop_height] = self.crop[..] {
+            if x.saturating_add(crop_width) > width || y.saturating_add(crop_height) > height {
+                return Err(format!(
+                    "transform.crop {:?} goes outside the {}x{} video",
+                    self.crop, width, height
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Size of the frames after the crop, rotation and scale, which the mask is placed on
+    pub fn frame_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (width, height) = match self.crop[..] {
+            [_, _, width, height] => (width, height),
+            _ => (width, height),
+        };
+        let (width, height) = if self.rotate == 90 || self.rotate == 270 {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        self.scale_size().unwrap_or((width, height))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Output {
+    pub resolution: String,
+    pub pad: String,
+    #[serde(default)]
+    pub intro: String,
+    #[serde(default)]
+    pub outro: String,
+    #[serde(default)]
+    pub thumbnail: String,
+    #[serde(default = "default_thumbnail_format")]
+    pub thumbnail_format: String,
+    #[serde(default)]
+    pub verify: bool,
+    #[serde(default)]
+    pub metrics: Vec<String>,
+    #[serde(default = "default_embed_settings")]
+    pub embed_settings: bool,
+    #[serde(default = "default_sidecar")]
+    pub sidecar: String,
+}
+
+fn default_thumbnail_format() -> String {
+    String::from("jpg")
+}
+
+fn default_embed_settings() -> bool {
+    true
+}
+
+fn default_sidecar() -> String {
+    String::from("toml")
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output {
+            resolution: String::new(),
+            pad: String::from("letterbox"),
+            intro: String::new(),
+            outro: String::new(),
+            thumbnail: String::new(),
+            thumbnail_format: default_thumbnail_format(),
+            verify: false,
+            metrics: vec![],
+            embed_settings: default_embed_settings(),
+            sidecar: default_sidecar(),
+        }
+    }
+}
+
+impl Output {
+    /// Target `(width, height)` from `WxH` or a name like `1080p`, with `-vertical` swapping
+    /// the sides
+    pub fn size(&self) -> Option<(u32, u32)> {
+        let resolution = self.resolution.to_lowercase();
+        let (name, vertical) = match resolution.strip_suffix("-vertical") {
+            Some(name) => (name, true),
+            None => (resolution.as_str(), false),
+        };
+        let (width, height) = match name {
+            "720p" => (1280, 720),
+            "1080p" => (1920, 1080),
+            "1440p" => (2560, 1440),
+            "2160p" | "4k" => (3840, 2160),
+            _ => {
+                let (width, height) = name.split_once('x')?;
+                (width.trim().parse().ok()?, height.trim().parse().ok()?)
+            }
+        };
+        Some(if vertical {
+            (height, width)
+        } else {
+            (width, height)
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Color {
+    pub lut_path: String,
+    pub saturation: f32,
+    pub contrast: f32,
+    pub gamma: f32,
+}
+
+impl Default for Color {
+    fn default() -> Self {
+        Color {
+            lut_path: String::new(),
+            saturation: 1.0,
+            contrast: 1.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Overlay {
+    pub image_path: String,
+    pub position: String,
+    pub opacity: f32,
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Overlay {
+            image_path: String::new(),
+            position: String::from("bottom-right"),
+            opacity: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Vfr {
+    pub mode: String,
+    pub fps: f32,
+    #[serde(default = "default_sequence_fps")]
+    pub sequence_fps: f32,
+}
+
+impl Default for Vfr {
+    fn default() -> Self {
+        Vfr {
+            mode: String::from("auto"),
+            fps: 0.0,
+            sequence_fps: default_sequence_fps(),
+        }
+    }
+}
+
+fn default_sequence_fps() -> f32 {
+    60.0
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Audio {
+    #[serde(default = "default_audio_enabled")]
+    pub enabled: bool,
+    pub keep_original: bool,
+    #[serde(default)]
+    pub normalize: bool,
+    #[serde(default)]
+    pub music_path: String,
+    #[serde(default)]
+    pub music_volume: f32,
+}
+
+fn default_audio_enabled() -> bool {
+    true
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Audio {
+            enabled: true,
+            keep_original: false,
+            normalize: false,
+            music_path: String::new(),
+            music_volume: 0.3,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Notifications {
+    pub desktop: bool,
+    pub webhook_url: Option<String>,
+    #[serde(default)]
+    pub copy_path: bool,
+    #[serde(default)]
+    pub open_folder: bool,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Notifications {
+            desktop: true,
+            webhook_url: None,
+            copy_path: false,
+            open_folder: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Upload {
+    pub provider: String,
+    pub public_url: String,
+    pub s3_bucket: String,
+    pub s3_prefix: String,
+    pub s3_endpoint: String,
+    pub sftp_destination: String,
+    pub youtube_client_id: String,
+    pub youtube_privacy: String,
+}
+
+impl Default for Upload {
+    fn default() -> Self {
+        Upload {
+            provider: String::new(),
+            public_url: String::new(),
+            s3_bucket: String::new(),
+            s3_prefix: String::new(),
+            s3_endpoint: String::new(),
+            sftp_destination: String::new(),
+            youtube_client_id: String::new(),
+            youtube_privacy: String::from("unlisted"),
+        }
+    }
+}
+
+/// Secrets of the youtube client, kept out of the config so they are never written out with the
+/// rest of the settings
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Credentials {
+    pub youtube_client_secret: String,
+    pub youtube_refresh_token: String,
+}
+
+/// Keys of the credentials, which are dropped when found in the config
+const CREDENTIAL_KEYS: [&str; 2] = ["youtube_client_secret", "youtube_refresh_token"];
+
+impl Credentials {
+    pub fn path() -> PathBuf {
+        Config::global_path().with_file_name("credentials.toml")
+    }
+
+    /// Reads `TERES_YOUTUBE_CLIENT_SECRET` and `TERES_YOUTUBE_REFRESH_TOKEN`, falling back to the
+    /// credentials file
+    pub fn load() -> Credentials {
+        let path = Credentials::path();
+        let file = match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).unwrap_or_else(|error| {
+                warn!("Could not read {}: {}", path.display(), error);
+                Credentials::default()
+            }),
+            Err(_) => Credentials::default(),
+        };
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        Credentials {
+            youtube_client_secret: var("TERES_YOUTUBE_CLIENT_SECRET")
+                .unwrap_or(file.youtube_client_secret),
+            youtube_refresh_token: var("TERES_YOUTUBE_REFRESH_TOKEN")
+                .unwrap_or(file.youtube_refresh_token),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Schedule {
+    #[serde(default)]
+    pub start_time: String,
+    #[serde(default)]
+    pub end_time: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Monitoring {
+    pub enabled: bool,
+    #[serde(default)]
+    pub max_temperature: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DebugOptions {
+    pub overlay_frame_numbers: bool,
+    pub compare: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Advanced {
+    #[serde(default)]
+    pub threads: u32,
+    #[serde(default = "default_process_priority")]
+    pub process_priority: String,
+    #[serde(default)]
+    pub cpu_affinity: Vec<usize>,
+    #[serde(default)]
+    pub chunks: u32,
+    #[serde(default = "default_disk_check")]
+    pub disk_check: String,
+    #[serde(default)]
+    pub ffmpeg_path: String,
+    #[serde(default)]
+    pub ffprobe_path: String,
+    #[serde(default)]
+    pub vspipe_path: String,
+    #[serde(default)]
+    pub vspipe_args: Vec<String>,
+    #[serde(default = "default_pause_on_exit")]
+    pub pause_on_exit: String,
+    #[serde(default)]
+    pub keep_index: bool,
+    #[serde(default)]
+    pub index_cache: String,
+    #[serde(default)]
+    pub deterministic: bool,
+    #[serde(default)]
+    pub api_folders: Vec<String>,
     pub encoding: AdvancedEncoding,
     pub blend_weighting: AdvancedBlending,
     pub interpolation: AdvancedInterpolation,
+    #[serde(default)]
+    pub performance: AdvancedPerformance,
+}
+
+/// Threads of deterministic renders, the same on every machine so a render farm matches
+const DETERMINISTIC_THREADS: u32 = 4;
+
+fn default_process_priority() -> String {
+    String::from("normal")
+}
+
+fn default_vaapi_device() -> String {
+    String::from("/dev/dri/renderD128")
+}
+
+fn default_disk_check() -> String {
+    String::from("refuse")
+}
+
+fn default_pause_on_exit() -> String {
+    String::from("auto")
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AdvancedEncoding {
     pub gpu: bool,
     pub gpu_type: String,
+    #[serde(default = "default_vaapi_device")]
+    pub vaapi_device: String,
     pub deduplicate: bool,
     pub custom_ffmpeg_filters: Option<String>,
 }
 
+impl Advanced {
+    /// Folder indexes are kept in with keep_index
+    pub fn index_cache_dir(&self) -> Option<PathBuf> {
+        if self.index_cache.is_empty() {
+            dirs::cache_dir().map(|dir| dir.join("teres").join("index"))
+        } else {
+            Some(PathBuf::from(&self.index_cache))
+        }
+    }
+}
+
+impl AdvancedEncoding {
+    /// `custom_ffmpeg_filters` when it is a filter chain added to the video filters
+    pub fn custom_filters(&self) -> Option<&str> {
+        self.custom_ffmpeg_filters
+            .as_deref()
+            .map(str::trim)
+            .filter(|custom| !custom.is_empty() && !custom.starts_with('-'))
+    }
+
+    /// `custom_ffmpeg_filters` split into arguments when it is output options, which start with
+    /// a `-`, replacing the encoder arguments teres picks
+    pub fn custom_args(&self) -> Option<Vec<String>> {
+        self.custom_ffmpeg_filters
+            .as_deref()
+            .map(str::trim)
+            .filter(|custom| custom.starts_with('-'))
+            .and_then(helpers::split_args)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AdvancedBlending {
     pub gaussian_std_dev: i32,
@@ -78,71 +882,1426 @@ pub struct AdvancedInterpolation {
     pub speed: String,
     pub tuning: String,
     pub algorithm: String,
+    #[serde(default)]
+    pub mask: f32,
+    #[serde(default)]
+    pub rife_model: String,
+    #[serde(default = "default_rife_gpu_index")]
+    pub rife_gpu_index: i32,
+}
+
+fn default_rife_gpu_index() -> i32 {
+    -1
+}
+
+/// How the interpolation and the encoder share the cpu and the gpus
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdvancedPerformance {
+    pub vs_threads: u32,
+    pub interpolation_gpu: String,
+    pub encoder_gpu_index: i32,
+    pub max_cache_mb: u32,
+}
+
+impl Default for AdvancedPerformance {
+    fn default() -> Self {
+        AdvancedPerformance {
+            vs_threads: 0,
+            interpolation_gpu: String::from("auto"),
+            encoder_gpu_index: -1,
+            max_cache_mb: 0,
+        }
+    }
+}
+
+impl AdvancedPerformance {
+    /// Threads of the vapoursynth core, 0 leaving it to vapoursynth
+    pub fn vs_threads(&self, advanced_threads: u32) -> u32 {
+        if self.vs_threads > 0 {
+            self.vs_threads
+        } else {
+            advanced_threads
+        }
+    }
+
+    /// Whether svp interpolates on the gpu
+    pub fn interpolation_gpu(&self, encoding_gpu: bool) -> bool {
+        match self.interpolation_gpu.to_lowercase().as_str() {
+            "on" => true,
+            "off" => false,
+            _ => encoding_gpu,
+        }
+    }
 }
 
 impl Config {
-    pub fn parse() -> Config {
-        let filepath = home_dir().unwrap();
-        let config_file = filepath.join(".config/teres/teres.toml");
-        if !config_file.exists() {
-            Config::create(&config_file);
+    pub fn global_path() -> PathBuf {
+        home_dir().unwrap().join(".config/teres/teres.toml")
+    }
+
+    /// Resolves the configuration by merging, in increasing priority, the defaults, the global
+    /// config file, a `teres.toml` in the current directory, `TERES_SECTION__KEY` environment
+    /// variables, the selected preset and `key=value` overrides from the command line
+    pub fn load(overrides: &[String], preset: Option<&str>) -> (Config, Provenance) {
+        match Config::try_load(overrides, preset) {
+            (Ok(config), provenance) => (config, provenance),
+            (Err((key, message)), provenance) => {
+                unreadable(&key, &message, &provenance);
+                unreachable!()
+            }
+        }
+    }
+
+    /// Like `load`, but returns the key and problem of a value with the wrong type instead of
+    /// exiting
+    pub fn try_load(
+        overrides: &[String],
+        preset: Option<&str>,
+    ) -> (Result<Config, (String, String)>, Provenance) {
+        let (merged, provenance) = Config::resolve(overrides, preset);
+        let config = Config::read(merged);
+        if let Ok(config) = &config {
+            binaries::configure(config);
+            helpers::set_pause_on_exit(&config.advanced.pause_on_exit);
+        }
+        (config, provenance)
+    }
+
+    /// Reads merged values into a config, failing with the dotted key and the problem of the
+    /// first value that has the wrong type
+    pub fn read(merged: Value) -> Result<Config, (String, String)> {
+        merged.try_into().map_err(|error: toml::de::Error| {
+            let message = error.to_string();
+            match message.rsplit_once(" for key `") {
+                Some((message, key)) => {
+                    (key.trim_end_matches('`').to_string(), message.to_string())
+                }
+                None => (String::from("config"), message),
+            }
+        })
+    }
+
+    /// The defaults with a builtin preset on top, without the config files or environment so
+    /// they are the same on every machine
+    pub fn builtin(preset: Option<&str>) -> Result<Config, String> {
+        let mut merged: Value =
+            toml::from_str(DEFAULT_CONFIG).map_err(|error| error.to_string())?;
+        if let Some(layer) = preset.and_then(presets::builtin) {
+            merge(&mut merged, layer, "preset", "", &mut Provenance::new());
+        }
+        merged.try_into().map_err(|error| error.to_string())
+    }
+
+    pub fn resolve(overrides: &[String], preset: Option<&str>) -> (Value, Provenance) {
+        let mut merged = Value::Table(Default::default());
+        let mut provenance = Provenance::new();
+
+        let defaults: Value = toml::from_str(DEFAULT_CONFIG).unwrap();
+        merge(&mut merged, defaults, "default", "", &mut provenance);
+
+        let global_file = Config::global_path();
+        if !global_file.exists() {
+            Config::create(&global_file);
+        }
+        let local_file = std::env::current_dir()
+            .map(|dir| dir.join("teres.toml"))
+            .ok()
+            .filter(|file| file.exists() && *file != global_file);
+        for file in std::iter::once(global_file).chain(local_file) {
+            let layer = Config::read_file(&file);
+            merge(
+                &mut merged,
+                layer,
+                &file.display().to_string(),
+                "",
+                &mut provenance,
+            );
+        }
+
+        let mut env_vars: Vec<(String, String)> = std::env::vars()
+            .filter(|(key, _)| key.starts_with("TERES_") && key.contains("__"))
+            .collect();
+        env_vars.sort();
+        for (key, value) in env_vars {
+            let path = key["TERES_".len()..].to_lowercase().replace("__", ".");
+            merge(
+                &mut merged,
+                nested_value(&path, &value),
+                &format!("env {}", key),
+                "",
+                &mut provenance,
+            );
+        }
+
+        let presets = match &mut merged {
+            Value::Table(table) => table.remove("presets"),
+            _ => None,
+        };
+        if let Some(name) = preset {
+            let layer = match name.strip_prefix("builtin:") {
+                Some(builtin) => presets::builtin(builtin),
+                None => presets
+                    .as_ref()
+                    .and_then(|presets| presets.get(name))
+                    .cloned(),
+            };
+            match layer {
+                Some(layer) => merge(
+                    &mut merged,
+                    layer,
+                    &format!("preset {}", name),
+                    "",
+                    &mut provenance,
+                ),
+                None => {
+                    let available: Vec<String> = presets
+                        .as_ref()
+                        .and_then(|presets| presets.as_table())
+                        .map(|presets| presets.keys().cloned().collect::<Vec<_>>())
+                        .unwrap_or_default()
+                        .into_iter()
+                        .chain(
+                            presets::BUILTIN
+                                .iter()
+                                .map(|(builtin, _)| format!("builtin:{}", builtin)),
+                        )
+                        .collect();
+                    error!(
+                        "Unknown preset {}, available presets: {}",
+                        name,
+                        available.join(", ")
+                    );
+                    crate::helpers::exit(exitcode::CONFIG);
+                }
+            }
+        }
+
+        for item in overrides {
+            match item.split_once('=') {
+                Some((path, value)) => merge(
+                    &mut merged,
+                    nested_value(path.trim(), value.trim()),
+                    "command line",
+                    "",
+                    &mut provenance,
+                ),
+                None => warn!("Ignoring override {}, expected key=value", item),
+            }
+        }
+
+        // secrets set like any other value would be printed and written out with the settings
+        if let Some(Value::Table(upload)) = merged.get_mut("upload") {
+            for key in CREDENTIAL_KEYS {
+                if upload.remove(key).is_some() {
+                    provenance.remove(&format!("upload.{}", key));
+                    static WARNED: std::sync::Once = std::sync::Once::new();
+                    WARNED.call_once(|| {
+                        warn!(
+                            "Ignoring upload.{} in the config, move it to {} or TERES_{}",
+                            key,
+                            Credentials::path().display(),
+                            key.to_uppercase()
+                        )
+                    });
+                }
+            }
+        }
+
+        (merged, provenance)
+    }
+
+    fn read_file(file: &Path) -> Value {
+        let contents = fs::read_to_string(file).expect("Could not parse config file to string");
+        let value: Value = toml::from_str(&contents).expect("Could not read values.");
+
+        // files from before versioning was added are version 1
+        let version = value
+            .get("version")
+            .and_then(|version| version.as_integer())
+            .unwrap_or(1);
+        if version >= CONFIG_VERSION {
+            return value;
+        }
+
+        let (migrated, moved) = migrate(value, version);
+        let backup = file.with_extension(format!("toml.v{}.bak", version));
+        let result = fs::copy(file, &backup).and_then(|_| {
+            // only rewrite the whole file, losing its comments, when keys had to move
+            let contents = if moved {
+                toml::to_string(&migrated)
+                    .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?
+            } else {
+                with_version(&contents)
+            };
+            fs::write(file, contents)
+        });
+        match result {
+            Ok(()) => warn!(
+                "Upgraded config file {} from version {} to {}, the old file was backed up to {}",
+                file.display(),
+                version,
+                CONFIG_VERSION,
+                backup.display()
+            ),
+            Err(error) => warn!(
+                "Could not upgrade config file {}: {}",
+                file.display(),
+                error
+            ),
+        }
+        migrated
+    }
+
+    /// Formats the resolved configuration as toml with where each value came from
+    pub fn annotated(overrides: &[String], preset: Option<&str>) -> String {
+        let (merged, provenance) = Config::resolve(overrides, preset);
+        if let Err((key, message)) = Config::read(merged.clone()) {
+            unreadable(&key, &message, &provenance);
+        }
+        let mut output = String::new();
+        if let Value::Table(table) = merged {
+            write_table(&mut output, &table, "", &provenance);
+        }
+        output
+    }
+
+    /// Checks for values that would make a render fail or do nothing, returning the problems
+    /// as `(key, message)`
+    pub fn validate(&self) -> Vec<(&'static str, String)> {
+        let mut errors = vec![];
+        let mut check = |ok: bool, key: &'static str, message: String| {
+            if !ok {
+                errors.push((key, message));
+            }
+        };
+        let one_of =
+            |value: &str, options: &[&str]| options.contains(&value.to_lowercase().as_str());
+
+        check(
+            self.blending.output_fps > 0,
+            "blending.output_fps",
+            format!("must be above 0, got {}", self.blending.output_fps),
+        );
+        check(
+            self.blending.amount >= 0.0,
+            "blending.amount",
+            format!("can't be negative, got {}", self.blending.amount),
+        );
+        check(
+            !self.blending.weighting.trim().starts_with('[')
+                || self.blending.custom_weights().is_some(),
+            "blending.weighting",
+            format!(
+                "must be a list of numbers like [1, 2, 1], got {}",
+                self.blending.weighting
+            ),
+        );
+        check(
+            self.blending.shutter_angle >= 0.0,
+            "blending.shutter_angle",
+            format!("can't be negative, got {}", self.blending.shutter_angle),
+        );
+        check(
+            self.interpolation.fps > 0.0,
+            "interpolation.fps",
+            format!("must be above 0, got {}", self.interpolation.fps),
+        );
+        check(
+            !(self.blending.enabled
+                && self.interpolation.enabled
+                && self.blending.output_fps as f32 > self.interpolation.fps),
+            "blending.output_fps",
+            format!(
+                "({}) can't be higher than interpolation.fps ({}) as there would be no frames to blend",
+                self.blending.output_fps, self.interpolation.fps
+            ),
+        );
+
+        // intel's global_quality starts at 1, the others take 0 as lossless
+        let min_quality = if self.advanced.encoding.gpu
+            && self.advanced.encoding.gpu_type.to_lowercase() == "intel"
+        {
+            1
+        } else {
+            0
+        };
+        check(
+            (min_quality..=51).contains(&self.encoding.quality),
+            "encoding.quality",
+            format!(
+                "must be from {} to 51, got {}",
+                min_quality, self.encoding.quality
+            ),
+        );
+        check(
+            !one_of(&self.encoding.container, &["gif", "webp"]) || self.encoding.animated_fps > 0,
+            "encoding.animated_fps",
+            String::from("must be above 0 for gif and webp outputs"),
+        );
+        check(
+            !self.encoding.preserve_alpha
+                || matches!(
+                    Container::named(&self.encoding.container),
+                    Container::Mov | Container::Webm | Container::Mkv | Container::PngSequence
+                ),
+            "encoding.preserve_alpha",
+            format!(
+                "needs a mov, webm, mkv or png_sequence container, got {}",
+                self.encoding.container
+            ),
+        );
+        check(
+            self.encoding.target_size_mb >= 0.0,
+            "encoding.target_size_mb",
+            format!("must be 0 or above, got {}", self.encoding.target_size_mb),
+        );
+        check(
+            self.encoding.target_size_mb == 0.0
+                || !(self.encoding.preserve_alpha
+                    || one_of(&self.encoding.container, &["gif", "webp", "png_sequence"])),
+            "encoding.target_size_mb",
+            String::from("can't be used with gif, webp or png_sequence outputs or preserve_alpha"),
+        );
+        check(
+            one_of(&self.encoding.codec, &["h264", "hevc", "vp9"]),
+            "encoding.codec",
+            format!("must be h264, hevc or vp9, got {}", self.encoding.codec),
+        );
+        check(
+            one_of(
+                &self.advanced.encoding.gpu_type,
+                &["nvidia", "amd", "intel", "apple", "vaapi"],
+            ),
+            "advanced.encoding.gpu_type",
+            format!(
+                "must be nvidia, amd, intel, apple or vaapi, got {}",
+                self.advanced.encoding.gpu_type
+            ),
+        );
+        // each encoder names its presets differently, prores and vp9 for alpha ignore them
+        let gpu_type = self.advanced.encoding.gpu_type.to_lowercase();
+        let (encoder, presets): (&str, &[&str]) = if self.encoding.preserve_alpha {
+            ("", &[])
+        } else if self.advanced.encoding.gpu && !self.encoding.vp9() {
+            match gpu_type.as_str() {
+                "nvidia" => ("nvenc", &["p1", "p2", "p3", "p4", "p5", "p6", "p7"]),
+                "intel" => (
+                    "qsv",
+                    &[
+                        "veryfast", "faster", "fast", "medium", "slow", "slower", "veryslow",
+                    ],
+                ),
+                "amd" => ("amf", &["speed", "balanced", "quality"]),
+                gpu_type => (gpu_type, &[]),
+            }
+        } else {
+            (
+                "software encoding",
+                &[
+                    "ultrafast",
+                    "superfast",
+                    "veryfast",
+                    "faster",
+                    "fast",
+                    "medium",
+                    "slow",
+                    "slower",
+                    "veryslow",
+                    "placebo",
+                ],
+            )
+        };
+        check(
+            self.encoding.preset.eq_ignore_ascii_case("default")
+                || encoder.is_empty()
+                || one_of(&self.encoding.preset, presets),
+            "encoding.preset",
+            if presets.is_empty() {
+                format!("{} has no presets, set it to default", encoder)
+            } else {
+                format!(
+                    "must be {} or default with {}, got {}",
+                    presets.join(", "),
+                    encoder,
+                    self.encoding.preset
+                )
+            },
+        );
+        check(
+            one_of(
+                &self.encoding.pixel_format,
+                &["yuv420p", "yuv420p10le", "yuv444p"],
+            ),
+            "encoding.pixel_format",
+            format!(
+                "must be yuv420p, yuv420p10le or yuv444p, got {}",
+                self.encoding.pixel_format
+            ),
+        );
+        // only nvenc of the gpu encoders is given other formats, and it has no 10-bit h264
+        let gpu_formats: &[&str] = match gpu_type.as_str() {
+            "nvidia" if self.encoding.codec.eq_ignore_ascii_case("hevc") => {
+                &["yuv420p", "yuv420p10le", "yuv444p"]
+            }
+            "nvidia" => &["yuv420p", "yuv444p"],
+            _ => &["yuv420p"],
+        };
+        check(
+            !self.advanced.encoding.gpu
+                || self.encoding.vp9()
+                || self.encoding.preserve_alpha
+                || one_of(&self.encoding.pixel_format, gpu_formats),
+            "encoding.pixel_format",
+            format!(
+                "must be {} with {} gpu encoding and the {} codec, got {}",
+                gpu_formats.join(" or "),
+                gpu_type,
+                self.encoding.codec,
+                self.encoding.pixel_format
+            ),
+        );
+        check(
+            !matches!(
+                self.advanced.encoding.custom_ffmpeg_filters.as_deref().map(str::trim),
+                Some(custom) if custom.starts_with('-') && helpers::split_args(custom).is_none()
+            ),
+            "advanced.encoding.custom_ffmpeg_filters",
+            String::from("has a quote that isn't closed"),
+        );
+
+        check(
+            self.timescale.input > 0.0,
+            "timescale.input",
+            format!("must be above 0, got {}", self.timescale.input),
+        );
+        check(
+            self.timescale.output > 0.0,
+            "timescale.output",
+            format!("must be above 0, got {}", self.timescale.output),
+        );
+
+        check(
+            self.timescale
+                .curve
+                .iter()
+                .all(|[seconds, speed]| *seconds >= 0.0 && *speed > 0.0),
+            "timescale.curve",
+            String::from("keyframes must be [seconds, speed] with a speed above 0"),
+        );
+        check(
+            self.timescale
+                .curve
+                .windows(2)
+                .all(|pair| pair[0][0] < pair[1][0]),
+            "timescale.curve",
+            String::from("keyframes must be in order of time"),
+        );
+
+        check(
+            one_of(
+                &self.interpolation.quality,
+                &["fast", "balanced", "quality"],
+            ),
+            "interpolation.quality",
+            format!(
+                "must be fast, balanced or quality, got {}",
+                self.interpolation.quality
+            ),
+        );
+
+        let interpolation = &self.advanced.interpolation;
+        check(
+            one_of(&interpolation.program, &["svp", "rife", "rife-ncnn"]),
+            "advanced.interpolation.program",
+            format!(
+                "must be svp, rife or rife-ncnn, got {}",
+                interpolation.program
+            ),
+        );
+        check(
+            one_of(
+                &interpolation.speed,
+                &["default", "medium", "fast", "faster"],
+            ),
+            "advanced.interpolation.speed",
+            format!(
+                "must be medium, fast, faster or default, got {}",
+                interpolation.speed
+            ),
+        );
+        check(
+            one_of(
+                &interpolation.tuning,
+                &["default", "film", "animation", "weak", "smooth"],
+            ),
+            "advanced.interpolation.tuning",
+            format!(
+                "must be film, animation, weak, smooth or default, got {}",
+                interpolation.tuning
+            ),
+        );
+        check(
+            one_of(&interpolation.algorithm, &["default", "2", "13", "23"]),
+            "advanced.interpolation.algorithm",
+            format!(
+                "must be 2, 13, 23 or default, got {}",
+                interpolation.algorithm
+            ),
+        );
+
+        check(
+            interpolation.mask >= 0.0,
+            "advanced.interpolation.mask",
+            format!("can't be negative, got {}", interpolation.mask),
+        );
+        check(
+            interpolation
+                .rife_model
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == '.'),
+            "advanced.interpolation.rife_model",
+            format!(
+                "must be a model version like 4.6 or an index like 9, got {}",
+                interpolation.rife_model
+            ),
+        );
+        check(
+            interpolation.program.to_lowercase() != "rife-ncnn"
+                || interpolation.rife_model.is_empty()
+                || interpolation.rife_model.parse::<u32>().is_ok(),
+            "advanced.interpolation.rife_model",
+            format!(
+                "must be the index of a model with rife-ncnn, got {}",
+                interpolation.rife_model
+            ),
+        );
+        let performance = &self.advanced.performance;
+        check(
+            one_of(&performance.interpolation_gpu, &["auto", "on", "off"]),
+            "advanced.performance.interpolation_gpu",
+            format!(
+                "must be auto, on or off, got {}",
+                performance.interpolation_gpu
+            ),
+        );
+        check(
+            performance.encoder_gpu_index >= -1,
+            "advanced.performance.encoder_gpu_index",
+            format!(
+                "must be -1 or the index of a gpu, got {}",
+                performance.encoder_gpu_index
+            ),
+        );
+        check(
+            interpolation.rife_gpu_index >= -1,
+            "advanced.interpolation.rife_gpu_index",
+            format!(
+                "must be -1 or the index of a gpu, got {}",
+                interpolation.rife_gpu_index
+            ),
+        );
+
+        check(
+            self.mask.rect.is_empty() || self.mask.rect.len() == 4,
+            "mask.rect",
+            format!(
+                "must be [x, y, width, height], got {} value(s)",
+                self.mask.rect.len()
+            ),
+        );
+        check(
+            self.mask.image.is_empty() || Path::new(&self.mask.image).is_file(),
+            "mask.image",
+            format!("{} does not exist", self.mask.image),
+        );
+        check(
+            self.mask.image.is_empty() || self.mask.rect.is_empty(),
+            "mask.rect",
+            String::from("can't be used together with mask.image"),
+        );
+
+        let transform = &self.transform;
+        check(
+            transform.crop.is_empty() || transform.crop.len() == 4,
+            "transform.crop",
+            format!(
+                "must be [x, y, width, height], got {} value(s)",
+                transform.crop.len()
+            ),
+        );
+        check(
+            transform.crop.iter().all(|value| value % 2 == 0),
+            "transform.crop",
+            String::from("values must be even for 4:2:0 video"),
+        );
+        check(
+            transform.scale.is_empty()
+                || matches!(transform.scale_size(), Some((width, height)) if width > 0 && height > 0 && width % 2 == 0 && height % 2 == 0),
+            "transform.scale",
+            format!(
+                "must be WxH with even sizes e.g. 1920x1080, got {}",
+                transform.scale
+            ),
+        );
+        check(
+            one_of(
+                &transform.resizer,
+                &["", "bilinear", "bicubic", "lanczos", "spline36", "point"],
+            ),
+            "transform.resizer",
+            format!(
+                "must be bilinear, bicubic, lanczos, spline36 or point, got {}",
+                transform.resizer
+            ),
+        );
+        check(
+            [0, 90, 180, 270].contains(&transform.rotate),
+            "transform.rotate",
+            format!("must be 0, 90, 180 or 270, got {}", transform.rotate),
+        );
+        check(
+            one_of(&transform.flip, &["", "horizontal", "vertical", "both"]),
+            "transform.flip",
+            format!(
+                "must be horizontal, vertical, both or empty, got {}",
+                transform.flip
+            ),
+        );
+
+        check(
+            self.output.resolution.is_empty()
+                || matches!(self.output.size(), Some((width, height)) if width > 0 && height > 0 && width % 2 == 0 && height % 2 == 0),
+            "output.resolution",
+            format!(
+                "must be WxH with even sizes or 720p, 1080p, 1440p or 4k, got {}",
+                self.output.resolution
+            ),
+        );
+        for (key, path) in [
+            ("output.intro", &self.output.intro),
+            ("output.outro", &self.output.outro),
+        ] {
+            check(
+                path.is_empty() || Path::new(path).is_file(),
+                key,
+                format!("{} does not exist", path),
+            );
+        }
+        check(
+            one_of(&self.output.pad, &["letterbox", "blur", "stretch"]),
+            "output.pad",
+            format!(
+                "must be letterbox, blur or stretch, got {}",
+                self.output.pad
+            ),
+        );
+        check(
+            self.output.thumbnail.is_empty()
+                || self.output.thumbnail.eq_ignore_ascii_case("middle")
+                || matches!(self.output.thumbnail.parse::<f64>(), Ok(seconds) if seconds >= 0.0),
+            "output.thumbnail",
+            format!(
+                "must be middle or a timestamp in seconds, got {}",
+                self.output.thumbnail
+            ),
+        );
+        check(
+            one_of(&self.output.thumbnail_format, &["jpg", "png"]),
+            "output.thumbnail_format",
+            format!("must be jpg or png, got {}", self.output.thumbnail_format),
+        );
+        check(
+            self.output
+                .metrics
+                .iter()
+                .all(|metric| one_of(metric, &["vmaf", "ssim", "psnr"])),
+            "output.metrics",
+            format!(
+                "must only hold vmaf, ssim and psnr, got {}",
+                self.output.metrics.join(", ")
+            ),
+        );
+        check(
+            one_of(&self.output.sidecar, &["toml", "json", "off"]),
+            "output.sidecar",
+            format!("must be toml, json or off, got {}", self.output.sidecar),
+        );
+
+        let color = &self.color;
+        check(
+            color.lut_path.is_empty() || Path::new(&color.lut_path).is_file(),
+            "color.lut_path",
+            format!("{} does not exist", color.lut_path),
+        );
+        check(
+            !color.lut_path.contains('\''),
+            "color.lut_path",
+            format!(
+                "can't contain a single quote, which ends the path in the ffmpeg filter, got {}",
+                color.lut_path
+            ),
+        );
+        check(
+            color.lut_path.is_empty() || color.lut_path.to_lowercase().ends_with(".cube"),
+            "color.lut_path",
+            format!("must be a .cube file, got {}", color.lut_path),
+        );
+        check(
+            (0.0..=3.0).contains(&color.saturation),
+            "color.saturation",
+            format!("must be between 0 and 3, got {}", color.saturation),
+        );
+        check(
+            (-2.0..=2.0).contains(&color.contrast),
+            "color.contrast",
+            format!("must be between -2 and 2, got {}", color.contrast),
+        );
+        check(
+            (0.1..=10.0).contains(&color.gamma),
+            "color.gamma",
+            format!("must be between 0.1 and 10, got {}", color.gamma),
+        );
+
+        let overlay = &self.overlay;
+        check(
+            overlay.image_path.is_empty() || Path::new(&overlay.image_path).is_file(),
+            "overlay.image_path",
+            format!("{} does not exist", overlay.image_path),
+        );
+        check(
+            one_of(
+                &overlay.position,
+                &[
+                    "top-left",
+                    "top-right",
+                    "bottom-left",
+                    "bottom-right",
+                    "center",
+                ],
+            ),
+            "overlay.position",
+            format!(
+                "must be top-left, top-right, bottom-left, bottom-right or center, got {}",
+                overlay.position
+            ),
+        );
+        check(
+            (0.0..=1.0).contains(&overlay.opacity),
+            "overlay.opacity",
+            format!("must be between 0 and 1, got {}", overlay.opacity),
+        );
+
+        check(
+            self.audio.music_path.is_empty() || Path::new(&self.audio.music_path).is_file(),
+            "audio.music_path",
+            format!("{} does not exist", self.audio.music_path),
+        );
+        check(
+            self.audio.music_volume >= 0.0,
+            "audio.music_volume",
+            format!("can't be negative, got {}", self.audio.music_volume),
+        );
+
+        let upload = &self.upload;
+        check(
+            one_of(&upload.provider, &["", "youtube", "s3", "sftp"]),
+            "upload.provider",
+            format!(
+                "must be youtube, s3, sftp or empty, got {}",
+                upload.provider
+            ),
+        );
+        let provider = upload.provider.to_lowercase();
+        check(
+            provider != "s3" || !upload.s3_bucket.is_empty(),
+            "upload.s3_bucket",
+            String::from("is needed to upload to s3"),
+        );
+        check(
+            provider != "sftp" || upload.sftp_destination.contains(':'),
+            "upload.sftp_destination",
+            format!(
+                "must be user@host:folder to upload with sftp, got {}",
+                upload.sftp_destination
+            ),
+        );
+        let credentials = if provider == "youtube" {
+            Credentials::load()
+        } else {
+            Credentials::default()
+        };
+        check(
+            provider != "youtube"
+                || !(upload.youtube_client_id.is_empty()
+                    || credentials.youtube_client_secret.is_empty()
+                    || credentials.youtube_refresh_token.is_empty()),
+            "upload.youtube_client_id",
+            format!(
+                "youtube uploads need youtube_client_id, and youtube_client_secret and youtube_refresh_token in {} or TERES_YOUTUBE_CLIENT_SECRET and TERES_YOUTUBE_REFRESH_TOKEN",
+                Credentials::path().display()
+            ),
+        );
+        check(
+            one_of(&upload.youtube_privacy, &["public", "unlisted", "private"]),
+            "upload.youtube_privacy",
+            format!(
+                "must be public, unlisted or private, got {}",
+                upload.youtube_privacy
+            ),
+        );
+
+        check(
+            one_of(&self.debug.compare, &["", "split", "stacked"]),
+            "debug.compare",
+            format!(
+                "must be split, stacked or empty, got {}",
+                self.debug.compare
+            ),
+        );
+
+        check(
+            one_of(&self.vfr.mode, &["auto", "cfr", "off"]),
+            "vfr.mode",
+            format!("must be auto, cfr or off, got {}", self.vfr.mode),
+        );
+        for (key, time) in [
+            ("schedule.start_time", &self.schedule.start_time),
+            ("schedule.end_time", &self.schedule.end_time),
+        ] {
+            check(
+                time.is_empty() || schedule::parse(time).is_some(),
+                key,
+                format!("must be a time like 01:00 or empty, got {}", time),
+            );
+        }
+        check(
+            self.schedule.start_time.is_empty() == self.schedule.end_time.is_empty(),
+            "schedule",
+            String::from("needs both start_time and end_time, or neither"),
+        );
+        check(
+            self.vfr.sequence_fps > 0.0,
+            "vfr.sequence_fps",
+            format!("must be above 0, got {}", self.vfr.sequence_fps),
+        );
+        check(
+            one_of(&self.advanced.process_priority, &["low", "normal", "high"]),
+            "advanced.process_priority",
+            format!(
+                "must be low, normal or high, got {}",
+                self.advanced.process_priority
+            ),
+        );
+        check(
+            one_of(&self.advanced.disk_check, &["refuse", "warn", "off"]),
+            "advanced.disk_check",
+            format!(
+                "must be refuse, warn or off, got {}",
+                self.advanced.disk_check
+            ),
+        );
+        check(
+            !Path::new(&self.advanced.index_cache).is_file(),
+            "advanced.index_cache",
+            format!("is a file, not a folder: {}", self.advanced.index_cache),
+        );
+        check(
+            one_of(&self.advanced.pause_on_exit, &["auto", "always", "never"]),
+            "advanced.pause_on_exit",
+            format!(
+                "must be auto, always or never, got {}",
+                self.advanced.pause_on_exit
+            ),
+        );
+
+        errors
+    }
+
+    /// Swaps every advanced setting for the most conservative one, to rule them out when
+    /// tracking down problems
+    pub fn safe_mode(&mut self) {
+        self.filters = Filters {
+            brightness: 1.0,
+            contrast: 1.0,
+            saturation: 1.0,
+        };
+        self.advanced.threads = 1;
+        self.advanced.chunks = 0;
+        self.advanced.encoding.gpu = false;
+        self.advanced.encoding.deduplicate = false;
+        self.advanced.encoding.custom_ffmpeg_filters = None;
+        self.advanced.interpolation = AdvancedInterpolation {
+            program: String::from("svp"),
+            speed: String::from("default"),
+            tuning: String::from("default"),
+            algorithm: String::from("default"),
+            mask: 0.0,
+            rife_model: String::new(),
+            rife_gpu_index: -1,
+        };
+        self.advanced.performance = AdvancedPerformance::default();
+        self.advanced.process_priority = default_process_priority();
+        self.advanced.cpu_affinity = vec![];
+        self.advanced.vspipe_args = vec![];
+        self.encoding.extra_args = vec![];
+        self.encoding.codec = default_codec();
+        self.encoding.preset = default_preset();
+        self.encoding.pixel_format = default_pixel_format();
+        self.blending.adaptive = false;
+        self.mask = Mask::default();
+        self.color = Color::default();
+        // the programs on the PATH or bundled with teres instead of configured ones
+        self.advanced.ffmpeg_path = String::new();
+        self.advanced.ffprobe_path = String::new();
+        self.advanced.vspipe_path = String::new();
+        binaries::configure(self);
+    }
+
+    /// Throttles rendering for `--background` so it can run alongside games and calls: a
+    /// quarter of the cores at low priority, less memory, and the slower `medium` software preset
+    /// instead of `superfast`, which makes smaller files for the time the few threads take
+    pub fn background(&mut self) {
+        let cores = std::thread::available_parallelism().map_or(4, |cores| cores.get()) as u32;
+        let threads = (cores / 4).max(1);
+        self.advanced.threads = threads;
+        self.advanced.chunks = 0;
+        self.advanced.process_priority = String::from("low");
+        self.advanced.performance.vs_threads = threads;
+        if self.advanced.performance.max_cache_mb == 0 {
+            self.advanced.performance.max_cache_mb = 1024;
+        }
+        if !self.advanced.encoding.gpu && self.encoding.preset.to_lowercase() == "default" {
+            self.encoding.preset = String::from("medium");
         }
+    }
+
+    /// Pins what makes renders of the same video differ for `advanced.deterministic`: a fixed
+    /// thread count as x264 splits its work by it, software encoding and interpolation which
+    /// change between gpus and drivers, and no chunks which move where the encoder starts over
+    pub fn deterministic(&mut self) {
+        self.advanced.threads = DETERMINISTIC_THREADS;
+        self.advanced.performance.vs_threads = DETERMINISTIC_THREADS;
+        self.advanced.chunks = 0;
+        self.advanced.encoding.gpu = false;
+        self.advanced.performance.interpolation_gpu = String::from("off");
+    }
 
-        let mut f = std::fs::File::open(config_file).expect("Could not open file.");
-        let mut contents = String::new();
-        f.read_to_string(&mut contents)
-            .expect("Could not parse config file to string");
-        let scrape_config: Config = toml::from_str(&contents).expect("Could not read values.");
-        scrape_config
+    /// These settings without the upload and notification sections, which hold accounts,
+    /// tokens and webhooks, for everywhere settings are written out next to renders
+    pub fn shareable(&self) -> Config {
+        Config {
+            notifications: Notifications::default(),
+            upload: Upload::default(),
+            ..self.clone()
+        }
+    }
+
+    /// The `PORTABLE_KEYS` of these settings as `key=value` overrides, to render the same way
+    /// with the config of another machine
+    pub fn portable(&self) -> Vec<String> {
+        Value::try_from(self)
+            .map(|value| portable_overrides(&value).0)
+            .unwrap_or_default()
+    }
+
+    /// These settings with `key=value` overrides applied over them
+    pub fn with_overrides(&self, overrides: &[String]) -> Result<Config, String> {
+        let mut merged = Value::try_from(self).map_err(|error| error.to_string())?;
+        for item in overrides {
+            let (path, value) = item
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {}", item))?;
+            merge(
+                &mut merged,
+                nested_value(path.trim(), value.trim()),
+                "override",
+                "",
+                &mut Provenance::new(),
+            );
+        }
+        merged
+            .try_into()
+            .map_err(|error: toml::de::Error| error.to_string())
     }
 
     pub fn create(filepath: &std::path::Path) {
         let prefix = filepath.parent().unwrap();
         std::fs::create_dir_all(prefix).unwrap();
-        fs::write(filepath, String::from("# Teres Configuration
-# For documentation for what each value means and the accecpted values see
-# https://animafps.github.io/teres/docs/configuration
+        fs::write(filepath, DEFAULT_CONFIG).expect("Failed to create config file")
+    }
+}
 
-[blending]
-enabled = true
-amount = 1.0
-weighting = \"equal\" # equal/gaussian/gaussian_sym/pyramid/pyramid_sym
-output_fps = 60
+/// Reports a value of the wrong type along with where it was set and exits
+fn unreadable(key: &str, message: &str, provenance: &Provenance) {
+    error!(
+        "Invalid config value {} (from {}): {}",
+        key,
+        provenance.get(key).map_or("default", |source| source),
+        message
+    );
+    helpers::exit(exitcode::CONFIG);
+}
 
-[interpolation]
-enabled = true
-fps = 480.0
+/// Settings that only change how the blur looks and how it is encoded, the only ones taken
+/// from other machines, api requests and the metadata of downloaded videos. Paths, programs,
+/// options handed on to ffmpeg or vspipe as they are and what happens after a render always
+/// come from the local config
+pub const PORTABLE_KEYS: &[&str] = &[
+    "blending",
+    "interpolation",
+    "timescale",
+    "filters",
+    "transform",
+    "vfr",
+    "mask.rect",
+    "mask.invert",
+    "output.resolution",
+    "output.pad",
+    "color.saturation",
+    "color.contrast",
+    "color.gamma",
+    "overlay.position",
+    "overlay.opacity",
+    "audio.enabled",
+    "audio.keep_original",
+    "audio.music_volume",
+    "audio.normalize",
+    "encoding.quality",
+    "encoding.container",
+    "encoding.animated_fps",
+    "encoding.animated_width",
+    "encoding.codec",
+    "encoding.preset",
+    "encoding.pixel_format",
+    "encoding.max_bitrate_kbps",
+    "encoding.preserve_alpha",
+    "advanced.deterministic",
+    "advanced.encoding.deduplicate",
+    "advanced.blend_weighting",
+    "advanced.interpolation.program",
+    "advanced.interpolation.speed",
+    "advanced.interpolation.tuning",
+    "advanced.interpolation.algorithm",
+    "advanced.interpolation.mask",
+];
 
-[encoding]
-quality = 18
-detailed_filename = false
-container = \"mp4\"
+/// Weightings of `blending.weighting` which aren't python pasted into the script
+const NAMED_WEIGHTINGS: [&str; 5] = [
+    "equal",
+    "gaussian",
+    "gaussian_sym",
+    "pyramid",
+    "pyramid_sym",
+];
 
-[timescale]
-input = 1.0
-output = 1.0
-adjust_audio_pitch = false
+/// Whether a dotted key is one of the `PORTABLE_KEYS` and its value can't reach further than
+/// the setting, custom weightings are python and containers become the output extension
+pub fn is_portable(key: &str, value: &Value) -> bool {
+    let listed = PORTABLE_KEYS.iter().any(|portable| {
+        key == *portable
+            || key
+                .strip_prefix(portable)
+                .is_some_and(|rest| rest.starts_with('.'))
+    });
+    match key {
+        _ if !listed => false,
+        "blending.weighting" => value.as_str().is_some_and(|weighting| {
+            NAMED_WEIGHTINGS.contains(&weighting) || custom_weights(weighting).is_some()
+        }),
+        "encoding.container" => value.as_str().is_some_and(|container| {
+            container
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }),
+        _ => true,
+    }
+}
 
-[filters]
-brightness = 1.0
-contrast = 1.0
-saturation = 1.0
+/// Flattens a table of settings into `key=value` overrides of its portable values, along with
+/// the keys that were left out
+pub fn portable_overrides(value: &Value) -> (Vec<String>, Vec<String>) {
+    fn flatten(value: &Value, prefix: &str, leaves: &mut Vec<(String, Value)>) {
+        match value {
+            Value::Table(table) => {
+                for (key, value) in table {
+                    let path = if prefix.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", prefix, key)
+                    };
+                    flatten(value, &path, leaves);
+                }
+            }
+            _ => leaves.push((prefix.to_string(), value.clone())),
+        }
+    }
+    let mut leaves = vec![];
+    flatten(value, "", &mut leaves);
+    let (portable, rejected): (Vec<_>, Vec<_>) = leaves
+        .into_iter()
+        .filter(|(key, _)| key != "version")
+        .partition(|(key, value)| is_portable(key, value));
+    (
+        portable
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect(),
+        rejected.into_iter().map(|(key, _)| key).collect(),
+    )
+}
 
-[advanced.encoding]
-gpu = false
-gpu_type = \"nvidia\" # nvidia/intel/amd
-deduplicate = false
+/// Keys of `key=value` overrides which aren't portable
+pub fn unportable(overrides: &[String]) -> Vec<String> {
+    overrides
+        .iter()
+        .filter_map(|item| match item.split_once('=') {
+            Some((key, raw)) if is_portable(key.trim(), &raw_value(raw.trim())) => None,
+            Some((key, _)) => Some(key.trim().to_string()),
+            None => Some(item.clone()),
+        })
+        .collect()
+}
 
-[advanced.blend_weighting]
-gaussian_std_dev = 2
-triangle_reverse = false
-bound = [0, 2]
+/// Keys renamed or moved to another section by each version, as `(version, old, new)`
+const MOVED_KEYS: &[(i64, &str, &str)] = &[];
 
-[advanced.interpolation]
-program = \"svp\" # svp/rife/rife-ncnn
-speed = \"default\" # medium/fast/faster/default (default is medium)
-tuning = \"default\" # film/animation/weak/smooth/default (default is smooth)
-algorithm = \"default\" # 2/13/23/default (default is 13)"))
-            .expect("Failed to create config file")
+/// Upgrades a config file one version at a time to `CONFIG_VERSION` by moving the keys it sets
+/// that were renamed, leaving out whatever it doesn't set so the defaults still apply. Returns
+/// whether any key moved
+fn migrate(mut value: Value, from: i64) -> (Value, bool) {
+    let mut moved = false;
+    for (_, old, new) in MOVED_KEYS.iter().filter(|(version, ..)| *version > from) {
+        if let Some(setting) = take_key(&mut value, old) {
+            merge(
+                &mut value,
+                nested_value_of(new, setting),
+                "migration",
+                "",
+                &mut Provenance::new(),
+            );
+            moved = true;
+        }
+    }
+    if let Value::Table(table) = &mut value {
+        table.insert("version".to_string(), Value::Integer(CONFIG_VERSION));
+    }
+    (value, moved)
+}
+
+/// Removes a dotted key from a table, returning its value
+fn take_key(value: &mut Value, path: &str) -> Option<Value> {
+    let (parent, key) = match path.rsplit_once('.') {
+        Some((parent, key)) => (
+            parent
+                .split('.')
+                .try_fold(value, |value, section| value.get_mut(section))?,
+            key,
+        ),
+        None => (value, path),
+    };
+    parent.as_table_mut()?.remove(key)
+}
+
+/// The contents of a config file with its `version` set to `CONFIG_VERSION`, keeping the rest
+/// of the file as it was written
+fn with_version(contents: &str) -> String {
+    let mut replaced = false;
+    let mut in_table = false;
+    let mut lines: Vec<String> = vec![];
+    for line in contents.lines() {
+        in_table |= line.trim_start().starts_with('[');
+        let key = line.split('=').next().unwrap_or_default().trim();
+        if !in_table && !replaced && key == "version" && line.contains('=') {
+            lines.push(format!("version = {}", CONFIG_VERSION));
+            replaced = true;
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    if !replaced {
+        lines.insert(0, format!("version = {}", CONFIG_VERSION));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Builds `{ a = { b = value } }` from `a.b` and a raw value, which is read as toml when possible
+fn nested_value(path: &str, raw: &str) -> Value {
+    nested_value_of(path, raw_value(raw))
+}
+
+/// Builds `{ a = { b = value } }` from `a.b` and a value
+fn nested_value_of(path: &str, mut value: Value) -> Value {
+    for key in path.rsplit('.') {
+        let mut table = toml::value::Table::new();
+        table.insert(key.to_string(), value);
+        value = Value::Table(table);
+    }
+    value
+}
+
+/// Reads the value of an override as toml, or as a string when it isn't
+fn raw_value(raw: &str) -> Value {
+    toml::from_str::<BTreeMap<String, Value>>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+fn merge(base: &mut Value, layer: Value, source: &str, prefix: &str, provenance: &mut Provenance) {
+    match (base, layer) {
+        (Value::Table(base), Value::Table(layer)) => {
+            for (key, value) in layer {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                match base.get_mut(&key) {
+                    Some(existing) if existing.is_table() && value.is_table() => {
+                        merge(existing, value, source, &path, provenance)
+                    }
+                    _ => {
+                        record(&value, source, &path, provenance);
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => {
+            record(&layer, source, prefix, provenance);
+            *base = layer;
+        }
+    }
+}
+
+fn record(value: &Value, source: &str, path: &str, provenance: &mut Provenance) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                record(value, source, &format!("{}.{}", path, key), provenance);
+            }
+        }
+        _ => {
+            provenance.insert(path.to_string(), source.to_string());
+        }
+    }
+}
+
+fn write_table(
+    output: &mut String,
+    table: &toml::value::Table,
+    prefix: &str,
+    provenance: &Provenance,
+) {
+    let (tables, values): (Vec<_>, Vec<_>) = table.iter().partition(|(_, value)| value.is_table());
+    if !prefix.is_empty() && !values.is_empty() {
+        output.push_str(&format!("\n[{}]\n", prefix));
+    }
+    for (key, value) in values {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        output.push_str(&format!(
+            "{} = {} # {}\n",
+            key,
+            value,
+            provenance.get(&path).map_or("default", |source| source)
+        ));
+    }
+    for (key, value) in tables {
+        if let Value::Table(table) = value {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            write_table(output, table, &path, provenance);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(resolution: &str) -> Output {
+        Output {
+            resolution: resolution.to_string(),
+            ..Output::default()
+        }
+    }
+
+    fn timescale(curve: Vec<[f32; 2]>) -> Timescale {
+        Timescale {
+            input: 1.0,
+            output: 1.0,
+            adjust_audio_pitch: false,
+            curve,
+        }
+    }
+
+    #[test]
+    fn segments_are_empty_without_a_curve() {
+        assert!(timescale(vec![]).segments().is_empty());
+    }
+
+    #[test]
+    fn segments_keep_constant_speeds_whole() {
+        assert_eq!(
+            timescale(vec![[2.0, 0.5], [4.0, 0.5]]).segments(),
+            [
+                (0.0, Some(2.0), 0.5),
+                (2.0, Some(4.0), 0.5),
+                (4.0, None, 0.5)
+            ]
+        );
+    }
+
+    #[test]
+    fn segments_step_through_ramps() {
+        let segments = timescale(vec![[0.0, 1.0], [5.0, 0.25]]).segments();
+        // a step every RAMP_STEP seconds and the last speed until the end
+        assert_eq!(segments.len(), 21);
+        let (start, end, speed) = segments[0];
+        assert_eq!((start, end), (0.0, Some(0.25)));
+        assert!((speed - 0.98125).abs() < 1e-6);
+        assert_eq!(segments[20], (5.0, None, 0.25));
+    }
+
+    #[test]
+    fn output_size_reads_names_and_sizes() {
+        assert_eq!(output("1080p").size(), Some((1920, 1080)));
+        assert_eq!(output("4K").size(), Some((3840, 2160)));
+        assert_eq!(output("2560x1080").size(), Some((2560, 1080)));
+    }
+
+    #[test]
+    fn output_size_swaps_vertical_sides() {
+        assert_eq!(output("1080p-vertical").size(), Some((1080, 1920)));
+        assert_eq!(output("1280x720-vertical").size(), Some((720, 1280)));
+    }
+
+    #[test]
+    fn output_size_is_none_when_unset_or_unknown() {
+        assert_eq!(output("").size(), None);
+        assert_eq!(output("huge").size(), None);
+        assert_eq!(output("1920xtall").size(), None);
+    }
+
+    #[test]
+    fn read_names_the_key_of_a_wrong_type() {
+        let mut merged: Value = toml::from_str(DEFAULT_CONFIG).unwrap();
+        merge(
+            &mut merged,
+            nested_value("blending.amount", "\"lots\""),
+            "override",
+            "",
+            &mut Provenance::new(),
+        );
+        let (key, message) = Config::read(merged).unwrap_err();
+        assert_eq!(key, "blending.amount");
+        assert!(message.starts_with("invalid type"), "{}", message);
     }
 }
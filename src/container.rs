@@ -0,0 +1,104 @@
+use crate::sequence;
+use std::ffi::OsStr;
+use std::path::Path;
+
+/// What ffmpeg needs to write each kind of output file, picked from its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Mp4,
+    Mov,
+    Mkv,
+    Webm,
+    Gif,
+    Webp,
+    /// Numbered png frames instead of a video, e.g. for compositing software
+    PngSequence,
+    /// Anything else, left to ffmpeg's defaults for the extension
+    Other,
+}
+
+impl Container {
+    pub fn of(path: &Path) -> Container {
+        let extension = path
+            .extension()
+            .and_then(OsStr::to_str)
+            .map(str::to_lowercase)
+            .unwrap_or_default();
+        if sequence::is_sequence(path) {
+            return if extension == "png" {
+                Container::PngSequence
+            } else {
+                Container::Other
+            };
+        }
+        Container::named(&extension)
+    }
+
+    /// From an extension or a container name like `matroska`
+    pub fn named(name: &str) -> Container {
+        match name.to_lowercase().as_str() {
+            "mp4" | "m4v" => Container::Mp4,
+            "mov" | "quicktime" => Container::Mov,
+            "mkv" | "matroska" => Container::Mkv,
+            "webm" => Container::Webm,
+            "gif" => Container::Gif,
+            "webp" => Container::Webp,
+            "png_sequence" => Container::PngSequence,
+            _ => Container::Other,
+        }
+    }
+
+    /// Extension of output files for the `encoding.container` setting, which also takes the
+    /// container names
+    pub fn extension(name: &str) -> String {
+        match Container::named(name) {
+            Container::Mov => String::from("mov"),
+            Container::Mkv => String::from("mkv"),
+            Container::PngSequence => String::from("png"),
+            _ => name.to_lowercase(),
+        }
+    }
+
+    /// Looping animations without audio
+    pub fn is_animated(self) -> bool {
+        matches!(self, Container::Gif | Container::Webp)
+    }
+
+    /// Muxer options, mp4 and mov move their index to the front so they play while downloading
+    /// and only keep tags of their own names without `use_metadata_tags`
+    pub fn mux_args(self, custom_tags: bool) -> &'static [&'static str] {
+        match self {
+            Container::Mp4 | Container::Mov if custom_tags => {
+                &["-movflags", "+faststart+use_metadata_tags"]
+            }
+            Container::Mp4 | Container::Mov => &["-movflags", "+faststart"],
+            _ => &[],
+        }
+    }
+
+    /// Encoder of reencoded audio, webm only holds opus and vorbis
+    pub fn audio_codec(self) -> &'static str {
+        match self {
+            Container::Webm => "libopus",
+            _ => "aac",
+        }
+    }
+
+    /// Bitrate of reencoded audio, opus needs less for the same quality
+    pub fn audio_bitrate(self) -> &'static str {
+        match self {
+            Container::Webm => "192k",
+            _ => "320k",
+        }
+    }
+
+    /// Whether audio in `codec`, as ffprobe names it, can be copied in without reencoding
+    pub fn can_copy_audio(self, codec: &str) -> bool {
+        match self {
+            Container::Mkv | Container::Other => true,
+            Container::Webm => ["opus", "vorbis"].contains(&codec),
+            Container::Mp4 | Container::Mov => ["aac", "mp3", "alac", "ac3"].contains(&codec),
+            Container::Gif | Container::Webp | Container::PngSequence => false,
+        }
+    }
+}
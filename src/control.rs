@@ -0,0 +1,249 @@
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::Mutex;
+
+/// Folder holding a file per running teres process listing its vspipe and ffmpeg pids
+fn control_dir() -> PathBuf {
+    std::env::temp_dir().join("teres")
+}
+
+fn pid_file() -> PathBuf {
+    control_dir().join(format!("{}.pids", std::process::id()))
+}
+
+/// Pids of this process' running renders, several when rendering in chunks
+static PIDS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Records the pids of a running render so other teres processes can pause it
+pub fn register(pids: &[u32]) {
+    let mut running = PIDS.lock().unwrap();
+    running.extend(pids);
+    write_pids(&running);
+}
+
+pub fn unregister(pids: &[u32]) {
+    let mut running = PIDS.lock().unwrap();
+    running.retain(|pid| !pids.contains(pid));
+    if running.is_empty() {
+        let _ = std::fs::remove_file(pid_file());
+    } else {
+        write_pids(&running);
+    }
+}
+
+fn write_pids(pids: &[u32]) {
+    let result = std::fs::create_dir_all(control_dir()).and_then(|_| {
+        std::fs::write(
+            pid_file(),
+            pids.iter()
+                .map(|pid| pid.to_string())
+                .collect::<Vec<String>>()
+                .join(" "),
+        )
+    });
+    if let Err(error) = result {
+        debug!("Could not write pid file: {}", error);
+    }
+}
+
+/// Runs a command to its end like `Command::output`, registered so it is paused and stopped
+/// with the renders
+pub fn output(command: &mut Command) -> std::io::Result<Output> {
+    let child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let pid = child.id();
+    register(&[pid]);
+    let output = child.wait_with_output();
+    unregister(&[pid]);
+    output
+}
+
+pub fn pause() {
+    signal_renders("STOP", "Paused");
+}
+
+pub fn resume() {
+    signal_renders("CONT", "Resumed");
+}
+
+/// Pauses or resumes the renders of this process only
+pub fn suspend(paused: bool) {
+    let signal = if paused { "STOP" } else { "CONT" };
+    for pid in PIDS.lock().unwrap().iter() {
+        if !send_signal(&pid.to_string(), signal) {
+            debug!("Could not signal process {}", pid);
+        }
+    }
+}
+
+/// Stops the renders of this process, which then fail as if the processes crashed
+pub fn cancel() {
+    for pid in PIDS.lock().unwrap().iter() {
+        if !kill(*pid) {
+            debug!("Could not stop process {}", pid);
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn kill(pid: u32) -> bool {
+    send_signal(&pid.to_string(), "TERM")
+}
+
+#[cfg(target_os = "windows")]
+fn kill(pid: u32) -> bool {
+    Command::new("taskkill")
+        .args(["/F", "/PID", &pid.to_string()])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+fn signal_renders(signal: &str, action: &str) {
+    let entries = match control_dir().read_dir() {
+        Ok(entries) => entries,
+        Err(_) => {
+            info!("No renders are running");
+            return;
+        }
+    };
+
+    let mut signalled = 0;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path.extension() != Some(OsStr::new("pids")) {
+            continue;
+        }
+        let pids = std::fs::read_to_string(&path).unwrap_or_default();
+        let mut alive = false;
+        for pid in pids.split_whitespace() {
+            if send_signal(pid, signal) {
+                alive = true;
+            }
+        }
+        if alive {
+            signalled += 1;
+        } else {
+            // the teres process that wrote it has exited without cleaning up
+            trace!("Removing stale pid file {}", path.display());
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    if signalled == 0 {
+        info!("No renders are running");
+    } else {
+        info!("{} {} render(s)", action, signalled);
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn send_signal(pid: &str, signal: &str) -> bool {
+    Command::new("kill")
+        .args([format!("-{}", signal), pid.to_string()])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(target_os = "windows")]
+fn send_signal(pid: &str, signal: &str) -> bool {
+    // PowerShell's Debug-Process can't suspend, so use the undocumented but stable NtSuspendProcess
+    let function = if signal == "STOP" {
+        "NtSuspendProcess"
+    } else {
+        "NtResumeProcess"
+    };
+    let script = format!(
+        "$s = Add-Type -Name N -PassThru -MemberDefinition '[DllImport(\"ntdll.dll\")] public static extern int {0}(IntPtr h);'; \
+         $p = Get-Process -Id {1} -ErrorAction Stop; [void]$s::{0}($p.Handle)",
+        function, pid
+    );
+    powershell(&script)
+}
+
+/// Sets the cpu priority and affinity of the render processes
+pub fn set_priority(pids: &[u32], priority: &str, cpu_affinity: &[usize]) {
+    let priority = priority.to_lowercase();
+    if priority != "normal" {
+        if !["low", "high"].contains(&priority.as_str()) {
+            warn!("Unknown process priority {}, using normal", priority);
+        } else {
+            for pid in pids {
+                if !set_process_priority(*pid, &priority) {
+                    warn!(
+                        "Could not set the priority of process {} to {}",
+                        pid, priority
+                    );
+                }
+            }
+        }
+    }
+
+    if !cpu_affinity.is_empty() {
+        for pid in pids {
+            if !set_process_affinity(*pid, cpu_affinity) {
+                warn!("Could not set the cpu affinity of process {}", pid);
+            }
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn set_process_priority(pid: u32, priority: &str) -> bool {
+    // raising priority (negative niceness) needs root
+    let niceness = if priority == "low" { "10" } else { "-5" };
+    Command::new("renice")
+        .args(["-n", niceness, "-p", &pid.to_string()])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(target_family = "unix")]
+fn set_process_affinity(pid: u32, cpu_affinity: &[usize]) -> bool {
+    let cores = cpu_affinity
+        .iter()
+        .map(|core| core.to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+    Command::new("taskset")
+        .args(["-a", "-p", "-c", &cores, &pid.to_string()])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(target_os = "windows")]
+fn set_process_priority(pid: u32, priority: &str) -> bool {
+    let class = if priority == "low" {
+        "BelowNormal"
+    } else {
+        "AboveNormal"
+    };
+    powershell(&format!(
+        "(Get-Process -Id {} -ErrorAction Stop).PriorityClass = '{}'",
+        pid, class
+    ))
+}
+
+#[cfg(target_os = "windows")]
+fn set_process_affinity(pid: u32, cpu_affinity: &[usize]) -> bool {
+    let mask: u64 = cpu_affinity.iter().map(|core| 1u64 << core).sum();
+    powershell(&format!(
+        "(Get-Process -Id {} -ErrorAction Stop).ProcessorAffinity = {}",
+        pid, mask
+    ))
+}
+
+#[cfg(target_os = "windows")]
+fn powershell(script: &str) -> bool {
+    Command::new("powershell")
+        .args(["-NoProfile", "-Command", script])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
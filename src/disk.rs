@@ -0,0 +1,96 @@
+use crate::config::Config;
+use crate::container::Container;
+use crate::probe::MediaInfo;
+use crate::rendering::Render;
+use indicatif::HumanBytes;
+use std::path::Path;
+use std::process::Command;
+
+/// Rough size in bytes of a render, from the output resolution, frame rate and quality. It errs
+/// on the large side since running out of space costs more than a false alarm
+pub fn estimate(settings: &Config, info: &MediaInfo, input_path: &Path, remux_only: bool) -> u64 {
+    if remux_only {
+        return input_path.metadata().map(|meta| meta.len()).unwrap_or(0);
+    }
+    let (width, height) = settings.output.size().unwrap_or((info.width, info.height));
+    let fps = if settings.blending.enabled {
+        f64::from(settings.blending.output_fps)
+    } else {
+        info.fps_f64().unwrap_or(60.0)
+    };
+    // bits per pixel of each frame, x264 about doubles its bitrate every 6 crf
+    let bits_per_pixel = if settings.encoding.preserve_alpha
+        && Container::named(&settings.encoding.container) == Container::Mov
+    {
+        4.0
+    } else if Container::named(&settings.encoding.container) == Container::PngSequence {
+        // pngs of game footage shrink to about half of the raw 24 bits
+        12.0
+    } else {
+        0.3 * 2f64.powf(f64::from(18 - settings.encoding.quality) / 6.0)
+    };
+    let pixels = f64::from(width) * f64::from(height) * fps * info.duration;
+    (pixels * bits_per_pixel / 8.0) as u64
+}
+
+/// Describes the drive short of space for a render, if any. Chunked renders also keep a copy of
+/// the output in the temp folder until the chunks are joined
+pub fn shortage(render: &Render, remux_only: bool, chunked: bool) -> Option<String> {
+    let info = render.media_info.as_ref()?;
+    let needed = estimate(&render.settings, info, &render.video_path, remux_only);
+    let mut folders = vec![(render.output_filepath.parent()?, "output")];
+    if chunked {
+        folders.push((render.script_path.parent()?, "temp"));
+    }
+    folders.into_iter().find_map(|(folder, name)| {
+        // a bare file name is in the current folder
+        let folder = if folder.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            folder
+        };
+        let free = free_space(folder)?;
+        (free < needed).then(|| {
+            format!(
+                "the {} folder {} has {} free, the render needs about {}",
+                name,
+                folder.display(),
+                HumanBytes(free),
+                HumanBytes(needed)
+            )
+        })
+    })
+}
+
+/// Free bytes on the drive holding `path`
+#[cfg(target_family = "unix")]
+pub fn free_space(path: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // Filesystem 1024-blocks Used Available Capacity Mounted on
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let available = stdout.lines().nth(1)?.split_whitespace().nth(3)?;
+    available
+        .parse::<u64>()
+        .ok()
+        .map(|kilobytes| kilobytes * 1024)
+}
+
+/// Free bytes on the drive holding `path`
+#[cfg(target_os = "windows")]
+pub fn free_space(path: &Path) -> Option<u64> {
+    let output = Command::new("powershell")
+        .args([
+            "-NoProfile",
+            "-Command",
+            &format!(
+                "(Get-Item -LiteralPath '{}').PSDrive.Free",
+                path.display().to_string().replace('\'', "''")
+            ),
+        ])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
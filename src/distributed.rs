@@ -0,0 +1,329 @@
+use crate::config::{self, Config};
+use crate::container::Container;
+use crate::helpers;
+use crate::history::{self, Job};
+use crate::rendering::{Render, Rendering};
+use crate::sidecar;
+use indicatif::ProgressBar;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const DEFAULT_PORT: u16 = 7420;
+
+/// Environment variable holding the secret workers and coordinators share
+const TOKEN_VAR: &str = "TERES_WORKER_TOKEN";
+
+/// Largest input video a worker accepts
+const MAX_INPUT_SIZE: u64 = 64 * 1024 * 1024 * 1024;
+
+/// Longest json line read before a video
+const MAX_HEADER_SIZE: u64 = 1024 * 1024;
+
+/// How long a worker waits for the next bytes of a request before dropping the connection
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Sent to a worker as a json line followed by `size` bytes of the input video, the settings
+/// are the portable ones as `key=value` overrides of the worker's own config
+#[derive(Serialize, Deserialize)]
+struct Request {
+    token: String,
+    filename: String,
+    size: u64,
+    settings: Vec<String>,
+}
+
+/// Sent back as a json line followed by `size` bytes of the rendered video
+#[derive(Serialize, Deserialize)]
+struct Response {
+    job: Job,
+    size: u64,
+}
+
+fn token() -> Option<String> {
+    std::env::var(TOKEN_VAR)
+        .ok()
+        .filter(|token| !token.is_empty())
+}
+
+/// Compares in the same time wherever the tokens differ
+pub fn same_token(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Renders videos sent by coordinators one at a time until stopped, with its own config and
+/// the portable settings of each request over it
+pub fn worker(port: u16, settings: Config) {
+    let Some(token) = token() else {
+        error!(
+            "Set {} to a secret shared with the coordinators before starting a worker",
+            TOKEN_VAR
+        );
+        helpers::exit(exitcode::CONFIG);
+        return;
+    };
+    let listener = match TcpListener::bind(("0.0.0.0", port)) {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!("Could not listen on port {}: {}", port, error);
+            helpers::exit(exitcode::UNAVAILABLE);
+            return;
+        }
+    };
+    info!("Waiting for videos on port {}", port);
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| {
+            let peer = stream.peer_addr()?;
+            debug!("Connection from {}", peer);
+            handle(stream, &token, &settings)
+        });
+        if let Err(error) = result {
+            error!("Could not render video from coordinator: {}", error);
+        }
+    }
+}
+
+fn handle(stream: TcpStream, token: &str, local: &Config) -> Result<(), std::io::Error> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request: Request = read_header(&mut reader)?;
+    if !same_token(&request.token, token) {
+        return Err(invalid("wrong token"));
+    }
+    if request.size > MAX_INPUT_SIZE {
+        return Err(invalid("video too large"));
+    }
+    let unportable = config::unportable(&request.settings);
+    if !unportable.is_empty() {
+        return Err(invalid(&format!(
+            "settings only the worker's config may set: {}",
+            unportable.join(", ")
+        )));
+    }
+    let settings = local
+        .with_overrides(&request.settings)
+        .map_err(|error| invalid(&error))?;
+    if let Some((key, message)) = settings.validate().into_iter().next() {
+        return Err(invalid(&format!("{} {}", key, message)));
+    }
+    let filename = Path::new(&request.filename)
+        .file_name()
+        .ok_or_else(|| invalid("missing filename"))?
+        .to_owned();
+
+    let folder = std::env::temp_dir()
+        .join("teres-worker")
+        .join(rand::random::<u32>().to_string());
+    std::fs::create_dir_all(&folder)?;
+    let input = folder.join(&filename);
+    let received = std::fs::File::create(&input)
+        .and_then(|mut file| std::io::copy(&mut (&mut reader).take(request.size), &mut file));
+    if let Err(error) = received {
+        let _ = std::fs::remove_dir_all(&folder);
+        return Err(error);
+    }
+    info!("Rendering {} for {}", input.display(), stream.peer_addr()?);
+
+    // the coordinator waits for an answer, so a failed render is still sent back
+    let job = match Render::new(input.clone(), settings.clone()) {
+        Some(render) => Rendering::render_video(&render, false, true, ProgressBar::hidden())
+            .map_err(|error| error!("Could not render {}: {}", input.display(), error))
+            .ok(),
+        None => None,
+    }
+    .unwrap_or_else(|| {
+        Job::new(
+            input.clone(),
+            PathBuf::new(),
+            "failed",
+            Duration::ZERO,
+            None,
+            None,
+            settings,
+        )
+    });
+    let result = send_response(&stream, job);
+    let _ = std::fs::remove_dir_all(&folder);
+    result
+}
+
+fn send_response(mut stream: &TcpStream, job: Job) -> Result<(), std::io::Error> {
+    let finished = job.status == "finished";
+    let response = Response {
+        size: if finished {
+            std::fs::metadata(&job.output)?.len()
+        } else {
+            0
+        },
+        job,
+    };
+    writeln!(stream, "{}", serde_json::to_string(&response)?)?;
+    if finished {
+        std::io::copy(&mut std::fs::File::open(&response.job.output)?, &mut stream)?;
+    }
+    stream.flush()
+}
+
+/// Sends each video to the first free worker and saves the rendered videos next to the inputs
+pub fn coordinate(workers: &[String], videos: Vec<(PathBuf, Config)>) -> Vec<Job> {
+    let Some(token) = token() else {
+        error!(
+            "Set {} to the secret the workers were started with",
+            TOKEN_VAR
+        );
+        helpers::exit(exitcode::CONFIG);
+        return vec![];
+    };
+    let queue = Arc::new(Mutex::new(videos));
+    let jobs = Arc::new(Mutex::new(vec![]));
+    let threads: Vec<_> = workers
+        .iter()
+        .map(|worker| {
+            let address = if worker.contains(':') {
+                worker.clone()
+            } else {
+                format!("{}:{}", worker, DEFAULT_PORT)
+            };
+            let queue = Arc::clone(&queue);
+            let jobs = Arc::clone(&jobs);
+            let token = token.clone();
+            std::thread::spawn(move || loop {
+                let next = queue.lock().unwrap().pop();
+                let Some((video, settings)) = next else {
+                    break;
+                };
+                info!("Sending {} to {}", video.display(), address);
+                match send(&address, &token, &video, &settings) {
+                    Ok(job) => {
+                        history::record(&job);
+                        jobs.lock().unwrap().push(job);
+                    }
+                    Err(error) => {
+                        // give the video to another worker and stop using this one
+                        error!("Worker {} failed: {}", address, error);
+                        queue.lock().unwrap().push((video, settings));
+                        break;
+                    }
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        let _ = thread.join();
+    }
+
+    let left = queue.lock().unwrap().len();
+    if left > 0 {
+        error!(
+            "{} video(s) were not rendered as no workers were left",
+            left
+        );
+    }
+    let jobs = jobs.lock().unwrap().clone();
+    jobs
+}
+
+fn send(
+    address: &str,
+    token: &str,
+    video: &Path,
+    settings: &Config,
+) -> Result<Job, std::io::Error> {
+    let output = output_path(video, settings)?;
+    if output.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", output.display()),
+        ));
+    }
+    let mut stream = TcpStream::connect(address)?;
+    let request = Request {
+        token: token.to_string(),
+        filename: video
+            .file_name()
+            .ok_or_else(|| invalid("missing filename"))?
+            .to_string_lossy()
+            .to_string(),
+        size: std::fs::metadata(video)?.len(),
+        settings: settings.portable(),
+    };
+    writeln!(stream, "{}", serde_json::to_string(&request)?)?;
+    std::io::copy(&mut std::fs::File::open(video)?, &mut stream)?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let response: Response = read_header(&mut reader)?;
+    let mut job = response.job;
+    job.input = video.to_path_buf();
+    job.settings = settings.shareable();
+    if job.status != "finished" {
+        error!("{} failed to render on {}", video.display(), address);
+        return Ok(job);
+    }
+
+    // create_new so a file written since the check above is never replaced
+    std::io::copy(
+        &mut reader.take(response.size),
+        &mut std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&output)?,
+    )?;
+    if let Err(error) = sidecar::write(&output, video, settings) {
+        warn!(
+            "Could not write sidecar metadata for {}: {}",
+            output.display(),
+            error
+        );
+    }
+    info!("Received {} from {}", output.display(), address);
+    job.output = output;
+    Ok(job)
+}
+
+/// Where the rendered video is saved, named from the coordinator's own filename_template and
+/// always next to the input whatever the worker answers
+fn output_path(video: &Path, settings: &Config) -> Result<PathBuf, std::io::Error> {
+    let name = video
+        .file_stem()
+        .ok_or_else(|| invalid("missing filename"))?
+        .to_string_lossy();
+    let extension = if settings.encoding.container.eq_ignore_ascii_case("source") {
+        video
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_string())
+            .unwrap_or_else(|| String::from("mp4"))
+    } else {
+        Container::extension(&settings.encoding.container)
+    };
+    let filename = format!(
+        "{}.{}",
+        helpers::format_filename(&settings.encoding.filename_template, &name, settings),
+        extension
+    );
+    // the template and the values filled into it may hold separators, only the last part is kept
+    let filename = Path::new(&filename)
+        .file_name()
+        .filter(|name| !sidecar::is_sidecar(Path::new(name)))
+        .ok_or_else(|| invalid("invalid output filename"))?;
+    Ok(video.with_file_name(filename))
+}
+
+fn read_header<T: DeserializeOwned>(
+    reader: &mut BufReader<impl Read>,
+) -> Result<T, std::io::Error> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_HEADER_SIZE).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,227 @@
+use crate::binaries;
+use crate::config::Config;
+use crate::helpers::exit;
+use regex::Regex;
+use std::collections::HashMap;
+use std::process::Command;
+
+/// Oldest ffmpeg with every filter option teres uses, `amix=normalize` being the newest
+const MIN_FFMPEG: (u32, u32) = (4, 4);
+/// Oldest VapourSynth core, the first with the API 4 plugins are built against
+const MIN_VAPOURSYNTH: u32 = 55;
+
+/// Prints an entry of the report, returning whether it passed or isn't needed
+fn report(name: &str, result: Result<String, String>, required: bool) -> bool {
+    let (status, detail) = match &result {
+        Ok(found) => ("ok", found),
+        Err(missing) if required => ("missing", missing),
+        Err(missing) => ("optional", missing),
+    };
+    println!("  {:<8} {:<14} {}", status, name, detail);
+    result.is_ok() || !required
+}
+
+/// Everything a program prints when run with `arg`, or None when it can't be run
+fn version(program: &str, arg: &str) -> Option<String> {
+    let output = Command::new(program).arg(arg).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout).to_string()
+        + &String::from_utf8_lossy(&output.stderr);
+    Some(text)
+}
+
+/// Checks every program and plugin a render can use and prints what is missing with how to
+/// install it, exiting with an error when a required one is missing
+pub fn run() {
+    let (settings, _) = Config::load(&[], None);
+    let mut healthy = true;
+
+    println!("Programs");
+    let ffmpeg = binaries::ffmpeg();
+    healthy &= report("ffmpeg", check_ffmpeg(&ffmpeg), true);
+    healthy &= report(
+        "ffprobe",
+        version(&binaries::ffprobe(), "-version")
+            .and_then(|text| text.split_whitespace().nth(2).map(str::to_string))
+            .ok_or_else(|| {
+                String::from(
+                    "comes with ffmpeg, reinstall it from https://ffmpeg.org/download.html",
+                )
+            }),
+        true,
+    );
+    let vspipe = binaries::vspipe();
+    healthy &= report("vspipe", check_vapoursynth(&vspipe), true);
+
+    println!("VapourSynth plugins");
+    let found = vapoursynth_modules();
+    let program = settings.advanced.interpolation.program.to_lowercase();
+    let plugins = [
+        ("ffms2", "ffms2", true, "install from https://github.com/FFMS/ffms2"),
+        ("fmtconv", "fmtc", true, "install from https://github.com/EleonoreMizo/fmtconv"),
+        ("frameblender", "frameblender", true, "install from https://github.com/couleurm/vs-frameblender"),
+        ("mvtools", "mv", true, "install from https://github.com/dubhater/vapoursynth-mvtools"),
+        ("svp", "svp", program == "svp", "install from https://www.svp-team.com/get/ (the SVPflow libraries)"),
+        ("rife-ncnn", "rife", program == "rife-ncnn", "install from https://github.com/HomeOfVapourSynthEvolution/VapourSynth-RIFE-ncnn-Vulkan"),
+        ("imwri", "imwri", !settings.overlay.image_path.is_empty(), "install from https://github.com/vapoursynth/vs-imwri, for overlay.image_path"),
+    ];
+    // plugins for settings that are turned off are only listed
+    for (name, namespace, required, install) in plugins {
+        healthy &= report(name, check_module(&found, namespace, install), required);
+    }
+
+    println!("Python modules");
+    let modules = [
+        ("havsfunc", true, "pip install havsfunc, or install from https://github.com/HomeOfVapourSynthEvolution/havsfunc"),
+        ("vsrife", program == "rife", "pip install vsrife"),
+    ];
+    for (name, required, install) in modules {
+        healthy &= report(name, check_module(&found, name, install), required);
+    }
+
+    println!("Encoders");
+    let encoders = version(&ffmpeg, "-encoders").unwrap_or_default();
+    let codec = settings.encoding.codec.to_lowercase();
+    let software = if codec == "hevc" {
+        "libx265"
+    } else {
+        "libx264"
+    };
+    healthy &= report(
+        software,
+        encoder(
+            &encoders,
+            software,
+            &format!("needs an ffmpeg build with --enable-{}", software),
+        ),
+        true,
+    );
+    for (suffix, gpu) in [
+        ("nvenc", "nvidia"),
+        ("amf", "amd"),
+        ("qsv", "intel"),
+        ("videotoolbox", "apple"),
+        ("vaapi", "vaapi"),
+    ] {
+        let name = &format!("{}_{}", codec, suffix);
+        let selected = settings.advanced.encoding.gpu
+            && settings
+                .advanced
+                .encoding
+                .gpu_type
+                .eq_ignore_ascii_case(gpu);
+        healthy &= report(
+            name,
+            encoder(
+                &encoders,
+                name,
+                &format!("for {} gpu encoding, needs an ffmpeg build with it", gpu),
+            ),
+            selected,
+        );
+    }
+
+    if !healthy {
+        error!("Some required dependencies are missing, see above");
+        exit(exitcode::UNAVAILABLE);
+    }
+    println!("Everything needed to render with the current config is installed");
+}
+
+fn check_ffmpeg(ffmpeg: &str) -> Result<String, String> {
+    let text = version(ffmpeg, "-version")
+        .ok_or_else(|| String::from("install it from https://ffmpeg.org/download.html"))?;
+    let found = text
+        .split_whitespace()
+        .nth(2)
+        .unwrap_or_default()
+        .to_string();
+    // git builds like N-109421-g... have no version to compare
+    let release = Regex::new(r"^n?(\d+)\.(\d+)").unwrap();
+    match release.captures(&found) {
+        Some(captures) => {
+            let version: (u32, u32) = (captures[1].parse().unwrap(), captures[2].parse().unwrap());
+            if version < MIN_FFMPEG {
+                return Err(format!(
+                    "{} is too old, {}.{} or newer is needed",
+                    found, MIN_FFMPEG.0, MIN_FFMPEG.1
+                ));
+            }
+            Ok(found)
+        }
+        None => Ok(found),
+    }
+}
+
+fn check_vapoursynth(vspipe: &str) -> Result<String, String> {
+    let text = version(vspipe, "--version").ok_or_else(|| {
+        String::from("install VapourSynth from https://github.com/vapoursynth/vapoursynth/releases")
+    })?;
+    let core = Regex::new(r"Core R(\d+)").unwrap();
+    let release: u32 = core
+        .captures(&text)
+        .and_then(|captures| captures[1].parse().ok())
+        .ok_or_else(|| String::from("could not read the VapourSynth version"))?;
+    if release < MIN_VAPOURSYNTH {
+        return Err(format!(
+            "R{} is too old, R{} or newer is needed",
+            release, MIN_VAPOURSYNTH
+        ));
+    }
+    Ok(format!("R{}", release))
+}
+
+/// Which plugin namespaces and python modules VapourSynth's python can load
+fn vapoursynth_modules() -> HashMap<String, bool> {
+    let script = r#"
+import importlib
+from vapoursynth import core
+for namespace in ["ffms2", "fmtc", "frameblender", "mv", "rife", "imwri"]:
+    print(namespace, hasattr(core, namespace))
+print("svp", hasattr(core, "svp1") and hasattr(core, "svp2"))
+for module in ["havsfunc", "vsrife"]:
+    try:
+        importlib.import_module(module)
+        print(module, True)
+    except Exception:
+        print(module, False)
+"#;
+    let python = if cfg!(target_os = "windows") {
+        "python"
+    } else {
+        "python3"
+    };
+    let output = match Command::new(python).args(["-c", script]).output() {
+        Ok(output) => output,
+        Err(_) => return HashMap::new(),
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, found)| (name.to_string(), found == "True"))
+        .collect()
+}
+
+fn check_module(
+    found: &HashMap<String, bool>,
+    name: &str,
+    install: &str,
+) -> Result<String, String> {
+    match found.get(name) {
+        Some(true) => Ok(String::from("found")),
+        Some(false) => Err(install.to_string()),
+        None => Err(String::from(
+            "could not check, the VapourSynth python module does not load",
+        )),
+    }
+}
+
+fn encoder(encoders: &str, name: &str, missing: &str) -> Result<String, String> {
+    if encoders
+        .lines()
+        .any(|line| line.split_whitespace().nth(1) == Some(name))
+    {
+        Ok(String::from("found"))
+    } else {
+        Err(missing.to_string())
+    }
+}
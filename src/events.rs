@@ -0,0 +1,81 @@
+use serde_json::Value;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, Once};
+use std::time::Duration;
+
+/// Clients following the progress, dropped once writing to them fails
+static SUBSCRIBERS: Mutex<Vec<Box<dyn Write + Send>>> = Mutex::new(Vec::new());
+static LISTEN: Once = Once::new();
+
+/// How long a write may wait for a subscriber to read, ones slower than this are dropped so
+/// they can't hold up rendering
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Where this process accepts subscribers, a unix socket or a file holding the localhost port
+/// on windows
+pub fn path() -> PathBuf {
+    let name = format!(
+        "{}.{}",
+        std::process::id(),
+        if cfg!(unix) { "sock" } else { "port" }
+    );
+    std::env::temp_dir().join("teres").join(name)
+}
+
+/// Starts accepting subscribers in the background, once per process
+pub fn listen() {
+    LISTEN.call_once(|| {
+        if let Err(error) = std::fs::create_dir_all(path().parent().unwrap()).and_then(|_| accept())
+        {
+            debug!("Could not open the progress socket: {}", error);
+        }
+    });
+}
+
+#[cfg(unix)]
+fn accept() -> Result<(), std::io::Error> {
+    let _ = std::fs::remove_file(path());
+    let listener = std::os::unix::net::UnixListener::bind(path())?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_ok() {
+                SUBSCRIBERS.lock().unwrap().push(Box::new(stream));
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(windows)]
+fn accept() -> Result<(), std::io::Error> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    std::fs::write(path(), listener.local_addr()?.port().to_string())?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_ok() {
+                SUBSCRIBERS.lock().unwrap().push(Box::new(stream));
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Sends an event as a json line to every subscriber, dropping the ones that fail or don't keep
+/// up
+pub fn emit(event: Value) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    if subscribers.is_empty() {
+        return;
+    }
+    let line = event.to_string() + "\n";
+    subscribers.retain_mut(|subscriber| {
+        subscriber.write_all(line.as_bytes()).is_ok() && subscriber.flush().is_ok()
+    });
+}
+
+pub fn close() {
+    if LISTEN.is_completed() {
+        let _ = std::fs::remove_file(path());
+    }
+}
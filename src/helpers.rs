@@ -1,12 +1,21 @@
-use crate::rendering::{CommandWithArgs, Render};
+use crate::config::Config;
+use crate::control;
+use crate::events;
+use crate::logging;
+use crate::rendering::{is_animated, CommandWithArgs, Render};
 use indicatif::ProgressBar;
 use is_terminal::IsTerminal;
-use log::debug;
+use log::{debug, Level};
 use regex::Regex;
+use serde_json::json;
+use std::collections::HashMap;
 use std::io::{self, BufReader};
 use std::io::{prelude::*, ErrorKind};
 use std::path::{Path, PathBuf};
-use std::process::{ChildStderr, Command, ExitStatus, Stdio};
+use std::process::{ChildStderr, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 pub fn change_file_name(path: impl AsRef<Path>, name: &str) -> PathBuf {
     let path = path.as_ref();
@@ -18,8 +27,32 @@ pub fn change_file_name(path: impl AsRef<Path>, name: &str) -> PathBuf {
     result
 }
 
-pub fn clean(video: PathBuf, script_path: PathBuf) {
+/// Fills in the placeholders of an output filename template
+pub fn format_filename(template: &str, name: &str, settings: &Config) -> String {
+    template
+        .replace("{name}", name)
+        .replace("{fps}", &settings.interpolation.fps.to_string())
+        .replace("{program}", &settings.advanced.interpolation.program)
+        .replace("{output_fps}", &settings.blending.output_fps.to_string())
+        .replace(
+            "{amount}",
+            &settings.blending.effective_amount().to_string(),
+        )
+        .replace("{weighting}", &settings.blending.weighting)
+        .replace("{quality}", &settings.encoding.quality.to_string())
+}
+
+pub fn clean(index_path: Option<PathBuf>, script_path: PathBuf) {
     debug!("Cleaning temp files at: {}", script_path.display());
+    if let Some(index_path) = index_path {
+        match std::fs::remove_file(index_path) {
+            Ok(()) => (),
+            Err(error) => match error.kind() {
+                ErrorKind::NotFound => (),
+                other_error => panic!("Problem deleting the file: {:?}", other_error),
+            },
+        }
+    }
     if script_path.parent().unwrap().read_dir().unwrap().count() <= 1 {
         trace!("Removed temp dir and file");
         std::fs::remove_dir_all(script_path.parent().unwrap()).unwrap();
@@ -27,52 +60,307 @@ pub fn clean(video: PathBuf, script_path: PathBuf) {
         trace!("Removed temp file");
         std::fs::remove_file(script_path).unwrap();
     }
-    let ffindex = std::fs::remove_file(
-        video
-            .parent()
-            .unwrap()
-            .join(video.file_name().unwrap().to_str().unwrap().to_owned() + ".ffindex"),
-    );
-    match ffindex {
-        Ok(()) => (),
-        Err(error) => match error.kind() {
-            ErrorKind::NotFound => (),
-            other_error => panic!("Problem deleting the file: {:?}", other_error),
-        },
-    }
 }
 
 pub fn clean_temp(videos: Vec<Render>) {
     for video in videos {
-        clean(video.video_path, video.script_path);
+        video.clean();
+    }
+}
+
+/// Checks whether files can be created in `dir` by writing and removing a probe file
+pub fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".teres_probe_{}", rand::random::<u16>()));
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Checks whether the file is a cloud storage placeholder (OneDrive, Google Drive, etc.)
+/// whose contents have not been downloaded locally yet
+#[cfg(target_os = "windows")]
+pub fn is_cloud_placeholder(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_OFFLINE: u32 = 0x1000;
+    const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x40000;
+    const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x400000;
+
+    match std::fs::metadata(path) {
+        Ok(metadata) => {
+            metadata.file_attributes()
+                & (FILE_ATTRIBUTE_OFFLINE
+                    | FILE_ATTRIBUTE_RECALL_ON_OPEN
+                    | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS)
+                != 0
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn is_cloud_placeholder(_path: &Path) -> bool {
+    false
+}
+
+/// Splits on whitespace like a shell, keeping quoted parts together. `None` when a quote isn't
+/// closed
+pub fn split_args(line: &str) -> Option<Vec<String>> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut quote = None;
+    let mut in_arg = false;
+    for character in line.chars() {
+        match (quote, character) {
+            (Some(open), _) if character == open => quote = None,
+            (Some(_), _) => current.push(character),
+            (None, '"' | '\'') => {
+                quote = Some(character);
+                in_arg = true;
+            }
+            (None, _) if character.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (None, _) => {
+                current.push(character);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return None;
+    }
+    if in_arg {
+        args.push(current);
     }
+    Some(args)
+}
+
+/// 64-bit FNV-1a, which unlike DefaultHasher stays the same between builds and rust versions,
+/// for names of files kept between runs
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Reads the whole file once so that cloud storage providers download it before indexing
+pub fn hydrate(path: &Path) -> Result<(), std::io::Error> {
+    let mut file = BufReader::new(std::fs::File::open(path)?);
+    let mut buffer = vec![0; 1024 * 1024];
+    while file.read(&mut buffer)? > 0 {}
+    Ok(())
 }
 
 pub fn exec(ffmpeg_settings: CommandWithArgs, pb: ProgressBar) -> ExitStatus {
-    let vspipe = Command::new(ffmpeg_settings.vspipe_exe)
+    // ffmpeg reports its progress as key=value lines on stdout, which works whatever the
+    // terminal and also gives the encoding speed
+    let mut ffmpeg_args = vec![String::from("-progress"), String::from("pipe:1")];
+    ffmpeg_args.extend(ffmpeg_settings.ffmpeg_args);
+    // gif and webp outputs have fewer frames than the script, so those follow vspipe instead
+    let count_output = !is_animated(Path::new(&ffmpeg_settings.output_filename));
+
+    if ffmpeg_settings.vspipe_args.is_empty() {
+        // ffmpeg only (remuxing), there are no vapoursynth frames to know the length from
+        let mut ffmpeg = Command::new(ffmpeg_settings.ffmpeg_exe)
+            .args(ffmpeg_args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Failed to start ffmpeg process");
+        control::register(&[ffmpeg.id()]);
+        control::set_priority(
+            &[ffmpeg.id()],
+            &ffmpeg_settings.process_priority,
+            &ffmpeg_settings.cpu_affinity,
+        );
+        encode_progress(
+            ffmpeg.stdout.take().unwrap(),
+            &pb,
+            &AtomicU64::new(0),
+            false,
+        );
+        let status = ffmpeg.wait().expect("Failed to wait on ffmpeg process");
+        control::unregister(&[ffmpeg.id()]);
+        pb.finish();
+        return status;
+    }
+
+    let mut vspipe = Command::new(ffmpeg_settings.vspipe_exe)
         .args(ffmpeg_settings.vspipe_args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
         .expect("Failed to start vspipe process");
+    let vspipe_id = vspipe.id();
+    let vspipe_stdout = vspipe.stdout.take().expect("Failed to open vspipe stdout");
 
-    let ffmpeg = Command::new(ffmpeg_settings.ffmpeg_exe)
-        .args(ffmpeg_settings.ffmpeg_args)
-        .stdin(Stdio::from(
-            vspipe.stdout.expect("Failed to open vspipe stdout"),
-        ))
-        .spawn()
-        .expect("Failed to start ffmpeg process");
+    // with -v the frames go through teres, timing which side of the pipe is waited on
+    let timed = logging::shows(Level::Debug);
+    let mut ffmpeg_command = Command::new(ffmpeg_settings.ffmpeg_exe);
+    ffmpeg_command.args(ffmpeg_args).stdout(Stdio::piped());
+    let (mut ffmpeg, relay) = if timed {
+        let mut ffmpeg = ffmpeg_command
+            .stdin(Stdio::piped())
+            .spawn()
+            .expect("Failed to start ffmpeg process");
+        let ffmpeg_stdin = ffmpeg.stdin.take().expect("Failed to open ffmpeg stdin");
+        let relay = std::thread::spawn(move || relay(vspipe_stdout, ffmpeg_stdin));
+        (ffmpeg, Some(relay))
+    } else {
+        let ffmpeg = ffmpeg_command
+            .stdin(Stdio::from(vspipe_stdout))
+            .spawn()
+            .expect("Failed to start ffmpeg process");
+        (ffmpeg, None)
+    };
 
     debug!("Spawned subprocesses");
+    control::register(&[vspipe_id, ffmpeg.id()]);
+    control::set_priority(
+        &[vspipe_id, ffmpeg.id()],
+        &ffmpeg_settings.process_priority,
+        &ffmpeg_settings.cpu_affinity,
+    );
+
+    let vspipe_stderr = vspipe.stderr.unwrap();
+    let (frames_bar, total) = (pb.clone(), Arc::new(AtomicU64::new(0)));
+    let frames_total = total.clone();
+    let reader =
+        std::thread::spawn(move || frames(vspipe_stderr, frames_bar, &frames_total, !count_output));
+    encode_progress(ffmpeg.stdout.take().unwrap(), &pb, &total, count_output);
+    let _ = reader.join();
+
+    let ffmpeg_id = ffmpeg.id();
+    let status = ffmpeg.wait().expect("Failed to wait on ffmpeg process");
+    control::unregister(&[vspipe_id, ffmpeg_id]);
+    if let Some(Ok(timings)) = relay.map(|relay| relay.join()) {
+        debug!(
+            "Took {:.1?} loading and indexing, {:.1?} waiting on vapoursynth (interpolating and blending) and {:.1?} waiting on ffmpeg (encoding)",
+            timings.indexing,
+            timings.vapoursynth,
+            timings.ffmpeg + timings.finished.elapsed()
+        );
+    }
+    status
+}
+
+/// Time a render spent in each stage, from the pipe between vspipe and ffmpeg
+struct Timings {
+    /// Until vspipe's first output, when the script is loaded and the source indexed
+    indexing: Duration,
+    /// Waiting for frames from vspipe
+    vapoursynth: Duration,
+    /// Waiting for ffmpeg to take the frames
+    ffmpeg: Duration,
+    /// When vspipe finished, ffmpeg's encoding of what is left is counted from it
+    finished: Instant,
+}
+
+/// Copies vspipe's output to ffmpeg, adding up how long each of them was waited on. ffmpeg's
+/// input is closed at the end so it finishes
+fn relay(mut vspipe: ChildStdout, mut ffmpeg: ChildStdin) -> Timings {
+    let start = Instant::now();
+    let mut timings = Timings {
+        indexing: Duration::ZERO,
+        vapoursynth: Duration::ZERO,
+        ffmpeg: Duration::ZERO,
+        finished: start,
+    };
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        let read_start = Instant::now();
+        let read = match vspipe.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        if timings.indexing.is_zero() {
+            timings.indexing = start.elapsed();
+        } else {
+            timings.vapoursynth += read_start.elapsed();
+        }
+        let write_start = Instant::now();
+        // ffmpeg exited, e.g. on an error it reports itself
+        if ffmpeg.write_all(&buffer[..read]).is_err() {
+            break;
+        }
+        timings.ffmpeg += write_start.elapsed();
+    }
+    timings.finished = Instant::now();
+    timings
+}
+
+/// Title of the console before teres changed it, restored on exit
+#[cfg(windows)]
+static ORIGINAL_TITLE: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+pub fn set_title(title: &str) {
+    #[cfg(windows)]
+    {
+        ORIGINAL_TITLE.get_or_init(|| winconsole::console::get_title().unwrap_or_default());
+        let _ = winconsole::console::set_title(title);
+    }
+    #[cfg(unix)]
+    if io::stderr().is_terminal() {
+        eprint!("\x1b]0;{}\x07", title);
+    }
+}
+
+pub fn restore_title() {
+    #[cfg(windows)]
+    if let Some(title) = ORIGINAL_TITLE.get() {
+        let _ = winconsole::console::set_title(title);
+    }
+    #[cfg(unix)]
+    if io::stderr().is_terminal() {
+        // terminals can't report their title, so clear it back to the shell's
+        eprint!("\x1b]0;\x07");
+    }
+}
 
-    progress(vspipe.stderr.unwrap(), pb);
+/// `advanced.pause_on_exit` of the loaded config, `auto` until it is loaded
+static PAUSE_ON_EXIT: Mutex<String> = Mutex::new(String::new());
 
-    ffmpeg.wait_with_output().unwrap().status
+pub fn set_pause_on_exit(value: &str) {
+    *PAUSE_ON_EXIT.lock().unwrap() = value.to_lowercase();
+}
+
+/// Whether to wait for enter before closing, so the output stays readable when closing also
+/// closes the window. Scripts and CI never get the prompt since there is no terminal to answer it
+fn should_pause() -> bool {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return false;
+    }
+    match PAUSE_ON_EXIT.lock().unwrap().as_str() {
+        "always" => true,
+        "never" => false,
+        _ => own_console(),
+    }
+}
+
+/// Whether teres is the only program in its console, which means windows opened the console for
+/// it: teres was double-clicked or had videos dropped on it instead of running from a shell
+#[cfg(windows)]
+fn own_console() -> bool {
+    let mut processes = [0u32; 2];
+    unsafe { winapi::um::wincon::GetConsoleProcessList(processes.as_mut_ptr(), 2) == 1 }
+}
+
+/// Terminals on linux and macos stay open after teres exits
+#[cfg(not(windows))]
+fn own_console() -> bool {
+    false
 }
 
 pub fn exit(status_code: i32) {
-    if std::io::stdin().is_terminal() {
+    restore_title();
+    events::close();
+    if should_pause() {
         eprintln!();
         let mut stdout = io::stderr();
 
@@ -82,32 +370,141 @@ pub fn exit(status_code: i32) {
 
         // Read a single byte and discard
         let mut stdin = io::stdin(); // We get `Stdin` here.
-        stdin.read_exact(&mut [0]).unwrap(); // read_line returns the number of bytes read, so we can ignore it.
+        let _ = stdin.read_exact(&mut [0]); // closing the input instead of pressing enter also continues
     }
     std::process::exit(status_code);
 }
 
-fn progress(stderr: ChildStderr, progress: ProgressBar) {
-    let mut read_frames = false;
+/// Reads the frame count of the script from vspipe's progress into `total`, following its frames
+/// too when ffmpeg's can't be used. The rest of the stderr is read so vspipe never blocks on it
+fn frames(stderr: ChildStderr, progress: ProgressBar, total: &AtomicU64, follow: bool) {
     let frame_regex = Regex::new(r"Frame: (?P<current>\d+)/(?P<total>\d+)").unwrap();
-    let output_regex = Regex::new(r"Output").unwrap();
     let mut buf = BufReader::new(stderr);
+    let mut read_frames = false;
 
     loop {
         let mut byte_vec = vec![];
-        buf.read_until(b'\r', &mut byte_vec).expect("stderr Error");
-        let string = String::from_utf8_lossy(&byte_vec);
-        if output_regex.is_match(&string) {
+        // vspipe exited, e.g. on a script error
+        if buf.read_until(b'\r', &mut byte_vec).unwrap_or(0) == 0 {
             break;
         }
-        let caps;
-        if frame_regex.is_match(&string) {
-            caps = frame_regex.captures(&string).unwrap();
+        let string = String::from_utf8_lossy(&byte_vec);
+        if let Some(caps) = frame_regex.captures(&string) {
             if !read_frames {
-                progress.set_length(caps["total"].parse::<u64>().unwrap());
+                let frames = caps["total"].parse::<u64>().unwrap();
+                progress.set_length(frames);
+                total.store(frames, Ordering::Relaxed);
                 read_frames = true
             }
-            progress.set_position(caps["current"].parse::<u64>().unwrap())
+            if follow {
+                progress.set_position(caps["current"].parse::<u64>().unwrap());
+            }
+        }
+    }
+}
+
+/// Follows ffmpeg's `-progress` output, moving the bar with the encoded frames when
+/// `count_output` is set and showing the encoding speed and bitrate next to it. Percentages are
+/// only known once vspipe has reported the `total` frames
+fn encode_progress(
+    stdout: ChildStdout,
+    progress: &ProgressBar,
+    total: &AtomicU64,
+    count_output: bool,
+) {
+    let mut last_percent = None;
+    let mut values: HashMap<String, String> = HashMap::new();
+
+    for line in BufReader::new(stdout).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        if key != "progress" {
+            values.insert(key.to_string(), value.to_string());
+            continue;
+        }
+        // a block of values ends with progress=continue or progress=end
+        let frame = values
+            .get("frame")
+            .and_then(|frame| frame.parse::<u64>().ok());
+        let fps = values
+            .get("fps")
+            .and_then(|fps| fps.parse::<f64>().ok())
+            .unwrap_or(0.0);
+        let bitrate = values
+            .get("bitrate")
+            .filter(|bitrate| bitrate.as_str() != "N/A")
+            .cloned()
+            .unwrap_or_default();
+        progress.set_prefix(format!("{:.0} fps {}", fps, bitrate));
+        // the percentage needs the length of the script from vspipe
+        let total = total.load(Ordering::Relaxed);
+        if total > 0 {
+            if let (true, Some(frame)) = (count_output, frame) {
+                progress.set_position(frame.min(total));
+            }
+            let percent = progress.position() * 100 / total;
+            if last_percent != Some(percent) {
+                last_percent = Some(percent);
+                events::emit(json!({
+                    "event": "progress",
+                    "input": progress.message(),
+                    "frame": progress.position(),
+                    "frames": total,
+                    "percent": percent,
+                    "eta": progress.eta().as_secs(),
+                    "fps": fps,
+                    "bitrate": bitrate,
+                }));
+                set_title(&format!(
+                    "Teres - {} {}% ({} left)",
+                    progress.message(),
+                    percent,
+                    indicatif::HumanDuration(progress.eta())
+                ));
+            }
         }
+
+        if value == "end" {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_args_keeps_quoted_parts_together() {
+        assert_eq!(
+            split_args(r#"-x264-params "keyint=60:min-keyint=60" -tune film"#).unwrap(),
+            ["-x264-params", "keyint=60:min-keyint=60", "-tune", "film"]
+        );
+        assert_eq!(
+            split_args("-metadata title='my clip'").unwrap(),
+            ["-metadata", "title=my clip"]
+        );
+    }
+
+    #[test]
+    fn split_args_skips_extra_whitespace() {
+        assert_eq!(split_args("  -an \t -sn ").unwrap(), ["-an", "-sn"]);
+        assert!(split_args("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn split_args_keeps_empty_quotes() {
+        assert_eq!(split_args("-metadata ''").unwrap(), ["-metadata", ""]);
+    }
+
+    #[test]
+    fn split_args_refuses_unclosed_quotes() {
+        assert_eq!(split_args(r#"-vf "scale=1280:-2"#), None);
     }
 }
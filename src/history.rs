@@ -0,0 +1,215 @@
+use crate::config::Config;
+use crate::metrics::Metrics;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A processed render, appended to the history file as a json line
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Job {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub status: String,
+    /// Unix timestamp of when the render started
+    pub started: u64,
+    /// Seconds the render took
+    pub elapsed: f64,
+    /// Length of the input video in seconds
+    pub video_duration: Option<f64>,
+    pub output_size: Option<u64>,
+    /// Input frames processed per second of rendering
+    pub average_fps: Option<f64>,
+    /// Quality scores from output.metrics
+    #[serde(default)]
+    pub metrics: Metrics,
+    pub settings: Config,
+}
+
+impl Job {
+    pub fn new(
+        input: PathBuf,
+        output: PathBuf,
+        status: &str,
+        elapsed: Duration,
+        video_duration: Option<f64>,
+        input_fps: Option<f64>,
+        settings: Config,
+    ) -> Job {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let output_size = std::fs::metadata(&output).map(|meta| meta.len()).ok();
+        let average_fps = match (video_duration, input_fps) {
+            (Some(duration), Some(fps)) if elapsed.as_secs_f64() > 0.0 => {
+                Some(duration * fps / elapsed.as_secs_f64())
+            }
+            _ => None,
+        };
+        Job {
+            input,
+            output,
+            status: status.to_string(),
+            started: now.saturating_sub(elapsed).as_secs(),
+            elapsed: elapsed.as_secs_f64(),
+            video_duration,
+            output_size,
+            average_fps,
+            metrics: Metrics::default(),
+            settings: settings.shareable(),
+        }
+    }
+}
+
+pub fn path() -> PathBuf {
+    Config::global_path().with_file_name("history.jsonl")
+}
+
+pub fn record(job: &Job) {
+    let result = serde_json::to_string(job)
+        .map_err(std::io::Error::from)
+        .and_then(|line| {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path())?;
+            writeln!(file, "{}", line)
+        });
+    if let Err(error) = result {
+        warn!("Could not record render in history: {}", error);
+    }
+}
+
+pub fn load() -> Vec<Job> {
+    let contents = std::fs::read_to_string(path()).unwrap_or_default();
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(job) => Some(job),
+            Err(error) => {
+                debug!("Skipping unreadable history entry: {}", error);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Writes the renders of this run to a json or csv file, picked by the file extension
+pub fn write_report(jobs: &[Job], file: &Path) {
+    let contents = if file.extension() == Some(OsStr::new("csv")) {
+        to_csv(jobs)
+    } else {
+        serde_json::to_string_pretty(jobs).unwrap_or_default()
+    };
+    match std::fs::write(file, contents) {
+        Ok(()) => info!("Wrote report to {}", file.display()),
+        Err(error) => error!("Could not write report {}: {}", file.display(), error),
+    }
+}
+
+/// Writes the history as csv or json lines to a file or stdout
+pub fn export(csv: bool, output: Option<&Path>) {
+    let jobs = load();
+    let contents = if csv {
+        to_csv(&jobs)
+    } else {
+        jobs.iter()
+            .filter_map(|job| serde_json::to_string(job).ok())
+            .map(|line| line + "\n")
+            .collect()
+    };
+
+    match output {
+        Some(file) => match std::fs::write(file, contents) {
+            Ok(()) => info!("Exported {} render(s) to {}", jobs.len(), file.display()),
+            Err(error) => error!("Could not write {}: {}", file.display(), error),
+        },
+        None => print!("{}", contents),
+    }
+}
+
+fn to_csv(jobs: &[Job]) -> String {
+    let mut csv = String::from(
+        "started,input,output,status,video_duration,time_taken,average_fps,output_size,interpolation_fps,interpolation_program,output_fps,blur_amount,weighting,quality,vmaf,ssim,psnr\n",
+    );
+    for job in jobs {
+        let fields = [
+            format_timestamp(job.started),
+            job.input.display().to_string(),
+            job.output.display().to_string(),
+            job.status.clone(),
+            job.video_duration
+                .map(|duration| format!("{:.2}", duration))
+                .unwrap_or_default(),
+            format!("{:.2}", job.elapsed),
+            job.average_fps
+                .map(|fps| format!("{:.2}", fps))
+                .unwrap_or_default(),
+            job.output_size
+                .map(|size| size.to_string())
+                .unwrap_or_default(),
+            job.settings.interpolation.fps.to_string(),
+            job.settings.advanced.interpolation.program.clone(),
+            job.settings.blending.output_fps.to_string(),
+            job.settings.blending.effective_amount().to_string(),
+            job.settings.blending.weighting.clone(),
+            job.settings.encoding.quality.to_string(),
+            job.metrics
+                .vmaf
+                .map(|vmaf| format!("{:.2}", vmaf))
+                .unwrap_or_default(),
+            job.metrics
+                .ssim
+                .map(|ssim| format!("{:.4}", ssim))
+                .unwrap_or_default(),
+            job.metrics
+                .psnr
+                .map(|psnr| format!("{:.2}", psnr))
+                .unwrap_or_default(),
+        ];
+        csv += &fields
+            .iter()
+            .map(|field| escape_csv(field))
+            .collect::<Vec<String>>()
+            .join(",");
+        csv += "\n";
+    }
+    csv
+}
+
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Formats a unix timestamp as `YYYY-MM-DD HH:MM:SS` (UTC) which spreadsheets recognise
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // civil from days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
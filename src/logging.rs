@@ -0,0 +1,159 @@
+use crate::history::format_timestamp;
+use env_logger::fmt::Color;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Prints records at the level chosen with -v/-q to the terminal and writes everything teres logs
+/// to a file in the temp dir so failed renders can be debugged after the fact
+struct Logger {
+    terminal: env_logger::Logger,
+    file: Option<Mutex<File>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.terminal.enabled(metadata) || is_own(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.terminal.matches(record) {
+            self.terminal.log(record);
+        }
+        if let (Some(file), true) = (&self.file, is_own(record.metadata())) {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default();
+            let _ = writeln!(
+                file.lock().unwrap(),
+                "{} {:5} {}: {}",
+                format_timestamp(timestamp),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        self.terminal.flush();
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+fn is_own(metadata: &Metadata) -> bool {
+    metadata.target().starts_with("teres")
+}
+
+/// Debug log of this run, named after the process id so parallel runs don't mix
+pub fn path() -> PathBuf {
+    std::env::temp_dir()
+        .join("teres")
+        .join(format!("teres-{}.log", std::process::id()))
+}
+
+/// Removes the logs of runs from over a week ago
+fn prune(folder: &Path) {
+    let week = std::time::Duration::from_secs(7 * 24 * 60 * 60);
+    for entry in std::fs::read_dir(folder).into_iter().flatten().flatten() {
+        let path = entry.path();
+        let old = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .map(|modified| modified.elapsed().unwrap_or_default() > week)
+            .unwrap_or(false);
+        if old && path.extension() == Some(std::ffi::OsStr::new("log")) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Level chosen with -v/-q, the file gets everything whatever it is
+static TERMINAL_LEVEL: OnceLock<LevelFilter> = OnceLock::new();
+
+/// Whether records at `level` are shown in the terminal
+pub fn shows(level: Level) -> bool {
+    TERMINAL_LEVEL
+        .get()
+        .is_some_and(|terminal_level| level <= *terminal_level)
+}
+
+pub fn init(level: LevelFilter) {
+    let _ = TERMINAL_LEVEL.set(level);
+    let terminal = env_logger::Builder::new()
+        .filter_level(level)
+        .format(|buf, record| {
+            let level = record.level();
+            match level {
+                Level::Info => writeln!(buf, "{}", record.args()),
+                Level::Error | Level::Warn => {
+                    let mut style = buf.style();
+                    style
+                        .set_color(if level == Level::Error {
+                            Color::Red
+                        } else {
+                            Color::Yellow
+                        })
+                        .set_bold(true);
+                    writeln!(
+                        buf,
+                        "{}: {}",
+                        style.value(level.as_str().to_lowercase()),
+                        record.args()
+                    )
+                }
+                Level::Debug | Level::Trace => {
+                    let mut style = buf.style();
+                    style.set_dimmed(true);
+                    writeln!(
+                        buf,
+                        "{}",
+                        style.value(format!(
+                            "[{} {}] {}",
+                            level.as_str().to_lowercase(),
+                            record.target(),
+                            record.args()
+                        ))
+                    )
+                }
+            }
+        })
+        .build();
+
+    let path = path();
+    prune(path.parent().unwrap());
+    let file = std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| File::create(&path))
+        .ok()
+        .map(Mutex::new);
+
+    log::set_max_level(if file.is_some() {
+        LevelFilter::Trace
+    } else {
+        level
+    });
+    log::set_boxed_logger(Box::new(Logger { terminal, file })).expect("Logger already set");
+    debug!(
+        "teres {} started with {}",
+        env!("CARGO_PKG_VERSION"),
+        std::env::args().collect::<Vec<_>>().join(" ")
+    );
+
+    // point panic reports at the log so it gets attached along with them
+    let report_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        error!("{}", info);
+        log::logger().flush();
+        report_hook(info);
+        eprintln!(
+            "Please attach the debug log at {} to the report as well",
+            path.display()
+        );
+    }));
+}
@@ -1,10 +1,45 @@
 use clap::Parser;
+mod binaries;
+mod bootstrap;
+mod chunks;
 mod cli;
 mod config;
+mod container;
+mod control;
+mod disk;
+mod distributed;
+mod doctor;
+mod events;
 mod helpers;
+mod history;
+mod logging;
+mod matrix;
+mod metrics;
+mod monitor;
+mod notifications;
+mod paths;
+mod presets;
+mod probe;
+mod queue;
+mod rename;
 mod rendering;
+mod reproduce;
+mod reveal;
+mod schedule;
 mod script_handler;
+mod selftest;
+mod sequence;
+mod server;
+mod service;
+mod sidecar;
+mod target_size;
 mod teres;
+mod thumbnail;
+mod update;
+mod upload;
+mod watch;
+mod when_done;
+mod wizard;
 use human_panic::setup_panic;
 
 #[macro_use]
@@ -14,10 +49,8 @@ extern crate log;
 fn main() {
     setup_panic!();
     let args = cli::Cli::parse_from(wild::args());
-    env_logger::Builder::new()
-        .filter_level(args.verbose.log_level_filter())
-        .init();
-    winconsole::console::set_title("Teres").unwrap();
+    logging::init(args.verbose.log_level_filter());
+    helpers::set_title("Teres");
 
     teres::run(args);
 
@@ -28,9 +61,7 @@ fn main() {
 fn main() {
     setup_panic!();
     let args = cli::Cli::parse_from(wild::args());
-    env_logger::Builder::new()
-        .filter_level(args.verbose.log_level_filter())
-        .init();
+    logging::init(args.verbose.log_level_filter());
 
     teres::run(args);
 
@@ -0,0 +1,54 @@
+/// Short names accepted by --matrix for the settings most worth exploring
+const ALIASES: &[(&str, &str)] = &[
+    ("blur", "blending.amount"),
+    ("fps", "blending.output_fps"),
+    ("weighting", "blending.weighting"),
+    ("interpolated_fps", "interpolation.fps"),
+];
+
+/// One combination of matrix values, as config overrides and the suffix added to its filename
+pub struct Variant {
+    pub overrides: Vec<String>,
+    pub suffix: String,
+}
+
+/// Expands `key=a,b,c` arguments into every combination of their values, returning the
+/// malformed argument on error
+pub fn variants(args: &[String]) -> Result<Vec<Variant>, String> {
+    let mut variants = vec![Variant {
+        overrides: vec![],
+        suffix: String::new(),
+    }];
+    for arg in args {
+        let (key, values) = arg.split_once('=').ok_or_else(|| arg.clone())?;
+        let key = key.trim();
+        let values: Vec<&str> = values
+            .split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .collect();
+        if key.is_empty() || values.is_empty() {
+            return Err(arg.clone());
+        }
+        let path = ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == key)
+            .map_or(key, |(_, path)| path);
+        let name = key.rsplit('.').next().unwrap_or(key);
+
+        variants = variants
+            .iter()
+            .flat_map(|variant| {
+                values.iter().map(move |value| {
+                    let mut overrides = variant.overrides.clone();
+                    overrides.push(format!("{}={}", path, value));
+                    Variant {
+                        overrides,
+                        suffix: format!("{}_{}-{}", variant.suffix, name, value),
+                    }
+                })
+            })
+            .collect();
+    }
+    Ok(variants)
+}
@@ -0,0 +1,151 @@
+use crate::config::Config;
+use crate::control;
+use crate::probe;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Command;
+
+/// Scores of the output against its input, from `output.metrics`
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Metrics {
+    /// 0 to 100
+    pub vmaf: Option<f64>,
+    /// 0 to 1
+    pub ssim: Option<f64>,
+    /// Decibels
+    pub psnr: Option<f64>,
+}
+
+impl std::fmt::Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let scores: Vec<String> = [
+            self.vmaf.map(|vmaf| format!("VMAF {:.2}", vmaf)),
+            self.ssim.map(|ssim| format!("SSIM {:.4}", ssim)),
+            self.psnr.map(|psnr| format!("PSNR {:.2} dB", psnr)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        write!(f, "{}", scores.join(", "))
+    }
+}
+
+/// Compares the output to the input retimed by the timescale and scaled to the size and frame
+/// rate of the output, with ffmpeg's libvmaf, ssim and psnr filters
+pub fn measure(
+    ffmpeg: &str,
+    input: &Path,
+    output: &Path,
+    settings: &Config,
+) -> Result<Metrics, String> {
+    let info = probe::probe(output).ok_or_else(|| String::from("the output could not be read"))?;
+    let fps = info
+        .fps
+        .map(|(num, den)| format!("{}/{}", num, den))
+        .ok_or_else(|| String::from("the output has no frame rate"))?;
+    let timescale = &settings.timescale;
+    if !(timescale.input > 0.0 && timescale.output > 0.0) {
+        return Err(format!(
+            "the timescale {}/{} can't retime the input",
+            timescale.input, timescale.output
+        ));
+    }
+    let metrics: Vec<String> = settings
+        .output
+        .metrics
+        .iter()
+        .map(|metric| metric.to_lowercase())
+        .collect();
+    let filters: Vec<&str> = metrics
+        .iter()
+        .map(|metric| {
+            if metric == "vmaf" {
+                "libvmaf"
+            } else {
+                metric.as_str()
+            }
+        })
+        .collect();
+
+    // each filter takes the output first and the reference second
+    let count = filters.len();
+    let mut graph = format!(
+        "[0:v]format=yuv420p,settb=AVTB,setpts=PTS-STARTPTS,split={}{};",
+        count,
+        labels("main", count)
+    );
+    graph += &format!(
+        "[1:v]setpts=PTS*{},fps={},scale={}:{}:flags=bicubic,format=yuv420p,settb=AVTB,setpts=PTS-STARTPTS,split={}{}",
+        timescale.input / timescale.output,
+        fps,
+        info.width,
+        info.height,
+        count,
+        labels("reference", count)
+    );
+    for (index, filter) in filters.iter().enumerate() {
+        graph += &format!(";[main{0}][reference{0}]{1}", index, filter);
+    }
+
+    let result = control::output(
+        Command::new(ffmpeg)
+            .args(["-hide_banner", "-nostats", "-i"])
+            .arg(output)
+            .arg("-i")
+            .arg(input)
+            .args(["-filter_complex", graph.as_str(), "-f", "null", "-"]),
+    )
+    .map_err(|error| error.to_string())?;
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    if !result.status.success() {
+        return Err(stderr.lines().last().unwrap_or_default().trim().to_string());
+    }
+    Ok(parse(&stderr))
+}
+
+fn labels(name: &str, count: usize) -> String {
+    (0..count)
+        .map(|index| format!("[{}{}]", name, index))
+        .collect()
+}
+
+/// Reads the averages the filters log when they finish
+fn parse(log: &str) -> Metrics {
+    let score = |pattern: &str| {
+        Regex::new(pattern)
+            .unwrap()
+            .captures(log)
+            .and_then(|captures| captures[1].parse().ok())
+    };
+    Metrics {
+        vmaf: score(r"VMAF score: ([\d.]+)"),
+        ssim: score(r"Parsed_ssim.* All:([\d.]+)"),
+        psnr: score(r"Parsed_psnr.* average:([\d.]+|inf)"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_the_averages() {
+        let log = "[Parsed_libvmaf_0 @ 0x55d0c0] VMAF score: 95.123456\n\
+            [Parsed_ssim_1 @ 0x55d0c1] SSIM Y:0.987000 (18.86) U:0.990000 (20.00) V:0.991000 (20.46) All:0.988654 (19.41)\n\
+            [Parsed_psnr_2 @ 0x55d0c2] PSNR y:40.100000 u:44.200000 v:44.900000 average:41.234567 min:38.000000 max:45.000000\n";
+        let metrics = parse(log);
+        assert_eq!(metrics.vmaf, Some(95.123456));
+        assert_eq!(metrics.ssim, Some(0.988654));
+        assert_eq!(metrics.psnr, Some(41.234567));
+    }
+
+    #[test]
+    fn parse_reads_identical_videos() {
+        let log = "[Parsed_psnr_0 @ 0x55d0c0] PSNR y:inf u:inf v:inf average:inf min:inf max:inf\n";
+        let metrics = parse(log);
+        assert_eq!(metrics.psnr, Some(f64::INFINITY));
+        assert_eq!(metrics.vmaf, None);
+        assert_eq!(metrics.ssim, None);
+    }
+}
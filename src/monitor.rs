@@ -0,0 +1,151 @@
+use crate::config::Monitoring;
+use crate::control;
+use indicatif::ProgressBar;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use sysinfo::{Components, System};
+
+/// How often the load and temperatures are read
+const INTERVAL: Duration = Duration::from_secs(2);
+
+/// Samples the cpu and gpu while a render runs, showing their load and temperature next to the
+/// progress and stopping the render when one gets hotter than monitoring.max_temperature
+pub struct Monitor {
+    stop: Arc<AtomicBool>,
+    /// Why the render was stopped
+    overheated: Arc<Mutex<Option<String>>>,
+    thread: JoinHandle<()>,
+}
+
+impl Monitor {
+    pub fn start(settings: &Monitoring, progress: ProgressBar) -> Option<Monitor> {
+        if !settings.enabled && settings.max_temperature == 0 {
+            return None;
+        }
+        let (stop, overheated) = (Arc::new(AtomicBool::new(false)), Arc::new(Mutex::new(None)));
+        let (thread_stop, thread_overheated) = (stop.clone(), overheated.clone());
+        let (show, max_temperature) = (settings.enabled, settings.max_temperature as f32);
+        let thread = std::thread::spawn(move || {
+            let mut system = System::new();
+            let mut components = Components::new_with_refreshed_list();
+            // nvidia-smi isn't looked for again once it is missing
+            let mut has_gpu = true;
+            while !thread_stop.load(Ordering::Relaxed) {
+                system.refresh_cpu();
+                components.refresh();
+                let cpu = Reading {
+                    name: "cpu",
+                    usage: system.global_cpu_info().cpu_usage(),
+                    temperature: cpu_temperature(&components),
+                };
+                let gpu = if has_gpu { gpu() } else { None };
+                has_gpu = gpu.is_some();
+
+                let readings: Vec<&Reading> = std::iter::once(&cpu).chain(gpu.as_ref()).collect();
+                if show {
+                    progress.set_message(
+                        readings
+                            .iter()
+                            .map(|reading| reading.to_string())
+                            .collect::<Vec<String>>()
+                            .join(" "),
+                    );
+                }
+                let hot = readings.iter().find(|reading| {
+                    max_temperature > 0.0
+                        && reading
+                            .temperature
+                            .is_some_and(|temperature| temperature > max_temperature)
+                });
+                if let Some(hot) = hot {
+                    *thread_overheated.lock().unwrap() = Some(format!(
+                        "the {} reached {:.0}°C, above the {:.0}°C set in monitoring.max_temperature",
+                        hot.name,
+                        hot.temperature.unwrap_or_default(),
+                        max_temperature
+                    ));
+                    control::cancel();
+                    break;
+                }
+                std::thread::park_timeout(INTERVAL);
+            }
+        });
+        Some(Monitor {
+            stop,
+            overheated,
+            thread,
+        })
+    }
+
+    /// Stops sampling, returning why the render was stopped when it got too hot
+    pub fn finish(self) -> Option<String> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.thread().unpark();
+        let _ = self.thread.join();
+        self.overheated.lock().unwrap().take()
+    }
+}
+
+struct Reading {
+    name: &'static str,
+    /// Percent
+    usage: f32,
+    /// Degrees celsius, when there is a sensor for it
+    temperature: Option<f32>,
+}
+
+impl std::fmt::Display for Reading {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {:.0}%", self.name, self.usage)?;
+        if let Some(temperature) = self.temperature {
+            write!(f, " {:.0}°C", temperature)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hottest cpu sensor, named differently by every platform and vendor
+fn cpu_temperature(components: &Components) -> Option<f32> {
+    components
+        .iter()
+        .filter(|component| {
+            let label = component.label().to_lowercase();
+            ["cpu", "core", "package", "tctl", "tdie"]
+                .iter()
+                .any(|name| label.contains(name))
+        })
+        .map(|component| component.temperature())
+        .filter(|temperature| temperature.is_finite())
+        .reduce(f32::max)
+}
+
+/// Busiest and hottest of the nvidia gpus
+fn gpu() -> Option<Reading> {
+    let output = Command::new("nvidia-smi")
+        .args([
+            "--query-gpu=utilization.gpu,temperature.gpu",
+            "--format=csv,noheader,nounits",
+        ])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let mut reading: Option<Reading> = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let (usage, temperature) = line.split_once(',')?;
+        let (usage, temperature): (f32, f32) =
+            (usage.trim().parse().ok()?, temperature.trim().parse().ok()?);
+        let reading = reading.get_or_insert(Reading {
+            name: "gpu",
+            usage,
+            temperature: Some(temperature),
+        });
+        reading.usage = reading.usage.max(usage);
+        reading.temperature = reading.temperature.map(|hottest| hottest.max(temperature));
+    }
+    reading
+}
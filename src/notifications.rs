@@ -0,0 +1,82 @@
+use crate::config::Config;
+use crate::reveal;
+use notify_rust::Notification;
+use std::path::Path;
+use std::time::Duration;
+
+pub fn render_finished(settings: &Config, filename: &str, output: &str, elapsed: Duration) {
+    let message = format!(
+        "{} finished in {}",
+        filename,
+        indicatif::HumanDuration(elapsed)
+    );
+    desktop(settings, "Render finished", &message);
+    webhook(
+        settings,
+        &message,
+        filename,
+        "finished",
+        Some(output),
+        elapsed,
+    );
+    if settings.notifications.copy_path {
+        reveal::copy_path(Path::new(output));
+    }
+    if settings.notifications.open_folder {
+        reveal::open_folder(Path::new(output));
+    }
+}
+
+pub fn render_failed(settings: &Config, filename: &str, elapsed: Duration) {
+    let message = format!(
+        "{} failed after {}",
+        filename,
+        indicatif::HumanDuration(elapsed)
+    );
+    desktop(settings, "Render failed", &message);
+    webhook(settings, &message, filename, "failed", None, elapsed);
+}
+
+fn desktop(settings: &Config, summary: &str, body: &str) {
+    if !settings.notifications.desktop {
+        return;
+    }
+    if let Err(error) = Notification::new()
+        .appname("Teres")
+        .summary(summary)
+        .body(body)
+        .show()
+    {
+        debug!("Could not show desktop notification: {}", error);
+    }
+}
+
+/// Posts the render status to a Discord or Slack compatible webhook, which read the
+/// `content` and `text` fields respectively
+fn webhook(
+    settings: &Config,
+    message: &str,
+    filename: &str,
+    status: &str,
+    output: Option<&str>,
+    elapsed: Duration,
+) {
+    let url = match &settings.notifications.webhook_url {
+        Some(url) if !url.is_empty() => url,
+        _ => return,
+    };
+    let body = serde_json::json!({
+        "content": message,
+        "text": message,
+        "file": filename,
+        "status": status,
+        "duration": elapsed.as_secs_f64(),
+        "output": output,
+    });
+    if let Err(error) = ureq::post(url)
+        .timeout(Duration::from_secs(10))
+        .send_json(body)
+    {
+        warn!("Could not send webhook notification: {}", error);
+    }
+}
@@ -0,0 +1,260 @@
+use std::path::{Path, PathBuf};
+
+/// Turns an input as it was given or dropped onto teres into the absolute path of the video:
+/// `file://` urls and windows `.url` internet shortcuts become their path, `.lnk` shortcuts
+/// their target, and verbatim `\\?\` paths the plain form other programs take
+pub fn resolve(path: &Path) -> Result<PathBuf, String> {
+    let path = if let Some(url) = path.to_str().filter(|path| is_url(path)) {
+        from_url(url)?
+    } else if has_extension(path, "url") {
+        from_url(&internet_shortcut(path)?)?
+    } else if has_extension(path, "lnk") {
+        shortcut_target(path)?
+    } else {
+        path.to_path_buf()
+    };
+    let absolute = std::path::absolute(&path).map_err(|error| error.to_string())?;
+    Ok(without_verbatim(&absolute))
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+fn is_url(path: &str) -> bool {
+    path.split_once("://")
+        .is_some_and(|(scheme, _)| scheme.len() > 1 && scheme.chars().all(char::is_alphabetic))
+}
+
+/// Path of a `file://` url, which browsers and some file managers drop instead of the path
+fn from_url(url: &str) -> Result<PathBuf, String> {
+    let Some(rest) = url.strip_prefix("file://") else {
+        return Err(format!(
+            "it links to {}, which isn't a file on this computer, download it first",
+            url
+        ));
+    };
+    let decoded = percent_decode(rest);
+    // file:///C:/clip.mp4 on windows and file://server/share/clip.mp4 for network shares
+    let path = match decoded.strip_prefix('/') {
+        Some(local) if local.chars().nth(1) == Some(':') => local.replace('/', "\\"),
+        Some(_) => decoded,
+        None if cfg!(windows) => format!(r"\\{}", decoded.replace('/', "\\")),
+        None => format!("//{}", decoded),
+    };
+    Ok(PathBuf::from(path))
+}
+
+fn percent_decode(text: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// The `URL=` line of a windows `.url` file
+fn internet_shortcut(path: &Path) -> Result<String, String> {
+    let contents = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+    contents
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("URL="))
+        .map(|url| url.trim().to_string())
+        .ok_or_else(|| String::from("the shortcut has no url in it"))
+}
+
+/// Target of a windows `.lnk` shortcut, read from its LinkInfo which holds the local path or
+/// network share the shortcut points to (see [MS-SHLLINK])
+fn shortcut_target(path: &Path) -> Result<PathBuf, String> {
+    let data = std::fs::read(path).map_err(|error| error.to_string())?;
+    let invalid = || String::from("it is not a shortcut teres can read");
+    let u16_at = |offset: usize| -> Option<usize> {
+        Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?) as usize)
+    };
+    let u32_at = |offset: usize| -> Option<usize> {
+        Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?) as usize)
+    };
+    if u32_at(0) != Some(0x4C) {
+        return Err(invalid());
+    }
+    let flags = u32_at(0x14).ok_or_else(invalid)?;
+    let mut offset = 0x4C;
+    // HasLinkTargetIDList, a shell item list that comes before the LinkInfo
+    if flags & 0x1 != 0 {
+        offset += 2 + u16_at(offset).ok_or_else(invalid)?;
+    }
+    // HasLinkInfo, missing from shortcuts to things that aren't files
+    if flags & 0x2 == 0 {
+        return Err(String::from("the shortcut doesn't point to a file"));
+    }
+    let info = offset;
+    let field = |at: usize| u32_at(info + at).ok_or_else(invalid);
+    let (header_size, info_flags) = (field(0x4)?, field(0x8)?);
+    let ansi = |at: usize| -> Result<String, String> {
+        let start = info + at;
+        let end = data
+            .get(start..)
+            .ok_or_else(invalid)?
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or_else(invalid)?;
+        Ok(String::from_utf8_lossy(&data[start..start + end]).to_string())
+    };
+    let unicode = |at: usize| -> Result<String, String> {
+        let units: Vec<u16> = data
+            .get(info + at..)
+            .ok_or_else(invalid)?
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect();
+        Ok(String::from_utf16_lossy(&units))
+    };
+    // the unicode offsets are only there with the longer header
+    let suffix = if header_size >= 0x24 {
+        unicode(field(0x20)?)?
+    } else {
+        ansi(field(0x18)?)?
+    };
+    let target = if info_flags & 0x1 != 0 {
+        let base = if header_size >= 0x24 {
+            unicode(field(0x1C)?)?
+        } else {
+            ansi(field(0x10)?)?
+        };
+        base + &suffix
+    } else if info_flags & 0x2 != 0 {
+        // CommonNetworkRelativeLink, the NetName is the \\server\share
+        let network = field(0x14)?;
+        let share = ansi(network + u32_at(info + network + 0x8).ok_or_else(invalid)?)?;
+        format!(r"{}\{}", share.trim_end_matches('\\'), suffix)
+    } else {
+        return Err(invalid());
+    };
+    if target.is_empty() {
+        return Err(invalid());
+    }
+    Ok(PathBuf::from(target))
+}
+
+/// `\\?\C:\clip.mp4` as `C:\clip.mp4` and `\\?\UNC\server\share` as `\\server\share`, which
+/// vapoursynth and python don't take
+pub fn without_verbatim(path: &Path) -> PathBuf {
+    let Some(text) = path.to_str() else {
+        return path.to_path_buf();
+    };
+    if let Some(share) = text.strip_prefix(r"\\?\UNC\") {
+        PathBuf::from(format!(r"\\{}", share))
+    } else if let Some(local) = text.strip_prefix(r"\\?\") {
+        PathBuf::from(local)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_url_reads_drive_paths() {
+        assert_eq!(
+            from_url("file:///C:/My%20Clips/game.mp4").unwrap(),
+            PathBuf::from(r"C:\My Clips\game.mp4")
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn from_url_reads_unix_paths_and_shares() {
+        assert_eq!(
+            from_url("file:///home/me/clip%231.mp4").unwrap(),
+            PathBuf::from("/home/me/clip#1.mp4")
+        );
+        assert_eq!(
+            from_url("file://nas/videos/clip.mp4").unwrap(),
+            PathBuf::from("//nas/videos/clip.mp4")
+        );
+    }
+
+    #[test]
+    fn from_url_refuses_web_links() {
+        assert!(from_url("https://example.com/clip.mp4")
+            .unwrap_err()
+            .contains("download it first"));
+    }
+
+    /// A shortcut with only a LinkInfo, made of its header fields after the size and header
+    /// size and then `strings`
+    fn shortcut(name: &str, link_flags: u32, info: [u32; 5], strings: &[u8]) -> PathBuf {
+        let mut data = vec![0; 0x4C];
+        data[..4].copy_from_slice(&0x4Cu32.to_le_bytes());
+        data[0x14..0x18].copy_from_slice(&link_flags.to_le_bytes());
+        let size = 0x1C + strings.len() as u32;
+        for field in [size, 0x1C].into_iter().chain(info) {
+            data.extend(field.to_le_bytes());
+        }
+        data.extend(strings);
+        let path =
+            std::env::temp_dir().join(format!("teres-test-{}-{}.lnk", name, std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn shortcut_target_reads_local_paths() {
+        // VolumeIDAndLocalBasePath, the base path at 0x1C and an empty suffix after it
+        let path = shortcut(
+            "local",
+            0x2,
+            [0x1, 0, 0x1C, 0, 0x2E],
+            b"C:\\clips\\game.mp4\0\0",
+        );
+        let target = shortcut_target(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(target.unwrap(), PathBuf::from(r"C:\clips\game.mp4"));
+    }
+
+    #[test]
+    fn shortcut_target_reads_network_shares() {
+        // CommonNetworkRelativeLink at 0x1C with its NetName 0x14 into it, then the suffix
+        let mut strings = vec![];
+        for field in [0x14u32, 0x1, 0x14, 0, 0] {
+            strings.extend(field.to_le_bytes());
+        }
+        strings.extend(b"\\\\server\\share\0clip.mp4\0");
+        let path = shortcut("network", 0x2, [0x2, 0, 0, 0x1C, 0x3F], &strings);
+        let target = shortcut_target(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(target.unwrap(), PathBuf::from(r"\\server\share\clip.mp4"));
+    }
+
+    #[test]
+    fn shortcut_target_refuses_other_files() {
+        let path = shortcut("folder", 0x0, [0; 5], b"");
+        let target = shortcut_target(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(target.unwrap_err(), "the shortcut doesn't point to a file");
+
+        let path = std::env::temp_dir().join(format!("teres-test-text-{}.lnk", std::process::id()));
+        std::fs::write(&path, "not a shortcut").unwrap();
+        let target = shortcut_target(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(target.unwrap_err(), "it is not a shortcut teres can read");
+    }
+}
@@ -0,0 +1,128 @@
+use crate::config::Config;
+
+/// Presets shipped with teres, selected with `--preset builtin:<name>`. Each only holds the
+/// values it changes and is applied the same way as presets from the config file
+pub const BUILTIN: &[(&str, &str)] = &[
+    (
+        "classic",
+        r#"# interpolate 60 fps footage to 960 fps and blend back down to 60 with a gaussian curve
+[blending]
+enabled = true
+amount = 1.0
+weighting = "gaussian_sym"
+output_fps = 60
+
+[interpolation]
+enabled = true
+fps = 960.0"#,
+    ),
+    (
+        "vegas",
+        r#"# blend neighbouring frames without interpolation like frame resampling in vegas
+[blending]
+enabled = true
+amount = 1.0
+weighting = "equal"
+output_fps = 60
+
+[interpolation]
+enabled = false"#,
+    ),
+    (
+        "fragmovie",
+        r#"# standard csgo fragmovie look, lighter blur from high fps recordings
+[blending]
+enabled = true
+amount = 0.6
+weighting = "equal"
+output_fps = 60
+
+[interpolation]
+enabled = true
+fps = 600.0
+
+[advanced.interpolation]
+speed = "medium"
+tuning = "weak""#,
+    ),
+];
+
+/// Settings picked by `--auto-settings` for common recording frame rates, as `(source fps,
+/// interpolation.fps, blending.output_fps, blending.amount)`. Interpolation stays around 10x the
+/// source or below, where artifacts start to show
+pub const AUTO: &[(f64, f32, i32, f32)] = &[
+    (30.0, 300.0, 30, 0.3),
+    (60.0, 480.0, 60, 0.8),
+    (120.0, 960.0, 60, 1.0),
+    (144.0, 1152.0, 60, 1.0),
+    (240.0, 1200.0, 60, 1.0),
+    (360.0, 1440.0, 60, 1.0),
+];
+
+/// Encoding for uploading to a platform, selected with `--export-for <name>`. Applied like
+/// `--set` overrides so they take precedence over the config and presets
+pub const EXPORT: &[(&str, &[&str])] = &[
+    (
+        // 10 MB is the upload limit without nitro
+        "discord",
+        &[
+            "encoding.container=mp4",
+            "encoding.codec=h264",
+            "encoding.pixel_format=yuv420p",
+            "encoding.target_size_mb=10.0",
+            "output.resolution=720p",
+        ],
+    ),
+    (
+        // youtube reencodes everything, so it gets the most quality it takes for 4k60
+        "youtube",
+        &[
+            "encoding.container=mp4",
+            "encoding.codec=h264",
+            "encoding.pixel_format=yuv420p",
+            "encoding.quality=16",
+            "encoding.max_bitrate_kbps=68000",
+        ],
+    ),
+    (
+        "tiktok",
+        &[
+            "encoding.container=mp4",
+            "encoding.codec=h264",
+            "encoding.pixel_format=yuv420p",
+            "encoding.max_bitrate_kbps=15000",
+            "output.resolution=1080p-vertical",
+            "output.pad=blur",
+        ],
+    ),
+];
+
+/// Applies the `AUTO` settings of the frame rate closest to `fps`, returning that frame rate
+pub fn auto(settings: &mut Config, fps: f64) -> f64 {
+    let (source, interpolated, output_fps, amount) = AUTO
+        .iter()
+        .min_by(|a, b| (a.0 - fps).abs().total_cmp(&(b.0 - fps).abs()))
+        .copied()
+        .unwrap();
+    settings.interpolation.enabled = true;
+    settings.interpolation.fps = interpolated;
+    settings.blending.enabled = true;
+    settings.blending.output_fps = output_fps;
+    settings.blending.amount = amount;
+    settings.blending.shutter_angle = 0.0;
+    source
+}
+
+pub fn export(name: &str) -> Option<&'static [&'static str]> {
+    EXPORT
+        .iter()
+        .find(|(platform, _)| platform.eq_ignore_ascii_case(name))
+        .map(|(_, overrides)| *overrides)
+}
+
+pub fn builtin(name: &str) -> Option<toml::Value> {
+    BUILTIN
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, template)| toml::from_str(template).unwrap())
+}
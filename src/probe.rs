@@ -0,0 +1,446 @@
+use crate::binaries;
+use crate::sequence;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Information about an input video collected with ffprobe
+#[derive(Debug, Clone, Default)]
+pub struct MediaInfo {
+    pub width: u32,
+    pub height: u32,
+    /// Average frame rate as `(num, den)`
+    pub fps: Option<(i64, i64)>,
+    /// Real (base) frame rate as `(num, den)`
+    pub r_fps: Option<(i64, i64)>,
+    pub duration: f64,
+    pub codec: String,
+    pub pix_fmt: String,
+    pub bit_depth: u32,
+    /// ffprobe's color matrix, e.g. `bt709` or `smpte170m`, empty when untagged
+    pub color_space: String,
+    /// `tv` (limited) or `pc` (full), empty when untagged
+    pub color_range: String,
+    pub audio: Option<AudioInfo>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AudioInfo {
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub channel_layout: String,
+}
+
+impl MediaInfo {
+    pub fn fps_f64(&self) -> Option<f64> {
+        self.fps.map(|(num, den)| num as f64 / den as f64)
+    }
+
+    /// Variable frame rate streams have an average rate different from their real rate
+    pub fn is_vfr(&self) -> bool {
+        match (self.r_fps, self.fps) {
+            (Some((r_num, r_den)), Some((avg_num, avg_den))) => {
+                let r_fps = r_num as f64 / r_den as f64;
+                let avg_fps = avg_num as f64 / avg_den as f64;
+                (r_fps - avg_fps).abs() / r_fps > 0.001
+            }
+            _ => false,
+        }
+    }
+
+    /// The vapoursynth resize name of the color matrix, guessed from the resolution when
+    /// untagged like most players do
+    pub fn matrix(&self) -> &'static str {
+        match self.color_space.as_str() {
+            "bt709" => "709",
+            "bt470bg" => "470bg",
+            "smpte170m" => "170m",
+            "bt2020nc" => "2020ncl",
+            "bt2020c" => "2020cl",
+            "smpte240m" => "240m",
+            "fcc" => "fcc",
+            _ if self.height > 0 && self.height < 720 => "170m",
+            _ => "709",
+        }
+    }
+
+    pub fn full_range(&self) -> bool {
+        // yuvj formats are full range even when untagged
+        self.color_range == "pc" || self.pix_fmt.starts_with("yuvj")
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.audio
+            .as_ref()
+            .map(|audio| audio.sample_rate)
+            .filter(|rate| *rate > 0)
+            .unwrap_or(48000)
+    }
+}
+
+impl fmt::Display for MediaInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  Resolution: {}x{}", self.width, self.height)?;
+        match (self.fps, self.fps_f64()) {
+            (Some((num, den)), Some(fps)) => writeln!(
+                f,
+                "  Frame rate: {:.3} ({}/{}){}",
+                fps,
+                num,
+                den,
+                if self.is_vfr() { " variable" } else { "" }
+            )?,
+            _ => writeln!(f, "  Frame rate: unknown")?,
+        }
+        writeln!(f, "  Duration: {:.2}s", self.duration)?;
+        writeln!(
+            f,
+            "  Video: {} {} {}-bit",
+            self.codec, self.pix_fmt, self.bit_depth
+        )?;
+        writeln!(
+            f,
+            "  Color: {} {} range{}",
+            self.matrix(),
+            if self.full_range() { "full" } else { "limited" },
+            if self.color_space.is_empty() {
+                " (untagged)"
+            } else {
+                ""
+            }
+        )?;
+        match &self.audio {
+            Some(audio) => write!(
+                f,
+                "  Audio: {} {}Hz {} ({} channels)",
+                audio.codec, audio.sample_rate, audio.channel_layout, audio.channels
+            ),
+            None => write!(f, "  Audio: none"),
+        }
+    }
+}
+
+/// Runs ffprobe on the video, returning `None` when it is unavailable or fails
+pub fn probe(video_path: &Path) -> Option<MediaInfo> {
+    match check(video_path) {
+        Ok(info) => info,
+        Err(error) => {
+            debug!("Could not probe {}: {}", video_path.display(), error);
+            None
+        }
+    }
+}
+
+/// Probes an input, describing why it can't be rendered when it is corrupt or has no video.
+/// `None` when ffprobe couldn't be run, which leaves it to the render to find out
+pub fn check(video_path: &Path) -> Result<Option<MediaInfo>, String> {
+    let mut command = Command::new(binaries::ffprobe());
+    if let Some(first) = sequence::first(video_path) {
+        // ffmpeg only looks for the first image among the first few numbers
+        command.args(["-start_number", first.to_string().as_str()]);
+    }
+    let output = command
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "stream=codec_type,codec_name,width,height,r_frame_rate,avg_frame_rate,pix_fmt,bits_per_raw_sample,color_space,color_range,sample_rate,channels,channel_layout:format=duration",
+            "-of",
+            "flat",
+        ])
+        .arg(video_path)
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(error) => {
+            debug!("Could not run ffprobe: {}", error);
+            return Ok(None);
+        }
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "it could not be read, it may be corrupt ({})",
+            stderr.lines().last().unwrap_or_default().trim()
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let info = parse_flat(&stdout);
+    trace!("Probed {}: {:?}", video_path.display(), info);
+    match info {
+        Some(info) => Ok(Some(info)),
+        None if stdout.contains("codec_type=\"audio\"") => {
+            Err(String::from("it is audio only, there is no video to blur"))
+        }
+        None => Err(String::from("it has no video stream")),
+    }
+}
+
+/// Value of a metadata tag of the container, `None` when it isn't set
+pub fn tag(video_path: &Path, name: &str) -> Option<String> {
+    let output = Command::new(binaries::ffprobe())
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            format!("format_tags={}", name).as_str(),
+            "-of",
+            "default=noprint_wrappers=1:nokey=1",
+        ])
+        .arg(video_path)
+        .output()
+        .ok()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || value.is_empty() {
+        return None;
+    }
+    Some(value)
+}
+
+/// What ffmpeg logs for damaged streams, lowercased. Other errors, like about metadata or
+/// timestamps, don't make the video unplayable
+const DECODE_ERRORS: [&str; 10] = [
+    "invalid data found",
+    "error while decoding",
+    "error submitting packet to decoder",
+    "error during demuxing",
+    "corrupt",
+    "concealing",
+    "truncat",
+    "partial file",
+    "moov atom not found",
+    "invalid nal unit",
+];
+
+/// Decodes the whole file to find corruption, like a render cut short by a full disk. Only
+/// errors about damaged streams count
+pub fn verify(ffmpeg: &str, video_path: &Path) -> Result<(), String> {
+    let output = Command::new(ffmpeg)
+        .args(["-v", "error", "-nostats", "-i"])
+        .arg(video_path)
+        .args(["-f", "null", "-"])
+        .output()
+        .map_err(|error| error.to_string())?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // the first one is enough to tell what went wrong
+    let error = stderr.lines().map(str::trim).find(|line| {
+        let line = line.to_lowercase();
+        DECODE_ERRORS.iter().any(|error| line.contains(error))
+    });
+    match error {
+        Some(error) => Err(error.to_string()),
+        None if !output.status.success() => Err(stderr
+            .lines()
+            .next()
+            .unwrap_or("decoding failed")
+            .trim()
+            .to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Loudness of the inputs' audio measured by the first loudnorm pass
+#[derive(Debug, Clone)]
+pub struct Loudness {
+    pub integrated: String,
+    pub true_peak: String,
+    pub range: String,
+    pub threshold: String,
+    pub offset: String,
+}
+
+/// Measures the loudness of the audio of the inputs joined in order, decoding only the audio
+pub fn loudness(ffmpeg: &str, inputs: &[PathBuf], filter: &str) -> Option<Loudness> {
+    let mut command = Command::new(ffmpeg);
+    command.args(["-hide_banner", "-nostats"]);
+    for input in inputs {
+        command.arg("-i").arg(input);
+    }
+    let streams: String = (0..inputs.len())
+        .map(|input| format!("[{}:a]", input))
+        .collect();
+    command.args([
+        "-filter_complex",
+        &format!(
+            "{}concat=n={}:v=0:a=1,{}:print_format=json",
+            streams,
+            inputs.len(),
+            filter
+        ),
+        "-f",
+        "null",
+        "-",
+    ]);
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        debug!(
+            "Measuring loudness failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return None;
+    }
+
+    // the measurements are printed as the last json object on stderr
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let json: serde_json::Value = serde_json::from_str(&stderr[stderr.rfind('{')?..]).ok()?;
+    let field = |name: &str| json.get(name)?.as_str().map(String::from);
+    let loudness = Loudness {
+        integrated: field("input_i")?,
+        true_peak: field("input_tp")?,
+        range: field("input_lra")?,
+        threshold: field("input_thresh")?,
+        offset: field("target_offset")?,
+    };
+    trace!("Measured loudness: {:?}", loudness);
+    Some(loudness)
+}
+
+/// Parses ffprobe's `flat` output format, e.g. `streams.stream.0.width=1920`
+fn parse_flat(output: &str) -> Option<MediaInfo> {
+    let mut streams: Vec<HashMap<String, String>> = vec![];
+    let mut duration = 0.0;
+
+    for line in output.lines() {
+        let (key, value) = match line.split_once('=') {
+            Some(pair) => pair,
+            None => continue,
+        };
+        let value = value.trim_matches('"').to_string();
+        if key == "format.duration" {
+            duration = value.parse().unwrap_or(0.0);
+        } else if let Some(rest) = key.strip_prefix("streams.stream.") {
+            if let Some((index, field)) = rest.split_once('.') {
+                let index: usize = match index.parse() {
+                    Ok(index) => index,
+                    Err(_) => continue,
+                };
+                if streams.len() <= index {
+                    streams.resize(index + 1, HashMap::new());
+                }
+                streams[index].insert(field.to_string(), value);
+            }
+        }
+    }
+
+    let field = |stream: &HashMap<String, String>, name: &str| -> String {
+        stream.get(name).cloned().unwrap_or_default()
+    };
+
+    let video = streams
+        .iter()
+        .find(|stream| field(stream, "codec_type") == "video")?;
+    let pix_fmt = field(video, "pix_fmt");
+    let bit_depth = field(video, "bits_per_raw_sample")
+        .parse()
+        .unwrap_or_else(|_| bit_depth_from_pix_fmt(&pix_fmt));
+
+    let audio = streams
+        .iter()
+        .find(|stream| field(stream, "codec_type") == "audio")
+        .map(|stream| AudioInfo {
+            codec: field(stream, "codec_name"),
+            sample_rate: field(stream, "sample_rate").parse().unwrap_or(0),
+            channels: field(stream, "channels").parse().unwrap_or(0),
+            channel_layout: field(stream, "channel_layout"),
+        });
+
+    Some(MediaInfo {
+        width: field(video, "width").parse().unwrap_or(0),
+        height: field(video, "height").parse().unwrap_or(0),
+        fps: parse_rate(&field(video, "avg_frame_rate")),
+        r_fps: parse_rate(&field(video, "r_frame_rate")),
+        duration,
+        codec: field(video, "codec_name"),
+        pix_fmt,
+        bit_depth,
+        color_space: field(video, "color_space"),
+        color_range: field(video, "color_range"),
+        audio,
+    })
+}
+
+fn bit_depth_from_pix_fmt(pix_fmt: &str) -> u32 {
+    for depth in [16, 14, 12, 10, 9] {
+        if pix_fmt.contains(&format!("p{}", depth)) {
+            return depth;
+        }
+    }
+    8
+}
+
+/// Parses an ffprobe `num/den` frame rate
+pub fn parse_rate(rate: &str) -> Option<(i64, i64)> {
+    let (num, den) = rate.split_once('/').unwrap_or((rate, "1"));
+    let num = num.parse::<i64>().ok()?;
+    let den = den.parse::<i64>().ok()?;
+    if num <= 0 || den <= 0 {
+        return None;
+    }
+    Some((num, den))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAT: &str = r#"streams.stream.0.codec_name="h264"
+streams.stream.0.codec_type="video"
+streams.stream.0.width=1920
+streams.stream.0.height=1080
+streams.stream.0.pix_fmt="yuv420p10le"
+streams.stream.0.avg_frame_rate="60000/1001"
+streams.stream.0.r_frame_rate="60/1"
+streams.stream.0.color_space="bt709"
+streams.stream.0.color_range="tv"
+streams.stream.1.codec_name="aac"
+streams.stream.1.codec_type="audio"
+streams.stream.1.sample_rate="48000"
+streams.stream.1.channels=2
+streams.stream.1.channel_layout="stereo"
+format.duration="12.500000"
+"#;
+
+    #[test]
+    fn parse_flat_reads_the_streams() {
+        let info = parse_flat(FLAT).unwrap();
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert_eq!(info.fps, Some((60000, 1001)));
+        assert_eq!(info.r_fps, Some((60, 1)));
+        assert_eq!(info.duration, 12.5);
+        assert_eq!(info.codec, "h264");
+        assert_eq!(info.bit_depth, 10);
+        assert_eq!(info.color_space, "bt709");
+        assert_eq!(info.color_range, "tv");
+        let audio = info.audio.unwrap();
+        assert_eq!(audio.codec, "aac");
+        assert_eq!((audio.sample_rate, audio.channels), (48000, 2));
+        assert_eq!(audio.channel_layout, "stereo");
+    }
+
+    #[test]
+    fn parse_flat_without_audio() {
+        let video_only: String = FLAT
+            .lines()
+            .filter(|line| !line.starts_with("streams.stream.1."))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert!(parse_flat(&video_only).unwrap().audio.is_none());
+    }
+
+    #[test]
+    fn parse_flat_needs_a_video_stream() {
+        let audio_only = "streams.stream.0.codec_name=\"mp3\"\nstreams.stream.0.codec_type=\"audio\"\nformat.duration=\"3.0\"\n";
+        assert!(parse_flat(audio_only).is_none());
+    }
+
+    #[test]
+    fn parse_flat_reads_the_bit_depth() {
+        let flat = "streams.stream.0.codec_type=\"video\"\nstreams.stream.0.pix_fmt=\"yuv420p\"\nstreams.stream.0.bits_per_raw_sample=\"12\"\n";
+        assert_eq!(parse_flat(flat).unwrap().bit_depth, 12);
+        let unknown = "streams.stream.0.codec_type=\"video\"\nstreams.stream.0.pix_fmt=\"yuv420p\"\nstreams.stream.0.bits_per_raw_sample=\"N/A\"\n";
+        assert_eq!(parse_flat(unknown).unwrap().bit_depth, 8);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::config::Config;
+use crate::rendering::{Render, Rendering};
+use crate::schedule::{self, Guard};
+use crate::teres::load_config;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// A video staged to be rendered later with `teres queue run`, its settings are resolved when
+/// it is rendered
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Entry {
+    pub input: PathBuf,
+    #[serde(default)]
+    pub overrides: Vec<String>,
+    #[serde(default)]
+    pub preset: Option<String>,
+}
+
+pub fn path() -> PathBuf {
+    Config::global_path().with_file_name("queue.json")
+}
+
+fn load() -> Vec<Entry> {
+    std::fs::read_to_string(path())
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save(entries: &[Entry]) {
+    let result = serde_json::to_string_pretty(entries)
+        .map_err(std::io::Error::from)
+        .and_then(|contents| {
+            std::fs::create_dir_all(path().parent().unwrap())?;
+            std::fs::write(path(), contents)
+        });
+    if let Err(error) = result {
+        error!("Could not save the queue: {}", error);
+    }
+}
+
+pub fn list() {
+    let entries = load();
+    if entries.is_empty() {
+        info!("The queue is empty");
+    }
+    for (index, entry) in entries.iter().enumerate() {
+        let mut line = format!("{}. {}", index + 1, entry.input.display());
+        if let Some(preset) = &entry.preset {
+            line += &format!(" --preset {}", preset);
+        }
+        for item in &entry.overrides {
+            line += &format!(" --set {}", item);
+        }
+        println!("{}", line);
+    }
+}
+
+pub fn add(paths: &[PathBuf], overrides: &[String], preset: Option<&str>) {
+    let mut entries = load();
+    for path in paths {
+        let input = match path.canonicalize() {
+            Ok(input) => input,
+            Err(_) => {
+                error!("Video {} does not exist", path.display());
+                continue;
+            }
+        };
+        info!("Queued {}", input.display());
+        entries.push(Entry {
+            input,
+            overrides: overrides.to_vec(),
+            preset: preset.map(str::to_string),
+        });
+    }
+    save(&entries);
+}
+
+/// Removes the entry at a position as shown by `teres queue list`
+pub fn remove(position: usize) {
+    let mut entries = load();
+    if position == 0 || position > entries.len() {
+        error!("There is no video at position {} in the queue", position);
+        return;
+    }
+    let entry = entries.remove(position - 1);
+    info!("Removed {}", entry.input.display());
+    save(&entries);
+}
+
+pub fn clear() {
+    save(&[]);
+    info!("Cleared the queue");
+}
+
+pub fn move_entry(from: usize, to: usize) {
+    let mut entries = load();
+    if from == 0 || from > entries.len() || to == 0 || to > entries.len() {
+        error!(
+            "Positions must be from 1 to {}, got {} and {}",
+            entries.len(),
+            from,
+            to
+        );
+        return;
+    }
+    let entry = entries.remove(from - 1);
+    info!("Moved {} to position {}", entry.input.display(), to);
+    entries.insert(to - 1, entry);
+    save(&entries);
+}
+
+/// Renders the queue in order, removing each video once it is rendered so an interrupted run
+/// continues where it stopped
+pub fn run(keep_going: bool) {
+    let mut failed = vec![];
+    loop {
+        let next = load().into_iter().find(|entry| !failed.contains(entry));
+        let Some(entry) = next else {
+            break;
+        };
+        let settings = load_config(&entry.overrides, entry.preset.as_deref());
+        schedule::wait(&settings.schedule);
+        let guard = Guard::start(&settings.schedule);
+        let mut rendering = Rendering {
+            queue: vec![],
+            renders_queued: false,
+            remux_only: false,
+            keep_going,
+            failed: vec![],
+            jobs: vec![],
+        };
+        match Render::new(entry.input.clone(), settings) {
+            Some(render) => {
+                rendering.queue_render(render);
+                rendering.render_videos();
+            }
+            None => {
+                error!("Could not queue {}", entry.input.display());
+                rendering.failed.push(entry.input.display().to_string());
+            }
+        }
+        if let Some(guard) = guard {
+            guard.finish();
+        }
+
+        if rendering.failed.is_empty() {
+            let mut entries = load();
+            if let Some(index) = entries.iter().position(|queued| *queued == entry) {
+                entries.remove(index);
+                save(&entries);
+            }
+        } else {
+            failed.push(entry);
+        }
+    }
+    if !failed.is_empty() {
+        error!(
+            "{} video(s) failed and were left in the queue",
+            failed.len()
+        );
+        crate::helpers::exit(exitcode::SOFTWARE);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::helpers::format_filename;
+use crate::sidecar;
+use std::path::{Path, PathBuf};
+
+/// Renames rendered videos (and their sidecars) to a new filename template
+pub fn run(template: &str, paths: &[PathBuf], dry_run: bool) {
+    let mut renamed = 0;
+    for path in paths {
+        if !path.exists() {
+            error!("{} does not exist", path.display());
+            continue;
+        }
+        for output in rendered_videos(path) {
+            if rename(&output, template, dry_run) {
+                renamed += 1;
+            }
+        }
+    }
+    info!(
+        "{} {} video(s)",
+        if dry_run { "Would rename" } else { "Renamed" },
+        renamed
+    );
+}
+
+/// Finds videos that have sidecar metadata in a folder or returns the file itself
+fn rendered_videos(path: &Path) -> Vec<PathBuf> {
+    if path.is_file() {
+        if sidecar::path_for(path).exists() {
+            return vec![path.to_path_buf()];
+        }
+        warn!(
+            "{} has no sidecar metadata (rendered before it was written?), skipping",
+            path.display()
+        );
+        return vec![];
+    }
+
+    let mut videos: Vec<PathBuf> = match path.read_dir() {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|file| {
+                file.is_file() && !sidecar::is_sidecar(file) && sidecar::path_for(file).exists()
+            })
+            .collect(),
+        Err(error) => {
+            error!("Could not read {}: {}", path.display(), error);
+            vec![]
+        }
+    };
+    videos.sort();
+    videos
+}
+
+fn rename(output: &Path, template: &str, dry_run: bool) -> bool {
+    let metadata = match sidecar::read(output) {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            warn!("Could not read sidecar for {}: {}", output.display(), error);
+            return false;
+        }
+    };
+    let name = match metadata.input.file_stem() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => return false,
+    };
+
+    let mut new_name = format_filename(template, &name, &metadata.settings);
+    if let Some(ext) = output.extension() {
+        new_name += ".";
+        new_name += &ext.to_string_lossy();
+    }
+    let new_path = output.with_file_name(new_name);
+
+    if new_path == output {
+        debug!("{} already matches the template", output.display());
+        return false;
+    }
+    if new_path.exists() {
+        warn!(
+            "Not renaming {} as {} already exists",
+            output.display(),
+            new_path.display()
+        );
+        return false;
+    }
+
+    info!("{} -> {}", output.display(), new_path.display());
+    if dry_run {
+        return true;
+    }
+    if let Err(error) = std::fs::rename(output, &new_path) {
+        error!("Could not rename {}: {}", output.display(), error);
+        return false;
+    }
+    if let Err(error) = sidecar::rename(output, &new_path) {
+        error!(
+            "Could not rename the sidecar of {}: {}",
+            output.display(),
+            error
+        );
+        // the video goes back so it stays next to its sidecar
+        if let Err(error) = std::fs::rename(&new_path, output) {
+            error!(
+                "Could not move {} back to {}: {}",
+                new_path.display(),
+                output.display(),
+                error
+            );
+        }
+        return false;
+    }
+    true
+}
@@ -1,36 +1,222 @@
+use crate::binaries;
+use crate::chunks;
 use crate::config::Config;
+use crate::container::Container;
+use crate::disk;
+use crate::events;
 use crate::helpers::{self, change_file_name, clean, exec};
-use crate::script_handler::create;
-use crate::teres::{create_temp_path, used_installer};
+use crate::history::{self, Job};
+use crate::metrics::{self, Metrics};
+use crate::monitor::Monitor;
+use crate::notifications;
+use crate::paths;
+use crate::probe::{self, Loudness, MediaInfo};
+use crate::script_handler::{create, Source};
+use crate::sequence;
+use crate::sidecar;
+use crate::target_size::{self, Pass};
+use crate::teres::create_temp_path;
+use crate::thumbnail;
+use crate::upload;
 use indicatif::{ProgressBar, ProgressStyle};
-use log::{debug, error};
+use log::{debug, error, warn};
+use serde_json::json;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::vec::Vec;
 
+/// Integrated loudness, true peak and loudness range audio is normalized to
+const LOUDNORM_TARGET: &str = "I=-16:TP=-1.5:LRA=11";
+
+/// A clip joined to the rendered video by name, with its media info when it could be read
+pub type Clip = (&'static str, PathBuf, Option<MediaInfo>);
+
 #[derive(Clone)]
 pub struct Render {
     pub video_path: PathBuf,
     pub video_folder: PathBuf,
     pub script_path: PathBuf,
+    pub index_path: PathBuf,
+    pub media_info: Option<MediaInfo>,
+    /// Clips joined after the input with --concat
+    pub joined: Vec<Source>,
+    /// The intro and outro joined to the rendered video
+    pub stitched: Vec<Clip>,
+    /// Loudness of the audio for audio.normalize, measured when the first command is built
+    loudness: OnceLock<Option<Loudness>>,
 
     input_filename: String,
-    output_filepath: PathBuf,
+    pub output_filepath: PathBuf,
 
-    settings: Config,
+    pub settings: Config,
 }
 
 impl Render {
-    pub fn new(input_path: PathBuf) -> Option<Render> {
-        let video_folder = input_path.parent()?.to_path_buf();
-        let video_path = input_path;
+    pub fn new(input_path: PathBuf, settings: Config) -> Option<Render> {
+        Render::with_joined(input_path, vec![], settings)
+    }
+
+    /// Joins the clips onto the end of the input before blurring, rendering them to a single
+    /// output named after the input
+    pub fn with_joined(
+        input_path: PathBuf,
+        joined: Vec<PathBuf>,
+        settings: Config,
+    ) -> Option<Render> {
+        // dropped shortcuts and urls, and relative paths which have no folder to write next to
+        let video_path = match paths::resolve(&input_path) {
+            Ok(path) => path,
+            Err(error) => {
+                error!("Could not read {}, {}", input_path.display(), error);
+                return None;
+            }
+        };
+        let Some(video_folder) = video_path.parent().map(Path::to_path_buf) else {
+            error!("{} is not a file", video_path.display());
+            return None;
+        };
+        let joined: Vec<PathBuf> = joined
+            .iter()
+            .map(|clip| paths::resolve(clip).unwrap_or_else(|_| clip.clone()))
+            .collect();
 
-        let video_name = video_path.file_stem()?.to_str()?.to_string();
+        let sequence = sequence::is_sequence(&video_path);
+        let video_name = if sequence {
+            sequence::name(&video_path)?
+        } else {
+            video_path.file_stem()?.to_str()?.to_string()
+        };
 
         let input_filename = video_path.file_name()?.to_str()?.to_string();
-        let settings = Config::parse();
-        let output_filepath = video_folder.join(format!("{}_blur.{}", video_name, settings.encoding.container));
-        let temp_path = create_temp_path(video_folder.clone()).unwrap();
-        let script_path = create(temp_path, &video_path, settings.clone());
+        // "source" keeps the container of each input, image sequences become mp4s
+        let container = if settings.encoding.container.to_lowercase() == "source" {
+            if sequence {
+                String::from("mp4")
+            } else {
+                video_path.extension()?.to_str()?.to_string()
+            }
+        } else {
+            Container::extension(&settings.encoding.container)
+        };
+        let output_name = format!(
+            "{}{}{}",
+            helpers::format_filename(&settings.encoding.filename_template, &video_name, &settings),
+            if settings.debug.compare.is_empty() {
+                ""
+            } else {
+                "_compare"
+            },
+            if joined.is_empty() { "" } else { "_concat" },
+        );
+        // the frames of png sequences go in a folder of their own
+        let output_filepath =
+            if Container::named(&settings.encoding.container) == Container::PngSequence {
+                video_folder
+                    .join(&output_name)
+                    .join(format!("{}_%06d.{}", output_name, container))
+            } else {
+                video_folder.join(format!("{}.{}", output_name, container))
+            };
+
+        let placeholder = helpers::is_cloud_placeholder(&video_path);
+        if placeholder && !Render::hydrate(&video_path) {
+            return None;
+        }
+
+        // ffms2 writes its index next to the source by default which fails on read-only media
+        // and gets synced back up for cloud storage folders
+        let redirect = placeholder || !helpers::is_writable(&video_folder);
+        let temp_path = if redirect {
+            debug!(
+                "Redirecting temp files for {} to the temp dir",
+                input_filename
+            );
+            create_temp_path(std::env::temp_dir()).unwrap()
+        } else {
+            create_temp_path(video_folder.clone()).unwrap()
+        };
+        let index_path = if let Some(kept) = Render::kept_index_path(&video_path, &settings) {
+            kept
+        } else if redirect {
+            temp_path.join(format!("{}.ffindex", input_filename))
+        } else {
+            video_folder.join(format!("{}.ffindex", input_filename))
+        };
+        let mut media_info = match probe::check(&video_path) {
+            Ok(media_info) => media_info,
+            Err(error) => {
+                error!("Skipping {}, {}", input_filename, error);
+                return None;
+            }
+        };
+        // the regions are in pixels of the video and of the transformed video, vapoursynth
+        // fails outside of them
+        if let Some(info) = &media_info {
+            let (width, height) = settings.transform.frame_size(info.width, info.height);
+            let fits = settings
+                .transform
+                .check_crop(info.width, info.height)
+                .and_then(|_| settings.mask.check_rect(width, height));
+            if let Err(error) = fits {
+                error!("Skipping {}, {}", input_filename, error);
+                return None;
+            }
+        }
+        let mut sources = vec![Render::source(
+            &video_path,
+            index_path.clone(),
+            media_info.as_mut(),
+            &settings,
+        )];
+        for clip in joined {
+            if helpers::is_cloud_placeholder(&clip) && !Render::hydrate(&clip) {
+                return None;
+            }
+            let mut clip_info = match probe::check(&clip) {
+                Ok(clip_info) => clip_info,
+                Err(error) => {
+                    error!("Skipping {}, {}", clip.display(), error);
+                    return None;
+                }
+            };
+            // the audio tracks are joined too, which ffmpeg can't do with one missing
+            let silent =
+                |info: &Option<MediaInfo>| matches!(info, Some(info) if info.audio.is_none());
+            if settings.audio.enabled && (silent(&clip_info) || silent(&media_info)) {
+                error!(
+                    "Can't join {} and {} as one of them has no audio track",
+                    input_filename,
+                    clip.display()
+                );
+                return None;
+            }
+            // joined clips are indexed in the temp folder so only it needs cleaning up
+            let index_path = match Render::kept_index_path(&clip, &settings) {
+                Some(kept) => kept,
+                None => temp_path.join(format!(
+                    "{}.{}.ffindex",
+                    rand::random::<u16>(),
+                    clip.file_name()?.to_str()?
+                )),
+            };
+            sources.push(Render::source(
+                &clip,
+                index_path,
+                clip_info.as_mut(),
+                &settings,
+            ));
+        }
+        let script_path = create(temp_path, &sources, settings.clone());
+        // probed once for the filter graph and the length of the output
+        let stitched = [
+            ("intro", &settings.output.intro),
+            ("outro", &settings.output.outro),
+        ]
+        .into_iter()
+        .filter(|(_, path)| !path.is_empty())
+        .map(|(name, path)| (name, PathBuf::from(path), probe::probe(Path::new(path))))
+        .collect();
+        let joined = sources.split_off(1);
 
         Some(Render {
             video_path,
@@ -38,9 +224,178 @@ impl Render {
             input_filename,
             output_filepath,
             script_path,
+            index_path,
+            media_info,
+            joined,
+            stitched,
+            loudness: OnceLock::new(),
             settings,
         })
     }
+
+    /// Downloads a cloud storage placeholder, returning whether it succeeded
+    fn hydrate(video_path: &Path) -> bool {
+        let filename = video_path.file_name().unwrap_or_default().to_string_lossy();
+        info!("Downloading {} from cloud storage", filename);
+        if let Err(error) = helpers::hydrate(video_path) {
+            error!("Could not download {}: {}", filename, error);
+            return false;
+        }
+        true
+    }
+
+    /// Every input of the render in order, the audio of each ends up in the output
+    pub fn inputs(&self) -> Vec<PathBuf> {
+        std::iter::once(self.video_path.clone())
+            .chain(self.joined.iter().map(|source| source.video_path.clone()))
+            .collect()
+    }
+
+    /// Removes the temp files of the render, indexes are left for the next render with
+    /// advanced.keep_index
+    pub fn clean(&self) {
+        let keep_index = self.settings.advanced.keep_index;
+        if !keep_index {
+            for source in &self.joined {
+                let _ = std::fs::remove_file(&source.index_path);
+            }
+        }
+        clean(
+            (!keep_index).then(|| self.index_path.clone()),
+            self.script_path.clone(),
+        );
+    }
+
+    /// Where the ffms2 index of a video is kept between renders with advanced.keep_index, named
+    /// after its full path so videos with the same name in different folders don't clash
+    fn kept_index_path(video_path: &Path, settings: &Config) -> Option<PathBuf> {
+        if !settings.advanced.keep_index {
+            return None;
+        }
+        let folder = settings.advanced.index_cache_dir()?;
+        if let Err(error) = std::fs::create_dir_all(&folder) {
+            warn!(
+                "Could not create the index cache {}: {}",
+                folder.display(),
+                error
+            );
+            return None;
+        }
+        let full_path = video_path.canonicalize().ok()?;
+        Some(folder.join(format!(
+            "{}.{:016x}.ffindex",
+            video_path.file_name()?.to_str()?,
+            helpers::stable_hash(full_path.as_os_str().as_encoded_bytes())
+        )))
+    }
+
+    /// How the script loads an input. Image sequences have no frame rate of their own, so they
+    /// play at vfr.sequence_fps which their probed info is corrected to
+    fn source(
+        video_path: &Path,
+        index_path: PathBuf,
+        media_info: Option<&mut MediaInfo>,
+        settings: &Config,
+    ) -> Source {
+        if sequence::is_sequence(video_path) {
+            let frames = sequence::numbers(video_path);
+            // keep 3 decimal places for rates like 59.94
+            let rate = ((settings.vfr.sequence_fps * 1000.0).round() as i64, 1000);
+            if let Some(info) = media_info {
+                info.fps = Some(rate);
+                info.duration = frames.len() as f64 * rate.1 as f64 / rate.0 as f64;
+            }
+            return Source {
+                video_path: video_path.to_path_buf(),
+                index_path,
+                cfr_rate: Some(rate),
+                // images are full range rgb
+                matrix: String::from("rgb"),
+                full_range: true,
+                first_frame: frames.first().copied(),
+            };
+        }
+        let cfr_rate = Render::cfr_rate(video_path, media_info.as_deref(), settings);
+        let color = media_info.cloned().unwrap_or_default();
+        Source {
+            video_path: video_path.to_path_buf(),
+            index_path,
+            cfr_rate,
+            matrix: color.matrix().to_string(),
+            full_range: color.full_range(),
+            first_frame: None,
+        }
+    }
+
+    /// Decides the constant frame rate the source gets converted to, warning about variable
+    /// frame rate inputs
+    fn cfr_rate(
+        video_path: &Path,
+        media_info: Option<&MediaInfo>,
+        settings: &Config,
+    ) -> Option<(i64, i64)> {
+        let mode = settings.vfr.mode.to_lowercase();
+        let media_info = media_info.cloned().unwrap_or_default();
+
+        if media_info.is_vfr() {
+            if mode == "off" {
+                warn!(
+                    "{} has a variable frame rate which will cause audio desync, set vfr mode to auto or cfr to convert it",
+                    video_path.display()
+                );
+                return None;
+            }
+            warn!(
+                "{} has a variable frame rate ({:.3} average), converting to a constant frame rate",
+                video_path.display(),
+                media_info.fps_f64().unwrap_or_default()
+            );
+        } else if mode != "cfr" {
+            return None;
+        }
+
+        if settings.vfr.fps > 0.0 {
+            // keep 3 decimal places for rates like 59.94
+            Some(((settings.vfr.fps * 1000.0).round() as i64, 1000))
+        } else {
+            media_info.fps
+        }
+    }
+
+    /// Reports a failed render, exiting unless keep_going is set
+    fn fail(
+        &self,
+        output: &str,
+        status: &str,
+        elapsed: std::time::Duration,
+        keep_going: bool,
+    ) -> Job {
+        notifications::render_failed(&self.settings, &self.input_filename, elapsed);
+        let job = self.job(Path::new(output), status, elapsed);
+        history::record(&job);
+        events::emit(json!({ "event": "failed", "input": self.video_path, "output": output }));
+        if !keep_going {
+            helpers::exit(exitcode::SOFTWARE);
+        }
+        self.clean();
+        helpers::set_title("Teres");
+        job
+    }
+
+    pub fn job(&self, output: &Path, status: &str, elapsed: std::time::Duration) -> Job {
+        Job::new(
+            self.video_path.clone(),
+            output.to_path_buf(),
+            status,
+            elapsed,
+            self.media_info.as_ref().map(|info| info.duration),
+            self.media_info
+                .as_ref()
+                .and_then(|info| info.fps)
+                .map(|(num, den)| num as f64 / den as f64),
+            self.settings.clone(),
+        )
+    }
 }
 
 impl PartialEq for Render {
@@ -53,8 +408,16 @@ impl PartialEq for Render {
 pub struct Rendering {
     pub queue: Vec<Render>,
     pub renders_queued: bool,
+    pub remux_only: bool,
+    /// Continue with the rest of the queue when a render fails instead of exiting
+    pub keep_going: bool,
+    /// Input filenames of the renders that failed with keep_going
+    pub failed: Vec<String>,
+    /// Every render processed, for the summary report
+    pub jobs: Vec<Job>,
 }
 
+#[derive(Clone)]
 pub struct CommandWithArgs {
     pub ffmpeg_exe: String,
     pub ffmpeg_args: Vec<String>,
@@ -63,6 +426,9 @@ pub struct CommandWithArgs {
     pub vspipe_args: Vec<String>,
 
     pub output_filename: String,
+
+    pub process_priority: String,
+    pub cpu_affinity: Vec<usize>,
 }
 
 impl Rendering {
@@ -73,53 +439,89 @@ impl Rendering {
 
     pub fn render_videos(&mut self) {
         if self.renders_queued {
+            let total = self.queue.len();
             for render in self.queue.iter() {
-                eprintln!("Processing {}", render.input_filename);
-                let output_filepath = render.output_filepath.clone();
-                let settings = render.settings.clone();
-                let video_path = render.video_path.clone();
-                let script_path = render.script_path.clone();
+                info!("Processing {}", render.input_filename);
                 let progress = ProgressBar::new(100);
                 progress.set_style(
                     ProgressStyle::default_bar()
                         .template(
                             format!(
-                                " [{}] {{wide_bar:.cyan/blue}} {{percent}}% {{eta_precise}}",
-                                video_path.file_name().unwrap().to_str().unwrap()
+                                " [{}] {{wide_bar:.cyan/blue}} {{percent}}% {{eta_precise}} {{prefix}} {{msg}}",
+                                render.input_filename
                             )
                             .as_str(),
                         )
                         .unwrap(),
                 );
-                Rendering::render_video(
-                    output_filepath,
-                    settings,
-                    video_path,
-                    script_path,
-                    progress,
-                )
-                .expect("Render thread failed");
+                let job =
+                    Rendering::render_video(render, self.remux_only, self.keep_going, progress)
+                        .expect("Render thread failed");
+                match job.status.as_str() {
+                    "finished" => {}
+                    "corrupt" => self
+                        .failed
+                        .push(format!("{} (corrupt output)", render.input_filename)),
+                    _ => self.failed.push(render.input_filename.clone()),
+                }
+                self.jobs.push(job);
+            }
+            if !self.failed.is_empty() {
+                error!(
+                    "Rendered {} of {} video(s), failed: {}",
+                    total - self.failed.len(),
+                    total,
+                    self.failed.join(", ")
+                );
             }
             self.queue.clear();
             self.renders_queued = false;
         }
     }
 
+    /// Renders a single video, returning its history record. Failures exit unless keep_going is
+    /// set
     pub fn render_video(
-        output_filepath: PathBuf,
-        settings: Config,
-        video_path: PathBuf,
-        script_path: PathBuf,
+        render: &Render,
+        remux_only: bool,
+        keep_going: bool,
         progress_bar: ProgressBar,
-    ) -> Result<(), std::io::Error> {
-        let video_clone = video_path.clone();
+    ) -> Result<Job, std::io::Error> {
+        let video_path = &render.video_path;
+        let settings = &render.settings;
 
-        let ffmpeg_settings = Rendering::build_ffmpeg_command(
-            &script_path,
-            &video_clone,
-            &output_filepath,
-            settings,
-        )?;
+        // a target size is met by encoding twice at the bitrate that fits the length, the first
+        // pass only gathering the stats the second is encoded with
+        let passes: Vec<Pass> = if settings.encoding.target_size_mb > 0.0 {
+            match target_size::bitrate(render) {
+                Ok(bitrate) => {
+                    info!(
+                        "Encoding {} at {} kbit/s in two passes to fit in {} MB",
+                        render.input_filename, bitrate, settings.encoding.target_size_mb
+                    );
+                    Pass::both(render, bitrate).into()
+                }
+                Err(error) => {
+                    error!(
+                        "Could not fit {} in {} MB: {}",
+                        render.input_filename, settings.encoding.target_size_mb, error
+                    );
+                    let output = render.output_filepath.display().to_string();
+                    return Ok(render.fail(
+                        &output,
+                        "failed",
+                        std::time::Duration::ZERO,
+                        keep_going,
+                    ));
+                }
+            }
+        } else {
+            vec![]
+        };
+        let build =
+            |pass: Option<&Pass>| Rendering::build_ffmpeg_command(render, remux_only, None, pass);
+        let ffmpeg_settings = build(passes.last())?;
+        let first_pass = passes.first().map(|pass| build(Some(pass))).transpose()?;
 
         debug!(
             "Starting processes with {} {} | {} {}",
@@ -129,73 +531,319 @@ impl Rendering {
             ffmpeg_settings.ffmpeg_args.join(" ")
         );
 
+        debug!("Rendering {} with {:?}", video_path.display(), settings);
+
+        events::listen();
+        events::emit(json!({
+            "event": "started",
+            "input": video_path,
+            "output": ffmpeg_settings.output_filename,
+        }));
+
         let now = std::time::Instant::now();
         let filename = ffmpeg_settings.output_filename.clone();
-        let process = exec(ffmpeg_settings, progress_bar);
-        if !process.success() {
-            error!("Processing failed");
-            helpers::exit(exitcode::SOFTWARE);
+        let ffmpeg_exe = ffmpeg_settings.ffmpeg_exe.clone();
+        let chunked = settings.advanced.chunks > 1
+            && !remux_only
+            // the joined chunks are copied, which leaves no encode to stitch in
+            && settings.output.intro.is_empty()
+            && settings.output.outro.is_empty()
+            && !is_animated(&render.output_filepath)
+            && Container::of(&render.output_filepath) != Container::PngSequence
+            // the passes need the stats of the whole video
+            && passes.is_empty();
+        let encode = |ffmpeg_settings: CommandWithArgs, progress_bar: ProgressBar| {
+            if chunked {
+                return chunks::render(render, ffmpeg_settings, progress_bar);
+            }
+            if let Some(first_pass) = &first_pass {
+                let status = exec(first_pass.clone(), progress_bar.clone());
+                if !status.success() {
+                    return Ok(status);
+                }
+                progress_bar.reset();
+            }
+            Ok(exec(ffmpeg_settings, progress_bar))
+        };
+        if let Some(shortage) = disk::shortage(render, remux_only, chunked) {
+            match settings.advanced.disk_check.to_lowercase().as_str() {
+                "refuse" => {
+                    error!("Not enough disk space, {}", shortage);
+                    return Ok(render.fail(&filename, "failed", now.elapsed(), keep_going));
+                }
+                "warn" => warn!("Disk space may run out, {}", shortage),
+                _ => {}
+            }
         }
-        eprintln!(
+        if Container::of(Path::new(&filename)) == Container::PngSequence {
+            if let Some(folder) = Path::new(&filename).parent() {
+                std::fs::create_dir_all(folder)?;
+            }
+        }
+        let monitor = Monitor::start(&settings.monitoring, progress_bar.clone());
+        let mut process = encode(ffmpeg_settings.clone(), progress_bar.clone())?;
+        let mut corrupt = None;
+        if process.success() && settings.output.verify {
+            corrupt = probe::verify(&ffmpeg_exe, Path::new(&filename)).err();
+            if let Some(error) = &corrupt {
+                // usually a hiccup like a full disk, so it gets one more go
+                warn!("{} is corrupt ({}), rendering it again", filename, error);
+                progress_bar.reset();
+                process = encode(ffmpeg_settings, progress_bar)?;
+                corrupt = if process.success() {
+                    probe::verify(&ffmpeg_exe, Path::new(&filename)).err()
+                } else {
+                    None
+                };
+            }
+        }
+        if let Some(pass) = passes.first() {
+            pass.clean();
+        }
+        if let Some(reason) = monitor.and_then(Monitor::finish) {
+            error!("Stopped rendering, {}", reason);
+            return Ok(render.fail(&filename, "failed", now.elapsed(), keep_going));
+        }
+        if !process.success() || corrupt.is_some() {
+            let status = match &corrupt {
+                Some(error) => {
+                    error!("{} is corrupt: {}", filename, error);
+                    "corrupt"
+                }
+                None => {
+                    error!("Processing failed");
+                    "failed"
+                }
+            };
+            return Ok(render.fail(&filename, status, now.elapsed(), keep_going));
+        }
+        let mut metrics = Metrics::default();
+        if !settings.output.metrics.is_empty() {
+            // joined clips, image sequences and animations have no single video to compare with
+            if render.joined.is_empty()
+                && !sequence::is_sequence(video_path)
+                && !is_animated(&render.output_filepath)
+                && Container::of(&render.output_filepath) != Container::PngSequence
+            {
+                match metrics::measure(&ffmpeg_exe, video_path, Path::new(&filename), settings) {
+                    Ok(measured) => {
+                        info!("Quality of {}: {}", filename, measured);
+                        metrics = measured;
+                    }
+                    Err(error) => warn!("Could not measure the quality of {}: {}", filename, error),
+                }
+            } else {
+                warn!(
+                    "Not measuring the quality of {}, it can't be compared to its input",
+                    filename
+                );
+            }
+        }
+        if let Err(error) = sidecar::write(Path::new(&filename), video_path, settings) {
+            warn!(
+                "Could not write sidecar metadata for {}: {}",
+                filename, error
+            );
+        }
+        if !settings.output.thumbnail.is_empty() {
+            match thumbnail::write(&ffmpeg_exe, Path::new(&filename), &settings.output) {
+                Ok(path) => debug!("Saved thumbnail {}", path.display()),
+                Err(error) => warn!("Could not save a thumbnail of {}: {}", filename, error),
+            }
+        }
+        if !settings.upload.provider.is_empty() {
+            match upload::upload(&settings.upload, Path::new(&filename)) {
+                Ok(url) => {
+                    info!("Uploaded {} to {}", filename, url);
+                    events::emit(json!({ "event": "uploaded", "output": filename, "url": url }));
+                }
+                Err(error) => warn!("Could not upload {}: {}", filename, error),
+            }
+        }
+        info!(
             "Finished processing {} to {} in {}",
             video_path.file_name().unwrap().to_str().unwrap(),
             filename,
             indicatif::HumanDuration(now.elapsed())
         );
-        clean(video_clone, script_path);
-        Ok(())
+        let mut job = render.job(Path::new(&filename), "finished", now.elapsed());
+        job.metrics = metrics;
+        history::record(&job);
+        events::emit(json!({
+            "event": "finished",
+            "input": video_path,
+            "output": filename,
+            "elapsed": now.elapsed().as_secs_f64(),
+        }));
+        notifications::render_finished(settings, &render.input_filename, &filename, now.elapsed());
+        render.clean();
+        helpers::set_title("Teres");
+        Ok(job)
     }
 
     pub fn build_ffmpeg_command(
-        script_path: &Path,
-        video_path: &Path,
-        output_path: &Path,
-        settings: Config,
+        render: &Render,
+        remux_only: bool,
+        chunks_list: Option<&Path>,
+        two_pass: Option<&Pass>,
     ) -> Result<CommandWithArgs, std::io::Error> {
-        let mut vspipe_path = "vspipe";
-        let mut ffmpeg_path = "ffmpeg";
-        let vspipe_exe;
-        let ffmpeg_exe;
-
-        if used_installer()? {
-            let exepath = std::env::current_exe()?;
-            let path = exepath.parent().unwrap();
-            vspipe_exe = format!("{}/lib/vapoursynth/VSPipe.exe", path.to_str().unwrap());
-            vspipe_path = vspipe_exe.as_str();
-            ffmpeg_exe = format!("{}/lib/ffmpeg/ffmpeg.exe", path.to_str().unwrap());
-            ffmpeg_path = ffmpeg_exe.as_str();
-        }
-
-        let pipe_args = vec![
-            script_path.to_str().unwrap().to_string(),
-            "-".to_string(),
-            "-p".to_string(),
-            "-c".to_string(),
-            "y4m".to_string(),
-        ];
-
-        let infile = video_path.display().to_string();
-
-        let mut ffmpeg_command = vec![
-            "-loglevel",
-            "error",
-            "-hide_banner",
-            "-nostats",
-            "-i",
-            "-",
-            "-i",
-            infile.as_str(),
-            "-map",
-            "0:v",
-            "-map",
-            "1:a?",
-        ];
+        let script_path = &render.script_path;
+        let inputs = render.inputs();
+        let output_path = &render.output_filepath;
+        let media_info = render.media_info.as_ref();
+        let mut settings = render.settings.clone();
+        if two_pass.is_some() {
+            // only the software encoders take the stats of an earlier pass
+            settings.advanced.encoding.gpu = false;
+        }
+        // the first pass only writes the stats, its output is thrown away
+        let stats_only = matches!(two_pass, Some(pass) if pass.number == 1);
+        let vspipe_exe = binaries::vspipe();
+        let ffmpeg_exe = binaries::ffmpeg();
+        let vspipe_path = vspipe_exe.as_str();
+        let ffmpeg_path = ffmpeg_exe.as_str();
+
+        let pipe_args = if remux_only || chunks_list.is_some() {
+            vec![]
+        } else {
+            let mut pipe_args = vec![
+                script_path.to_str().unwrap().to_string(),
+                "-".to_string(),
+                "-p".to_string(),
+                "-c".to_string(),
+                "y4m".to_string(),
+            ];
+            pipe_args.extend(settings.advanced.vspipe_args.iter().cloned());
+            pipe_args
+        };
+
+        let infiles: Vec<String> = inputs
+            .iter()
+            .map(|input| input.display().to_string())
+            .collect();
+        let joined = infiles.len() > 1;
+        let container = Container::of(output_path);
+        let animated = container.is_animated();
+        let frames = container == Container::PngSequence;
+        // gif, webp and png sequences can't hold audio and image sequences have none
+        let audio =
+            settings.audio.enabled && !animated && !frames && !sequence::is_sequence(&inputs[0]);
+        let music = if !audio || settings.audio.music_path.is_empty() {
+            None
+        } else {
+            Some(settings.audio.music_path.clone())
+        };
+        // intros and outros are joined to the rendered video while encoding
+        let stitched: &[Clip] = if remux_only || animated || chunks_list.is_some() {
+            &[]
+        } else {
+            &render.stitched
+        };
+        let has_audio = !matches!(media_info, Some(info) if info.audio.is_none());
+        // without any audio for the rendered part the stitched clips lose theirs too
+        let stitched_audio = audio && !stitched.is_empty() && (has_audio || music.is_some());
+        // the audio goes through a filter graph when it is made from several tracks
+        let mixed = audio && (joined || music.is_some() || stitched_audio);
+        // remuxing re-times the copied video stream instead of going through vapoursynth
+        let timestamp_scale = (settings.timescale.input / settings.timescale.output).to_string();
+
+        let mut ffmpeg_command = vec!["-loglevel", "error", "-hide_banner", "-nostats"];
+        let device_args = hardware_device_args(&settings);
+        if !remux_only && chunks_list.is_none() {
+            ffmpeg_command.extend(device_args.iter().map(String::as_str));
+        }
+        if remux_only {
+            ffmpeg_command.append(&mut vec![
+                "-itsscale",
+                timestamp_scale.as_str(),
+                "-i",
+                infiles[0].as_str(),
+                "-map",
+                "0:v",
+            ]);
+            if audio && !mixed {
+                ffmpeg_command.append(&mut vec!["-map", "0:a?"]);
+            }
+        } else {
+            if let Some(list) = chunks_list {
+                // rendered chunks are joined without reencoding
+                ffmpeg_command.append(&mut vec![
+                    "-f",
+                    "concat",
+                    "-safe",
+                    "0",
+                    "-i",
+                    list.to_str().unwrap(),
+                ]);
+            } else {
+                ffmpeg_command.append(&mut vec!["-i", "-"]);
+            }
+            // the inputs are only needed for their audio
+            if audio {
+                for infile in &infiles {
+                    ffmpeg_command.append(&mut vec!["-i", infile.as_str()]);
+                }
+            }
+            if stitched.is_empty() {
+                ffmpeg_command.append(&mut vec!["-map", "0:v"]);
+            }
+            if audio && !mixed {
+                ffmpeg_command.append(&mut vec!["-map", "1:a?"]);
+            }
+        }
+        if let Some(music) = &music {
+            // looped so it lasts as long as the video, which -shortest then cuts it to
+            ffmpeg_command.append(&mut vec!["-stream_loop", "-1", "-i", music.as_str()]);
+        }
+        let stitched_inputs: Vec<String> = stitched
+            .iter()
+            .map(|(_, path, _)| path.display().to_string())
+            .collect();
+        for input in &stitched_inputs {
+            ffmpeg_command.append(&mut vec!["-i", input.as_str()]);
+        }
         // audio filters
+        let sample_rate = media_info.map_or(48000, |info| info.sample_rate());
         let mut audio_filters = String::new();
         if settings.timescale.input != 1.0 {
             // asetrate: speed up and change pitch
-            audio_filters +=
-                format!("asetrate=48000*{}", (1.0 / settings.timescale.input)).as_str();
+            audio_filters += format!(
+                "asetrate={}*{}",
+                sample_rate,
+                (1.0 / settings.timescale.input)
+            )
+            .as_str();
+        }
+
+        // the video is reversed in the script, which remuxing skips
+        if !remux_only {
+            let mut transform = vec![];
+            if settings.transform.reverse {
+                transform.push("areverse");
+            }
+            if settings.transform.pingpong {
+                transform.push(
+                    "asplit[forwards][backwards];[backwards]areverse[reversed];[forwards][reversed]concat=n=2:v=0:a=1",
+                );
+            }
+            if !transform.is_empty() {
+                if !audio_filters.is_empty() {
+                    audio_filters += ",";
+                }
+                audio_filters += &transform.join(",");
+            }
+        }
+
+        let segments = settings.timescale.segments();
+        if !segments.is_empty() && !remux_only {
+            if !audio_filters.is_empty() {
+                audio_filters += ",";
+            }
+            audio_filters += &speed_ramp(
+                &segments,
+                sample_rate,
+                settings.timescale.adjust_audio_pitch,
+            );
         }
 
         if settings.timescale.output != 1.0 {
@@ -203,77 +851,243 @@ impl Rendering {
                 audio_filters += ",";
             }
             if settings.timescale.adjust_audio_pitch {
-                audio_filters += format!("asetrate=48000*{}", settings.timescale.output).as_str();
+                audio_filters +=
+                    format!("asetrate={}*{}", sample_rate, settings.timescale.output).as_str();
             } else {
                 // atempo: speed up without changing pitch
                 audio_filters += format!("atempo={}", settings.timescale.output).as_str();
             }
         }
 
-        let formatted_audio;
-        if !audio_filters.is_empty() {
-            ffmpeg_command.push("-af");
-            formatted_audio = audio_filters;
-            ffmpeg_command.push(formatted_audio.as_str());
+        if audio && settings.audio.normalize && has_audio {
+            let filter = format!("loudnorm={}", LOUDNORM_TARGET);
+            // measured once for all the commands of the render, like each pass of a target size
+            let loudness = render
+                .loudness
+                .get_or_init(|| probe::loudness(ffmpeg_path, &inputs, &filter));
+            match loudness {
+                Some(loudness) => {
+                    if !audio_filters.is_empty() {
+                        audio_filters += ",";
+                    }
+                    // loudnorm resamples to 192kHz, so go back to the source rate
+                    audio_filters += format!(
+                        "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true,aresample={}",
+                        filter,
+                        loudness.integrated,
+                        loudness.true_peak,
+                        loudness.range,
+                        loudness.threshold,
+                        loudness.offset,
+                        sample_rate
+                    )
+                    .as_str();
+                }
+                None => warn!("Could not measure the loudness of the audio, leaving it as is"),
+            }
         }
 
-        let quality = &settings.encoding.quality.to_string();
-        let custom_ffmpeg = settings
-            .advanced
-            .encoding
-            .custom_ffmpeg_filters
-            .unwrap_or_else(|| String::from("~"));
-        if custom_ffmpeg != String::from("~") {
-            ffmpeg_command.push(&custom_ffmpeg);
+        // every filter chain that isn't a simple -af or -vf, joined into a single graph
+        let mut graph: Vec<String> = vec![];
+        let copy_audio = audio_filters.is_empty()
+            && !mixed
+            && !matches!(
+                media_info.and_then(|info| info.audio.as_ref()),
+                Some(audio) if !container.can_copy_audio(&audio.codec)
+            );
+        let audio_label = if stitched.is_empty() {
+            "[audio]"
         } else {
-            // video format
-            if settings.advanced.encoding.gpu {
-                if settings.advanced.encoding.gpu_type.to_lowercase() == "nvidia" {
-                    ffmpeg_command.push("-c:v");
-                    ffmpeg_command.push("h264_nvenc");
-                    ffmpeg_command.push("-preset");
-                    ffmpeg_command.push("p7");
-                    ffmpeg_command.push("-qp");
-                    ffmpeg_command.push(quality);
-                } else if settings.advanced.encoding.gpu_type.to_lowercase() == "amd" {
-                    ffmpeg_command.push("-c:v");
-                    ffmpeg_command.push("h264_amf");
-                    ffmpeg_command.push("-qp_i");
-                    ffmpeg_command.push(quality);
-                    ffmpeg_command.push("-qp_b");
-                    ffmpeg_command.push(quality);
-                    ffmpeg_command.push("-qp_p");
-                    ffmpeg_command.push(quality);
-                    ffmpeg_command.push("-quality");
-                    ffmpeg_command.push("quality");
-                } else if settings.advanced.encoding.gpu_type.to_lowercase() == "intel" {
-                    ffmpeg_command.append(&mut vec![
-                        "-c:v",
-                        "h264_qsv",
-                        "-global_quality",
-                        quality,
-                        "-preset",
-                        "veryslow",
-                    ]);
+            "[body_audio]"
+        };
+        if mixed {
+            let audio_input = if remux_only { 0 } else { 1 };
+            let mut track = if joined {
+                // the audio of every input is joined in order, then processed like a single track
+                let streams: String = (audio_input..audio_input + infiles.len())
+                    .map(|input| format!("[{}:a]", input))
+                    .collect();
+                format!("{}concat=n={}:v=0:a=1", streams, infiles.len())
+            } else {
+                format!("[{}:a]anull", audio_input)
+            };
+            if !audio_filters.is_empty() {
+                track += ",";
+                track += &audio_filters;
+            }
+            graph.push(match &music {
+                Some(_) => {
+                    let music = format!(
+                        "[{}:a]volume={}",
+                        audio_input + infiles.len(),
+                        settings.audio.music_volume
+                    );
+                    if has_audio {
+                        format!(
+                            "{}[main];{}[music];[main][music]amix=inputs=2:duration=first:normalize=0{}",
+                            track, music, audio_label
+                        )
+                    } else {
+                        format!("{}{}", music, audio_label)
+                    }
                 }
+                None => format!("{}{}", track, audio_label),
+            });
+            if stitched.is_empty() {
+                ffmpeg_command.append(&mut vec!["-map", "[audio]"]);
+            }
+            if music.is_some() {
+                // the music input never ends, so the output ends with the video instead
+                ffmpeg_command.push("-shortest");
+            }
+        }
+
+        if !stitched.is_empty() {
+            graph.append(&mut stitch(
+                stitched,
+                // the pipe, the inputs when kept for their audio and the music come first
+                1 + if audio { infiles.len() } else { 0 } + usize::from(music.is_some()),
+                &video_filters(&settings),
+                hardware_upload(&settings),
+                stitched_audio,
+                sample_rate,
+            ));
+            ffmpeg_command.append(&mut vec!["-map", "[video]"]);
+            if stitched_audio {
+                ffmpeg_command.append(&mut vec!["-map", "[audio]"]);
+            }
+        }
+
+        let filter_graph = graph.join(";");
+        if !graph.is_empty() {
+            ffmpeg_command.append(&mut vec!["-filter_complex", filter_graph.as_str()]);
+        }
+
+        // the untouched audio goes in a second track, only worth it when the first is processed
+        let original_audio = audio
+            && settings.audio.keep_original
+            && !copy_audio
+            && !joined
+            && stitched.is_empty()
+            && has_audio;
+        if original_audio {
+            ffmpeg_command.append(&mut vec!["-map", if remux_only { "0:a?" } else { "1:a?" }]);
+        }
+
+        if audio && !mixed && !audio_filters.is_empty() {
+            ffmpeg_command.push(if original_audio { "-filter:a:0" } else { "-af" });
+            ffmpeg_command.push(audio_filters.as_str());
+        }
+
+        let video_filter = video_filter_args(&settings);
+        let animated_args = animated_args(&settings, output_path);
+        let png_sequence_args = png_sequence_args(&settings);
+        let video_codec = match two_pass {
+            Some(pass) => pass.codec_args(&settings),
+            None => video_codec_args(&settings),
+        };
+        let metadata = if settings.output.embed_settings && !animated && !frames && !stats_only {
+            metadata_args(&settings)
+        } else {
+            vec![]
+        };
+        if remux_only {
+            if two_pass.is_some() {
+                ffmpeg_command.extend(video_codec.iter().map(String::as_str));
+            } else {
+                ffmpeg_command.append(&mut vec!["-c:v", "copy"]);
+            }
+            if !audio {
+                ffmpeg_command.push("-an");
+            } else if copy_audio {
+                ffmpeg_command.append(&mut vec!["-c:a", "copy"]);
             } else {
                 ffmpeg_command.append(&mut vec![
-                    "-c:v",
-                    "libx264",
-                    "-pix_fmt",
-                    "yuv420p",
-                    "-preset",
-                    "superfast",
-                    "-crf",
-                    quality,
+                    "-c:a",
+                    container.audio_codec(),
+                    "-b:a",
+                    container.audio_bitrate(),
                 ]);
             }
+            if !stats_only {
+                ffmpeg_command.extend(container.mux_args(!metadata.is_empty()));
+            }
+        } else {
+            // video format
+            if chunks_list.is_some() {
+                ffmpeg_command.append(&mut vec!["-c:v", "copy"]);
+            } else if animated {
+                ffmpeg_command.extend(animated_args.iter().map(String::as_str));
+            } else if frames {
+                ffmpeg_command.extend(png_sequence_args.iter().map(String::as_str));
+            } else {
+                // stitching filters the video in the graph instead
+                if stitched.is_empty() {
+                    ffmpeg_command.extend(video_filter.iter().map(String::as_str));
+                }
+                ffmpeg_command.extend(video_codec.iter().map(String::as_str));
+            }
 
             // audio format
-            ffmpeg_command.append(&mut vec!["-c:a", "aac", "-b:a", "320k"]);
+            if audio {
+                ffmpeg_command.append(&mut vec![
+                    "-c:a",
+                    container.audio_codec(),
+                    "-b:a",
+                    container.audio_bitrate(),
+                ]);
+            } else {
+                ffmpeg_command.push("-an");
+            }
 
             // extra
-            ffmpeg_command.append(&mut vec!["-movflags", "+faststart"]);
+            if !stats_only {
+                ffmpeg_command.extend(container.mux_args(!metadata.is_empty()));
+            }
+        }
+
+        if original_audio {
+            // reencoded when the container can't hold the original codec
+            let can_copy = matches!(
+                media_info.and_then(|info| info.audio.as_ref()),
+                Some(audio) if container.can_copy_audio(&audio.codec)
+            );
+            ffmpeg_command.append(&mut vec![
+                "-c:a:1",
+                if can_copy {
+                    "copy"
+                } else {
+                    container.audio_codec()
+                },
+                "-metadata:s:a:0",
+                "title=Processed",
+                "-metadata:s:a:1",
+                "title=Original",
+            ]);
+        }
+
+        ffmpeg_command.extend(metadata.iter().map(String::as_str));
+
+        if settings.advanced.deterministic {
+            // leaves out the encoder versions and other details that change between builds
+            ffmpeg_command.append(&mut vec![
+                "-fflags",
+                "+bitexact",
+                "-flags:v",
+                "+bitexact",
+                "-flags:a",
+                "+bitexact",
+            ]);
+        }
+
+        let threads = settings.advanced.threads.to_string();
+        if settings.advanced.threads > 0 {
+            ffmpeg_command.append(&mut vec!["-threads", threads.as_str()]);
+        }
+
+        // the chunks were encoded with them already
+        if chunks_list.is_none() {
+            ffmpeg_command.extend(settings.encoding.extra_args.iter().map(String::as_str));
         }
 
         // output
@@ -289,7 +1103,7 @@ impl Rendering {
                     settings.interpolation.fps,
                     settings.advanced.interpolation.program,
                     settings.blending.output_fps,
-                    settings.blending.amount
+                    settings.blending.effective_amount()
                 )
                 .as_str(),
             )
@@ -298,7 +1112,11 @@ impl Rendering {
         } else {
             output_path.display().to_string()
         };
-        ffmpeg_command.push(outfile.as_str());
+        if stats_only {
+            ffmpeg_command.extend(["-f", "null", "-"]);
+        } else {
+            ffmpeg_command.push(outfile.as_str());
+        }
         debug!("{:?}", ffmpeg_command);
 
         let ffmpeg_args: Vec<String> = ffmpeg_command.iter().map(|n| n.to_string()).collect();
@@ -310,6 +1128,591 @@ impl Rendering {
             vspipe_args: pipe_args,
 
             output_filename: outfile,
+
+            process_priority: settings.advanced.process_priority.clone(),
+            cpu_affinity: settings.advanced.cpu_affinity.clone(),
+        })
+    }
+}
+
+/// Audio filters playing each segment at its speed, matching the frames picked by retime.py
+fn speed_ramp(
+    segments: &[(f32, Option<f32>, f32)],
+    sample_rate: u32,
+    adjust_pitch: bool,
+) -> String {
+    let split: String = (0..segments.len())
+        .map(|index| format!("[ramp{}]", index))
+        .collect();
+    let mut filters = vec![format!("asplit={}{}", segments.len(), split)];
+    for (index, (start, end, speed)) in segments.iter().enumerate() {
+        let trim = match end {
+            Some(end) => format!("atrim=start={}:end={}", start, end),
+            None => format!("atrim=start={}", start),
+        };
+        let tempo = if adjust_pitch {
+            // every segment has to end up at the same rate to be joined
+            format!(
+                "asetrate={}*{},aresample={}",
+                sample_rate, speed, sample_rate
+            )
+        } else {
+            // atempo only goes down to half speed, so slower speeds take several
+            let mut tempo = vec![];
+            let mut remaining = *speed;
+            while remaining < 0.5 {
+                tempo.push(String::from("atempo=0.5"));
+                remaining /= 0.5;
+            }
+            tempo.push(format!("atempo={}", remaining));
+            tempo.join(",")
+        };
+        filters.push(format!(
+            "[ramp{}]{},asetpts=PTS-STARTPTS,{}[ramped{}]",
+            index, trim, tempo, index
+        ));
+    }
+    let joined: String = (0..segments.len())
+        .map(|index| format!("[ramped{}]", index))
+        .collect();
+    filters.push(format!("{}concat=n={}:v=0:a=1", joined, segments.len()));
+    filters.join(";")
+}
+
+/// Whether the output is an animated gif or webp instead of a video
+pub fn is_animated(output_path: &Path) -> bool {
+    Container::of(output_path).is_animated()
+}
+
+/// Tags describing how the output was made, a summary in the comment players show and every
+/// setting as json so the render can be told apart and repeated later
+fn metadata_args(settings: &Config) -> Vec<String> {
+    let mut summary = vec![];
+    if settings.interpolation.enabled {
+        summary.push(format!(
+            "interpolated to {}fps with {}",
+            settings.interpolation.fps, settings.advanced.interpolation.program
+        ));
+    }
+    if settings.blending.enabled {
+        summary.push(format!(
+            "blurred to {}fps at {} amount with {} weighting",
+            settings.blending.output_fps, settings.blending.amount, settings.blending.weighting
+        ));
+    }
+    if settings.timescale.input != 1.0 || settings.timescale.output != 1.0 {
+        summary.push(format!(
+            "timescale {} in {} out",
+            settings.timescale.input, settings.timescale.output
+        ));
+    }
+    let mut args = vec![
+        String::from("-metadata"),
+        format!(
+            "comment=Rendered with teres {}: {}",
+            env!("CARGO_PKG_VERSION"),
+            summary.join(", ")
+        ),
+        String::from("-metadata"),
+        format!("teres_version={}", env!("CARGO_PKG_VERSION")),
+    ];
+    if let Ok(fingerprint) = serde_json::to_string(&settings.shareable()) {
+        args.push(String::from("-metadata"));
+        args.push(format!("teres_settings={}", fingerprint));
+    }
+    args
+}
+
+/// Arguments writing each frame as a png, with the transparency when it is kept
+fn png_sequence_args(settings: &Config) -> Vec<String> {
+    let filters = video_filters(settings);
+    let mut args = vec![];
+    if !filters.is_empty() {
+        args.push(String::from("-vf"));
+        args.push(filters.join(","));
+    }
+    args.extend([
+        String::from("-c:v"),
+        String::from("png"),
+        String::from("-pix_fmt"),
+        String::from(if settings.encoding.preserve_alpha {
+            "rgba"
+        } else {
+            "rgb24"
+        }),
+    ]);
+    args
+}
+
+/// Arguments encoding a looping gif with a palette made for the clip, or an animated webp, at
+/// a capped frame rate and width
+fn animated_args(settings: &Config, output_path: &Path) -> Vec<String> {
+    let encoding = &settings.encoding;
+    let mut filters = video_filters(settings);
+    filters.push(format!("fps={}", encoding.animated_fps));
+    if encoding.animated_width > 0 {
+        filters.push(format!(
+            "scale='min(iw,{})':-2:flags=lanczos",
+            encoding.animated_width
+        ));
+    }
+
+    let gif = output_path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+    let mut args = if gif {
+        // a palette per clip instead of the generic 256 colors, dithered to hide banding
+        filters.push(String::from(
+            "split[frames][palette_frames];[palette_frames]palettegen=stats_mode=diff[palette];[frames][palette]paletteuse=dither=bayer:bayer_scale=3",
+        ));
+        vec![String::from("-vf"), filters.join(",")]
+    } else {
+        // crf 0 to 51 mapped to webp's quality of 100 to 0
+        let quality = (51 - encoding.quality.clamp(0, 51)) * 100 / 51;
+        vec![
+            String::from("-vf"),
+            filters.join(","),
+            String::from("-c:v"),
+            String::from("libwebp_anim"),
+            String::from("-quality"),
+            quality.to_string(),
+        ]
+    };
+    args.append(&mut vec![String::from("-loop"), String::from("0")]);
+    args
+}
+
+/// `-vf` arguments for the color grade and the upload to the gpu, if any
+pub fn video_filter_args(settings: &Config) -> Vec<String> {
+    let mut filters = video_filters(settings);
+    if let Some(upload) = hardware_upload(settings) {
+        filters.push(upload.to_string());
+    }
+    if filters.is_empty() {
+        vec![]
+    } else {
+        vec![String::from("-vf"), filters.join(",")]
+    }
+}
+
+fn vaapi(settings: &Config) -> bool {
+    settings.advanced.encoding.gpu
+        && !settings.encoding.preserve_alpha
+        && !settings.encoding.vp9()
+        && settings
+            .advanced
+            .encoding
+            .gpu_type
+            .eq_ignore_ascii_case("vaapi")
+}
+
+/// Arguments opening the gpu before the inputs, which vaapi encoding needs
+pub fn hardware_device_args(settings: &Config) -> Vec<String> {
+    if vaapi(settings) {
+        vec![
+            String::from("-vaapi_device"),
+            settings.advanced.encoding.vaapi_device.clone(),
+        ]
+    } else {
+        vec![]
+    }
+}
+
+/// Last filter of the video for encoders that take frames in gpu memory
+fn hardware_upload(settings: &Config) -> Option<&'static str> {
+    vaapi(settings).then_some("format=nv12,hwupload")
+}
+
+/// Color grade and LUT filters for the video stream, done by ffmpeg as vapoursynth has no
+/// built in LUT support
+fn video_filters(settings: &Config) -> Vec<String> {
+    let color = &settings.color;
+    let mut filters = vec![];
+    if color.saturation != 1.0 || color.contrast != 1.0 || color.gamma != 1.0 {
+        filters.push(format!(
+            "eq=saturation={}:contrast={}:gamma={}",
+            color.saturation, color.contrast, color.gamma
+        ));
+    }
+    if !color.lut_path.is_empty() {
+        // quoted so the filter graph keeps the path as is, with colons escaped for the option
+        let path = Path::new(&color.lut_path)
+            .canonicalize()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|_| color.lut_path.clone());
+        filters.push(format!(
+            "lut3d=file='{}'",
+            path.replace('\\', "/").replace(':', "\\:")
+        ));
+    }
+    if let Some(custom) = settings.advanced.encoding.custom_filters() {
+        filters.push(custom.to_string());
+    }
+    if !filters.is_empty() {
+        // the encoders expect yuv again after the rgb LUT
+        filters.push(if settings.encoding.preserve_alpha {
+            String::from("format=yuva444p")
+        } else {
+            format!("format={}", settings.encoding.pixel_format.to_lowercase())
+        });
+    }
+    filters
+}
+
+/// Filter graph joining the intro and outro to the rendered video, into `[video]` and `[audio]`.
+/// The clips are scaled to the size of the video and get silence when they have no audio
+fn stitch(
+    clips: &[Clip],
+    first_input: usize,
+    video_filters: &[String],
+    upload: Option<&str>,
+    audio: bool,
+    sample_rate: u32,
+) -> Vec<String> {
+    let mut graph = vec![format!(
+        "[0:v]{},setsar=1[body]",
+        if video_filters.is_empty() {
+            String::from("null")
+        } else {
+            video_filters.join(",")
+        }
+    )];
+    let mut body = String::from("body");
+    let mut parts = vec![];
+    for (index, (name, _, info)) in clips.iter().enumerate() {
+        let input = first_input + index;
+        let reference = format!("{}_reference", name);
+        graph.push(format!(
+            "[{}:v][{}]scale2ref[{}_scaled][{}]",
+            input, body, name, reference
+        ));
+        graph.push(format!(
+            "[{0}_scaled]setsar=1,format=yuv420p[{0}_video]",
+            name
+        ));
+        body = reference;
+
+        if audio {
+            graph.push(match info {
+                Some(info) if info.audio.is_none() => format!(
+                    "anullsrc=r={}:cl=stereo,atrim=duration={}[{}_audio]",
+                    sample_rate, info.duration, name
+                ),
+                _ => format!("[{}:a]aresample={}[{}_audio]", input, sample_rate, name),
+            });
+        }
+        parts.push(*name);
+    }
+
+    // the intro goes before the rendered video and the outro after it
+    let position = usize::from(parts.first() == Some(&"intro"));
+    parts.insert(position, "body");
+    let segments: String = parts
+        .iter()
+        .map(|part| {
+            let (video, audio_label) = if *part == "body" {
+                (format!("[{}]", body), String::from("[body_audio]"))
+            } else {
+                (format!("[{}_video]", part), format!("[{}_audio]", part))
+            };
+            if audio {
+                video + &audio_label
+            } else {
+                video
+            }
         })
+        .collect();
+    graph.push(format!(
+        "{}concat=n={}:v=1:a={}[{}]{}",
+        segments,
+        parts.len(),
+        u8::from(audio),
+        if upload.is_some() { "joined" } else { "video" },
+        if audio { "[audio]" } else { "" }
+    ));
+    if let Some(upload) = upload {
+        graph.push(format!("[joined]{}[video]", upload));
+    }
+    graph
+}
+
+/// Encoder arguments for the video stream, tagged with the BT.709 limited range the script
+/// converts to
+pub fn video_codec_args(settings: &Config) -> Vec<String> {
+    if let Some(custom) = settings.advanced.encoding.custom_args() {
+        return custom
+            .into_iter()
+            .chain(COLOR_TAGS.iter().map(|arg| arg.to_string()))
+            .collect();
+    }
+    let quality = settings.encoding.quality.to_string();
+    let codec = settings.encoding.codec.to_lowercase();
+    // videotoolbox takes 1 to 100 with higher being better, about matching crf at 2 per step
+    let videotoolbox_quality = (100 - settings.encoding.quality * 2)
+        .clamp(1, 100)
+        .to_string();
+    let encoder = |suffix: &str| format!("{}_{}", codec, suffix);
+    let (nvenc, amf, qsv, videotoolbox, vaapi) = (
+        encoder("nvenc"),
+        encoder("amf"),
+        encoder("qsv"),
+        encoder("videotoolbox"),
+        encoder("vaapi"),
+    );
+    let encoder_gpu_index = settings.advanced.performance.encoder_gpu_index.to_string();
+    // without a preset, software encoding favours speed and the gpus their best quality
+    let (software_preset, nvenc_preset, qsv_preset, amf_quality) = (
+        settings.encoding.preset_or("superfast"),
+        settings.encoding.preset_or("p7"),
+        settings.encoding.preset_or("veryslow"),
+        settings.encoding.preset_or("quality"),
+    );
+    let software = if codec == "hevc" {
+        "libx265"
+    } else {
+        "libx264"
+    };
+    let cpu_used = vp9_cpu_used(&settings.encoding.preset_or("veryfast")).to_string();
+    // a ceiling on the crf, vp9 takes it as the target of its constrained quality mode
+    let max_bitrate = settings.encoding.max_bitrate_kbps;
+    let (maxrate, bufsize) = (format!("{}k", max_bitrate), format!("{}k", max_bitrate * 2));
+    let vp9_bitrate = if max_bitrate > 0 {
+        maxrate.as_str()
+    } else {
+        "0"
+    };
+    let mut args: Vec<&str> = if settings.encoding.preserve_alpha {
+        // only ProRes 4444 and VP9 keep the alpha, the gpu encoders drop it
+        if Container::named(&settings.encoding.container) == Container::Mov {
+            vec![
+                "-c:v",
+                "prores_ks",
+                "-profile:v",
+                "4444",
+                "-pix_fmt",
+                "yuva444p10le",
+            ]
+        } else {
+            vec![
+                "-c:v",
+                "libvpx-vp9",
+                "-pix_fmt",
+                "yuva420p",
+                "-b:v",
+                "0",
+                "-crf",
+                &quality,
+                "-row-mt",
+                "1",
+                "-cpu-used",
+                &cpu_used,
+            ]
+        }
+    } else if settings.encoding.vp9() {
+        // for the web, the gpu encoders don't make vp9
+        vec![
+            "-c:v",
+            "libvpx-vp9",
+            "-b:v",
+            vp9_bitrate,
+            "-crf",
+            &quality,
+            "-row-mt",
+            "1",
+            "-cpu-used",
+            &cpu_used,
+        ]
+    } else if settings.advanced.encoding.gpu {
+        match settings.advanced.encoding.gpu_type.to_lowercase().as_str() {
+            "nvidia" => vec!["-c:v", &nvenc, "-preset", &nvenc_preset, "-qp", &quality],
+            "amd" => vec![
+                "-c:v",
+                &amf,
+                "-qp_i",
+                &quality,
+                "-qp_b",
+                &quality,
+                "-qp_p",
+                &quality,
+                "-quality",
+                &amf_quality,
+            ],
+            "intel" => vec![
+                "-c:v",
+                &qsv,
+                "-global_quality",
+                &quality,
+                "-preset",
+                &qsv_preset,
+            ],
+            // allow_sw falls back to software encoding on macs without a hardware encoder
+            "apple" => vec![
+                "-c:v",
+                &videotoolbox,
+                "-q:v",
+                &videotoolbox_quality,
+                "-allow_sw",
+                "1",
+                "-pix_fmt",
+                "yuv420p",
+            ],
+            // the frames are uploaded to the gpu by the filters, see hardware_upload
+            "vaapi" => vec!["-c:v", &vaapi, "-rc_mode", "CQP", "-qp", &quality],
+            _ => vec![],
+        }
+    } else {
+        vec![
+            "-c:v",
+            software,
+            "-preset",
+            &software_preset,
+            "-crf",
+            &quality,
+        ]
+    };
+    if max_bitrate > 0
+        && !settings.encoding.preserve_alpha
+        && (settings.encoding.vp9() || !settings.advanced.encoding.gpu)
+    {
+        args.extend(["-maxrate", &maxrate, "-bufsize", &bufsize]);
+    }
+    if !settings.encoding.preserve_alpha {
+        args.extend(pixel_format_args(settings));
+    }
+    if args.contains(&nvenc.as_str()) && settings.advanced.performance.encoder_gpu_index >= 0 {
+        args.extend(["-gpu", &encoder_gpu_index]);
+    }
+    // quicktime and apple devices only play hevc tagged as hvc1
+    if codec == "hevc"
+        && !settings.encoding.preserve_alpha
+        && matches!(
+            Container::named(&settings.encoding.container),
+            Container::Mp4 | Container::Mov
+        )
+    {
+        args.extend(["-tag:v", "hvc1"]);
+    }
+    args.iter()
+        .chain(COLOR_TAGS.iter())
+        .map(|arg| arg.to_string())
+        .collect()
+}
+
+/// `-pix_fmt` and `-profile:v` for `encoding.pixel_format`, by the encoder they go to. The
+/// encoders left out only make 8-bit 4:2:0, which they pick on their own
+pub fn pixel_format_args(settings: &Config) -> Vec<&'static str> {
+    let encoding = &settings.encoding;
+    // 0 for yuv420p, 1 for yuv420p10le and 2 for yuv444p
+    let format = ["yuv420p", "yuv420p10le", "yuv444p"]
+        .iter()
+        .position(|format| encoding.pixel_format.eq_ignore_ascii_case(format))
+        .unwrap_or(0);
+    let hevc = encoding.codec.eq_ignore_ascii_case("hevc");
+    let (pixel_formats, profiles): ([&str; 3], [&str; 3]) = if encoding.vp9() {
+        (["yuv420p", "yuv420p10le", "yuv444p"], ["0", "2", "1"])
+    } else if settings.advanced.encoding.gpu {
+        match settings.advanced.encoding.gpu_type.to_lowercase().as_str() {
+            "nvidia" if hevc => (["yuv420p", "p010le", "yuv444p"], ["main", "main10", "rext"]),
+            // 10-bit h264 is refused when validating the config
+            "nvidia" => (
+                ["yuv420p", "p010le", "yuv444p"],
+                ["high", "high", "high444p"],
+            ),
+            "apple" => return vec!["-pix_fmt", "yuv420p"],
+            _ => return vec![],
+        }
+    } else if hevc {
+        (
+            ["yuv420p", "yuv420p10le", "yuv444p"],
+            ["main", "main10", "main444-8"],
+        )
+    } else {
+        (
+            ["yuv420p", "yuv420p10le", "yuv444p"],
+            ["high", "high10", "high444"],
+        )
+    };
+    if format == 0 && settings.advanced.encoding.gpu && !encoding.vp9() {
+        // nvenc has always been left to its defaults for 8-bit 4:2:0
+        return vec![];
+    }
+    let mut args = vec!["-pix_fmt", pixel_formats[format]];
+    if format > 0 {
+        args.extend(["-profile:v", profiles[format]]);
+    }
+    args
+}
+
+/// libvpx's speed for an x264 preset name, 5 being its fastest that still looks good
+pub fn vp9_cpu_used(preset: &str) -> u32 {
+    match preset {
+        "ultrafast" | "superfast" => 5,
+        "veryfast" | "faster" => 4,
+        "fast" => 3,
+        "medium" => 2,
+        "slow" | "slower" => 1,
+        _ => 0,
+    }
+}
+
+pub const COLOR_TAGS: [&str; 8] = [
+    "-colorspace",
+    "bt709",
+    "-color_primaries",
+    "bt709",
+    "-color_trc",
+    "bt709",
+    "-color_range",
+    "tv",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(codec: &str, pixel_format: &str, gpu: bool) -> Config {
+        let mut settings = Config::builtin(None).unwrap();
+        settings.encoding.codec = codec.to_string();
+        settings.encoding.pixel_format = pixel_format.to_string();
+        settings.advanced.encoding.gpu = gpu;
+        settings
+    }
+
+    #[test]
+    fn pixel_format_args_match_the_encoder() {
+        assert_eq!(
+            pixel_format_args(&settings("h264", "yuv420p", false)),
+            ["-pix_fmt", "yuv420p"]
+        );
+        assert_eq!(
+            pixel_format_args(&settings("h264", "yuv420p10le", false)),
+            ["-pix_fmt", "yuv420p10le", "-profile:v", "high10"]
+        );
+        assert_eq!(
+            pixel_format_args(&settings("hevc", "yuv444p", false)),
+            ["-pix_fmt", "yuv444p", "-profile:v", "main444-8"]
+        );
+        assert_eq!(
+            pixel_format_args(&settings("vp9", "yuv420p10le", false)),
+            ["-pix_fmt", "yuv420p10le", "-profile:v", "2"]
+        );
+        assert_eq!(
+            pixel_format_args(&settings("hevc", "yuv420p10le", true)),
+            ["-pix_fmt", "p010le", "-profile:v", "main10"]
+        );
+    }
+
+    #[test]
+    fn pixel_format_args_leave_8_bit_to_the_gpu() {
+        assert!(pixel_format_args(&settings("h264", "yuv420p", true)).is_empty());
+    }
+
+    #[test]
+    fn vp9_cpu_used_follows_the_preset() {
+        assert_eq!(vp9_cpu_used("superfast"), 5);
+        assert_eq!(vp9_cpu_used("veryfast"), 4);
+        assert_eq!(vp9_cpu_used("medium"), 2);
+        assert_eq!(vp9_cpu_used("veryslow"), 0);
+        assert_eq!(vp9_cpu_used("p7"), 0);
     }
 }
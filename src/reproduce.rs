@@ -0,0 +1,108 @@
+use crate::config::Config;
+use crate::helpers::exit;
+use crate::probe;
+use crate::rendering::{Render, Rendering};
+use crate::sidecar;
+use crate::teres::load_config;
+use std::path::Path;
+
+/// Renders a new input with the settings a previous output was made with, read from its
+/// metadata or else its sidecar
+pub fn run(blurred: &Path, input: &Path) {
+    if !input.exists() {
+        error!("{} does not exist", input.display());
+        exit(exitcode::NOINPUT);
+    }
+    let Some((version, reproduced)) = settings_of(blurred) else {
+        error!(
+            "{} has no settings in its metadata or a sidecar next to it, only outputs rendered with output.embed_settings or a sidecar can be reproduced",
+            blurred.display()
+        );
+        exit(exitcode::DATAERR);
+        return;
+    };
+    if version != env!("CARGO_PKG_VERSION") {
+        warn!(
+            "{} was rendered with teres {}, the result may differ with {}",
+            blurred.display(),
+            version,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+
+    // only how the blur looks and is encoded comes from the file, paths, programs, arguments and
+    // what happens after the render come from the config of this machine
+    let local = load_config(&[], None);
+    let kept = local.portable();
+    let taken: Vec<String> = reproduced
+        .portable()
+        .into_iter()
+        .filter(|item| !kept.contains(item))
+        .collect();
+    if !taken.is_empty() {
+        info!("Taking from {}: {}", blurred.display(), taken.join(", "));
+    }
+    let settings = match local.with_overrides(&taken) {
+        Ok(settings) => settings,
+        Err(error) => {
+            error!("Could not apply the reproduced settings: {}", error);
+            exit(exitcode::CONFIG);
+            return;
+        }
+    };
+
+    let errors = settings.validate();
+    if !errors.is_empty() {
+        for (key, message) in errors {
+            error!(
+                "Invalid value {} in the reproduced settings: {}",
+                key, message
+            );
+        }
+        exit(exitcode::CONFIG);
+        return;
+    }
+
+    info!(
+        "Rendering {} with the settings of {}",
+        input.display(),
+        blurred.display()
+    );
+    let mut rendering = Rendering {
+        queue: vec![],
+        renders_queued: false,
+        remux_only: false,
+        keep_going: false,
+        failed: vec![],
+        jobs: vec![],
+    };
+    match Render::new(input.to_path_buf(), settings) {
+        Some(render) => {
+            rendering.queue_render(render);
+            rendering.render_videos();
+        }
+        None => rendering.failed.push(input.display().to_string()),
+    }
+    if !rendering.failed.is_empty() {
+        exit(exitcode::SOFTWARE);
+    }
+}
+
+/// Version of teres and settings an output was rendered with
+fn settings_of(blurred: &Path) -> Option<(String, Config)> {
+    if let Some(embedded) = probe::tag(blurred, "teres_settings") {
+        match serde_json::from_str(&embedded) {
+            Ok(settings) => {
+                let version = probe::tag(blurred, "teres_version").unwrap_or_default();
+                return Some((version, settings));
+            }
+            Err(error) => warn!(
+                "Could not read the settings in the metadata of {}: {}",
+                blurred.display(),
+                error
+            ),
+        }
+    }
+    let sidecar = sidecar::read(blurred).ok()?;
+    Some((sidecar.version, sidecar.settings))
+}
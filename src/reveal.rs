@@ -0,0 +1,92 @@
+use crate::paths;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Full path of the output as the file manager shows it
+fn absolute(output: &Path) -> PathBuf {
+    let path = output
+        .canonicalize()
+        .or_else(|_| {
+            // png sequence outputs are a pattern, only their folder exists
+            let folder = output.parent().unwrap_or(Path::new(".")).canonicalize()?;
+            Ok::<PathBuf, std::io::Error>(folder.join(output.file_name().unwrap_or_default()))
+        })
+        .unwrap_or_else(|_| output.to_path_buf());
+    // windows canonicalizes to verbatim paths, which explorer doesn't take
+    paths::without_verbatim(&path)
+}
+
+/// Copies the full path of the output to the clipboard
+pub fn copy_path(output: &Path) {
+    let path = absolute(output).display().to_string();
+    for (program, args) in clipboard_commands() {
+        let child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let Ok(mut child) = child else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(path.as_bytes());
+        }
+        if child.wait().is_ok_and(|status| status.success()) {
+            debug!("Copied {} to the clipboard", path);
+            return;
+        }
+    }
+    warn!("Could not copy {} to the clipboard", path);
+}
+
+/// Opens the folder of the output in the file manager, with the output selected where the file
+/// manager supports it
+pub fn open_folder(output: &Path) {
+    let path = absolute(output);
+    let (program, args) = open_folder_command(&path);
+    // explorer exits with 1 even when it opened the folder
+    if let Err(error) = Command::new(program).args(&args).spawn() {
+        warn!("Could not open the folder of {}: {}", path.display(), error);
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn clipboard_commands() -> Vec<(&'static str, Vec<&'static str>)> {
+    // clip mangles anything but ascii, powershell reads the path as text
+    vec![(
+        "powershell",
+        vec!["-NoProfile", "-Command", "$input | Set-Clipboard"],
+    )]
+}
+
+#[cfg(target_os = "macos")]
+fn clipboard_commands() -> Vec<(&'static str, Vec<&'static str>)> {
+    vec![("pbcopy", vec![])]
+}
+
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+fn clipboard_commands() -> Vec<(&'static str, Vec<&'static str>)> {
+    vec![
+        ("wl-copy", vec![]),
+        ("xclip", vec!["-selection", "clipboard"]),
+        ("xsel", vec!["--clipboard", "--input"]),
+    ]
+}
+
+#[cfg(target_os = "windows")]
+fn open_folder_command(path: &Path) -> (&'static str, Vec<String>) {
+    ("explorer", vec![format!("/select,{}", path.display())])
+}
+
+#[cfg(target_os = "macos")]
+fn open_folder_command(path: &Path) -> (&'static str, Vec<String>) {
+    ("open", vec![String::from("-R"), path.display().to_string()])
+}
+
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+fn open_folder_command(path: &Path) -> (&'static str, Vec<String>) {
+    let folder = path.parent().unwrap_or(path);
+    ("xdg-open", vec![folder.display().to_string()])
+}
@@ -0,0 +1,94 @@
+use crate::config::Schedule;
+use crate::control;
+use chrono::{Local, NaiveTime};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How often the clock is checked against the window
+const INTERVAL: Duration = Duration::from_secs(30);
+
+/// Start and end of the rendering window in local time, none when renders can run any time
+pub fn window(schedule: &Schedule) -> Option<(NaiveTime, NaiveTime)> {
+    Some((parse(&schedule.start_time)?, parse(&schedule.end_time)?))
+}
+
+/// `HH:MM` in 24 hour time
+pub fn parse(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M").ok()
+}
+
+/// Whether `now` is inside the window, which can go past midnight like 22:00 to 06:00
+fn is_open((start, end): (NaiveTime, NaiveTime), now: NaiveTime) -> bool {
+    if start <= end {
+        start <= now && now < end
+    } else {
+        now >= start || now < end
+    }
+}
+
+/// Blocks until the rendering window opens
+pub fn wait(schedule: &Schedule) {
+    let Some(window) = window(schedule) else {
+        return;
+    };
+    if is_open(window, Local::now().time()) {
+        return;
+    }
+    info!(
+        "Waiting for the rendering window from {} to {}",
+        window.0.format("%H:%M"),
+        window.1.format("%H:%M")
+    );
+    while !is_open(window, Local::now().time()) {
+        std::thread::sleep(INTERVAL);
+    }
+}
+
+/// Pauses the renders of this process while the rendering window is closed, resuming them once
+/// it opens again
+pub struct Guard {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Guard {
+    pub fn start(schedule: &Schedule) -> Option<Guard> {
+        let window = window(schedule)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let mut paused = false;
+            while !thread_stop.load(Ordering::Relaxed) {
+                let open = is_open(window, Local::now().time());
+                if paused == open {
+                    if open {
+                        info!("The rendering window opened, resuming");
+                    } else {
+                        info!(
+                            "The rendering window closed, pausing until {}",
+                            window.0.format("%H:%M")
+                        );
+                    }
+                    control::suspend(!open);
+                    paused = !open;
+                } else if paused {
+                    // processes started since, like the next pass or a metrics run, are paused too
+                    control::suspend(true);
+                }
+                std::thread::park_timeout(INTERVAL);
+            }
+            if paused {
+                control::suspend(false);
+            }
+        });
+        Some(Guard { stop, thread })
+    }
+
+    pub fn finish(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.thread().unpark();
+        let _ = self.thread.join();
+    }
+}
@@ -1,10 +1,23 @@
-use crate::config::Config;
+use crate::config::{Config, Mask, Output, Overlay, Transform};
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
 };
 
-pub fn create(temp_path: PathBuf, video_path: &Path, settings: Config) -> PathBuf {
+/// An input clip, several get joined in order when concatenating
+#[derive(Clone)]
+pub struct Source {
+    pub video_path: PathBuf,
+    pub index_path: PathBuf,
+    pub cfr_rate: Option<(i64, i64)>,
+    /// vapoursynth resize name of the color matrix of the clip
+    pub matrix: String,
+    pub full_range: bool,
+    /// Number of the first image of an image sequence input
+    pub first_frame: Option<u64>,
+}
+
+pub fn create(temp_path: PathBuf, sources: &[Source], settings: Config) -> PathBuf {
     let script_filename = temp_path.join(rand::random::<u16>().to_string() + ".vpk");
 
     let mut script = "from vapoursynth import core\nimport vapoursynth as vs\nimport havsfunc as haf\nimport adjust\nimport weighting\n".to_owned();
@@ -13,81 +26,240 @@ pub fn create(temp_path: PathBuf, video_path: &Path, settings: Config) -> PathBu
         script += "import filldrops\n";
     }
 
+    let vs_threads = settings
+        .advanced
+        .performance
+        .vs_threads(settings.advanced.threads);
+    if vs_threads > 0 {
+        script += format!("core.num_threads = {}\n", vs_threads).as_str();
+    }
+    if settings.advanced.performance.max_cache_mb > 0 {
+        script += format!(
+            "core.max_cache_size = {}\n",
+            settings.advanced.performance.max_cache_mb
+        )
+        .as_str();
+    }
+
     if settings.advanced.interpolation.program == "rife" {
         script += "from vsrife import RIFE\n";
     }
 
-    let extentension = video_path.extension().unwrap().to_str().unwrap();
-    if extentension != ".avi" {
+    let segments = settings.timescale.segments();
+    if !segments.is_empty() {
+        script += "import retime\n";
+    }
+
+    if let [source] = sources {
         script += format!(
-            "video = core.ffms2.Source(source=\"{}\")\n",
-            video_path
-                .canonicalize()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .replace('\\', "\\\\")
+            "video = {}\n",
+            load(source, settings.encoding.preserve_alpha)
         )
         .as_str();
+        if settings.encoding.preserve_alpha {
+            script += "if isinstance(video, list):\n";
+            script += "    video, alpha = video[0], video[1]\n";
+            script += "else:\n";
+            script += "    alpha = core.std.PropToClip(video, \"_Alpha\")\n";
+        }
+        script += &convert("video", source, settings.encoding.chroma());
     } else {
+        script += "clips = []\n";
+        for source in sources {
+            script += format!("clip = {}\n", load(source, false)).as_str();
+            script += &convert("clip", source, settings.encoding.chroma());
+            script += "clips.append(clip)\n";
+        }
+        // every clip takes the resolution, format and frame rate of the first
+        script += "video = clips[0]\n";
+        script += "for clip in clips[1:]:\n";
+        script += "    clip = core.resize.Bicubic(clip, width=video.width, height=video.height, format=video.format.id, matrix_s=\"709\")\n";
+        script += "    video = video + haf.ChangeFPS(clip, video.fps_num, video.fps_den)\n";
+    }
+    // alpha isn't kept for joined clips
+    let alpha = settings.encoding.preserve_alpha && sources.len() == 1;
+    if alpha {
+        // the alpha plane goes through the same processing as the picture, as the luma of a
+        // clip of its own
+        script += "alpha = core.fmtc.bitdepth(clip=alpha, bits=8)\n";
+        script += "alpha = core.std.ShufflePlanes([alpha, core.std.BlankClip(alpha, format=vs.GRAY8, color=128)], [0, 0, 0], vs.YUV)\n";
+        script += "alpha = core.resize.Bicubic(alpha, format=vs.YUV420P8)\n";
         script += format!(
-            "video = core.avisource.AVISource(\"{}\")",
-            video_path
-                .canonicalize()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .replace('\\', "\\\\")
+            "def process(video):\n{}    return video\n",
+            indent(&process(&settings, false))
         )
         .as_str();
+        script += format!(
+            "def process_alpha(video):\n{}    return video\n",
+            indent(&process(&settings, true))
+        )
+        .as_str();
+        script += "video = process(video)\n";
+        script += "alpha = core.std.ShufflePlanes(process_alpha(alpha), 0, vs.GRAY)\n";
+        // y4m only carries alpha with 4:4:4
+        script += "video = core.resize.Bicubic(video, format=vs.YUV444P8)\n";
+        script += "video.set_output(alpha=alpha)\n";
+    } else {
+        script += &process(&settings, false);
+        if settings.encoding.ten_bit() {
+            // raised at the end, which still saves the encoder from banding in gradients
+            script += "video = core.fmtc.bitdepth(clip=video, bits=10)\n";
+        }
+        script += "video.set_output()\n";
     }
-    script += "video = core.fmtc.matrix(clip=video, mat=\"709\", col_fam=vs.YUV, bits=16)\n";
-    script += "video = core.fmtc.resample(clip=video, css=\"420\")\n";
-    script += "video = core.fmtc.bitdepth(clip=video, bits=8)\n";
+
+    let file = script_filename.clone();
+    std::fs::write(script_filename, script).expect("Could not write script file");
+    file
+}
+
+/// Everything done to the source after it is loaded, for the picture or for its alpha plane
+fn process(settings: &Config, alpha: bool) -> String {
+    let mut script = String::new();
+    let segments = settings.timescale.segments();
+    script += &transform(&settings.transform);
 
     if settings.timescale.input != 1.0 {
         script += format!(
-            "video = core.std.AssumeFPS(video, fpsnum=(video.fps * (1 / {})))",
+            "video = core.std.AssumeFPS(video, fpsnum=(video.fps * (1 / {})))\n",
             settings.timescale.input,
         )
         .as_str();
     }
 
+    if settings.transform.reverse {
+        script += "video = core.std.Reverse(video)\n";
+    }
+    if settings.transform.pingpong {
+        script += "video = video + core.std.Reverse(video)\n";
+    }
+
+    if settings.debug.overlay_frame_numbers && !alpha {
+        // kept to tell which source frame each output frame lands on
+        script += "from fractions import Fraction\nsource_fps = video.fps\n";
+    }
+
+    let static_threshold = settings.advanced.interpolation.mask;
+    let adaptive = settings.blending.enabled && settings.blending.adaptive;
+    let masked = settings.mask.is_enabled() || static_threshold > 0.0 || adaptive;
+    // the alpha only needs the same timing and geometry as the picture
+    let compare = if alpha {
+        String::new()
+    } else {
+        settings.debug.compare.to_lowercase()
+    };
+    if masked || !compare.is_empty() {
+        // kept to composite back outside of the mask or to compare against
+        script += "original = video\n";
+    }
+
     if settings.interpolation.enabled {
+        let quality = settings.interpolation.quality.to_lowercase();
+        // the alpha is always 4:2:0
+        let chroma = if alpha {
+            "420"
+        } else {
+            settings.encoding.chroma()
+        };
         if settings.advanced.interpolation.program == "rife" {
             script += "video = core.resize.Bicubic(video, format=vs.RGBS, matrix_in_s=\"709\")\n";
             script += format!("while video.fps < {}:\n", settings.interpolation.fps).as_str();
-            script += "    video = RIFE(video)\n";
-            script += "video = core.resize.Bicubic(video, format=vs.YUV420P8, matrix_s=\"709\")\n"
+            // half scale flow is faster and rougher, the ensemble averages two passes
+            let mut args = match quality.as_str() {
+                "fast" => String::from(", scale=0.5"),
+                "quality" => String::from(", ensemble=True"),
+                _ => String::new(),
+            };
+            args += &rife_args(settings, true, "device_index");
+            script += format!("    video = RIFE(video{})\n", args).as_str();
+            script += format!(
+                "video = core.resize.Bicubic(video, format=vs.YUV{}P8, matrix_s=\"709\")\n",
+                chroma
+            )
+            .as_str();
         } else if settings.advanced.interpolation.program == "rife-ncnn" {
             script += "video = core.resize.Bicubic(video, format=vs.RGBS, matrix_in_s=\"709\")\n";
 
             script += format!("while video.fps < {}:\n", settings.interpolation.fps).as_str();
-            script += "    video = core.rife.RIFE(video)\n";
+            // uhd estimates the flow at a lower resolution, tta flips the frames for a second pass
+            let mut args = match quality.as_str() {
+                "fast" => String::from(", uhd=True"),
+                "quality" => String::from(", tta=True"),
+                _ => String::new(),
+            };
+            args += &rife_args(settings, false, "gpu_id");
+            script += format!("    video = core.rife.RIFE(video{})\n", args).as_str();
 
-            script += "video = core.resize.Bicubic(video, format=vs.YUV420P8, matrix_s=\"709\")\n"
+            script += format!(
+                "video = core.resize.Bicubic(video, format=vs.YUV{}P8, matrix_s=\"709\")\n",
+                chroma
+            )
+            .as_str();
         } else {
-            let mut speed = settings.advanced.interpolation.speed;
+            // the tier picks the preset and algorithm unless they are set in advanced
+            let (tier_speed, tier_algorithm) = match quality.as_str() {
+                "fast" => ("faster", "2"),
+                "quality" => ("medium", "23"),
+                _ => ("medium", "13"),
+            };
+
+            let mut speed = settings.advanced.interpolation.speed.clone();
             if speed.to_lowercase() == "default" {
-                speed = "medium".to_string();
+                speed = tier_speed.to_string();
             }
 
-            let mut tuning = settings.advanced.interpolation.tuning;
+            let mut tuning = settings.advanced.interpolation.tuning.clone();
             if tuning.to_lowercase() == "default" {
                 tuning = "smooth".to_string();
             }
 
-            let mut algorithm = settings.advanced.interpolation.algorithm;
+            let mut algorithm = settings.advanced.interpolation.algorithm.clone();
             if algorithm.to_lowercase() == "default" {
-                algorithm = "13".to_string();
+                algorithm = tier_algorithm.to_string();
             }
 
-            let gpu_bool = if settings.advanced.encoding.gpu {
+            let gpu_bool = if settings
+                .advanced
+                .performance
+                .interpolation_gpu(settings.advanced.encoding.gpu)
+            {
                 "True"
             } else {
                 "False"
             };
-            script += format!("video = haf.InterFrame(video, GPU={}, NewNum={}, Preset=\"{}\", Tuning=\"{}\", OverrideAlgo={})\n", gpu_bool, settings.interpolation.fps, speed, tuning, algorithm).as_str()
+            if chroma != "420" {
+                // svp only takes 4:2:0, its output is brought back to 4:4:4 for the overlay
+                // and padding but the chroma detail is lost, rife keeps it
+                script += "video = core.resize.Bicubic(video, format=vs.YUV420P8)\n";
+            }
+            script += format!("video = haf.InterFrame(video, GPU={}, NewNum={}, Preset=\"{}\", Tuning=\"{}\", OverrideAlgo={})\n", gpu_bool, settings.interpolation.fps, speed, tuning, algorithm).as_str();
+            if chroma != "420" {
+                script += format!(
+                    "video = core.resize.Bicubic(video, format=vs.YUV{}P8)\n",
+                    chroma
+                )
+                .as_str();
+            }
+        }
+    }
+
+    if !segments.is_empty() {
+        // after interpolation so slowed down parts pick from the interpolated frames
+        let segments = segments
+            .iter()
+            .map(|(start, end, speed)| {
+                format!(
+                    "({}, {}, {})",
+                    start,
+                    end.map_or(String::from("None"), |end| end.to_string()),
+                    speed
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        script += format!("video = retime.Retime(video, [{}])\n", segments).as_str();
+        if masked || !compare.is_empty() {
+            script += format!("original = retime.Retime(original, [{}])\n", segments).as_str();
         }
     }
 
@@ -97,6 +269,20 @@ pub fn create(temp_path: PathBuf, video_path: &Path, settings: Config) -> PathBu
             settings.timescale.output,
         )
         .as_str();
+        if settings.debug.overlay_frame_numbers && !alpha {
+            script += format!(
+                "source_fps = source_fps * Fraction(\"{}\")\n",
+                settings.timescale.output
+            )
+            .as_str();
+        }
+        if masked || !compare.is_empty() {
+            script += format!(
+                "original = core.std.AssumeFPS(original, fpsnum=(original.fps * {}))\n",
+                settings.timescale.output,
+            )
+            .as_str();
+        }
     }
 
     if settings.advanced.encoding.deduplicate {
@@ -111,7 +297,7 @@ pub fn create(temp_path: PathBuf, video_path: &Path, settings: Config) -> PathBu
         .as_str();
         script += format!(
             "blended_frames = int(frame_gap *{})\n",
-            settings.blending.amount
+            settings.blending.effective_amount()
         )
         .as_str();
 
@@ -148,12 +334,20 @@ pub fn create(temp_path: PathBuf, video_path: &Path, settings: Config) -> PathBu
             triangle_reverse_bool
         );
         let custom_weight = format!(
-            "weighting.divide(blended_frames, {})",
-            settings.blending.weighting
+            "weighting.divide(blended_frames, [{}])",
+            settings
+                .blending
+                .custom_weights()
+                .unwrap_or_default()
+                .iter()
+                .map(|weight| weight.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
         );
         let custom_function = format!(
-            "weighting.custom(blended_frames, '{}', {})",
-            settings.blending.weighting, weighting_bound
+            "weighting.custom(blended_frames, {}, {})",
+            python_string(&settings.blending.weighting),
+            weighting_bound
         );
         let weighting_functions = HashMap::from([
             ("equal", "weighting.equal(blended_frames)"),
@@ -165,7 +359,7 @@ pub fn create(temp_path: PathBuf, video_path: &Path, settings: Config) -> PathBu
             ("custom_function", custom_function.as_str()),
         ]);
 
-        let mut weighting = settings.blending.weighting;
+        let mut weighting = settings.blending.weighting.clone();
         if weighting_functions.get(weighting.as_str()).is_none() {
             // check if it's a custom weighting function
             if weighting.starts_with('[') && weighting.ends_with(']') {
@@ -190,9 +384,14 @@ pub fn create(temp_path: PathBuf, video_path: &Path, settings: Config) -> PathBu
         .as_str();
     }
 
-    if settings.filters.brightness != 1.0
-        || settings.filters.contrast != 1.0
-        || settings.filters.saturation != 1.0
+    if masked {
+        script += &mask(&settings.mask, static_threshold, adaptive);
+    }
+
+    if !alpha
+        && (settings.filters.brightness != 1.0
+            || settings.filters.contrast != 1.0
+            || settings.filters.saturation != 1.0)
     {
         script += format!(
             "video = adjust.Tweak(video, bright={}, cont={}, sat={})\n",
@@ -201,8 +400,367 @@ pub fn create(temp_path: PathBuf, video_path: &Path, settings: Config) -> PathBu
         .as_str();
     }
 
-    script += "video.set_output()\n";
-    let file = script_filename.clone();
-    std::fs::write(script_filename, script).expect("Could not write script file");
-    file
+    if settings.debug.overlay_frame_numbers && !alpha {
+        script += "def frame_number(n, clip=video):\n";
+        script += "    position = n * source_fps / clip.fps\n";
+        script += "    marker = \"\" if position.denominator == 1 else \" interpolated\"\n";
+        script += "    return core.text.Text(clip, \"source frame %d%s\" % (int(position), marker), alignment=7)\n";
+        script += "video = core.std.FrameEval(video, frame_number)\n";
+    }
+
+    if !compare.is_empty() {
+        script += "original = haf.ChangeFPS(original, video.fps_num, video.fps_den)\n";
+        if compare == "stacked" {
+            script += "video = core.std.StackVertical([original, video])\n";
+        } else {
+            // left half of the original next to the right half of the result
+            script += "half = video.width // 4 * 2\n";
+            script += "video = core.std.StackHorizontal([core.std.Crop(original, right=video.width - half), core.std.Crop(video, left=half)])\n";
+        }
+    }
+
+    script += &output(&settings.output);
+    if !alpha {
+        script += &overlay(&settings.overlay);
+    }
+    script
+}
+
+/// Model and gpu arguments of either rife plugin, which name the gpu differently and take the
+/// model as a version string or as an index
+fn rife_args(settings: &Config, quoted_model: bool, gpu: &str) -> String {
+    let interpolation = &settings.advanced.interpolation;
+    let mut args = String::new();
+    if !interpolation.rife_model.is_empty() {
+        if quoted_model {
+            args += format!(", model={}", python_string(&interpolation.rife_model)).as_str();
+        } else {
+            args += format!(", model={}", interpolation.rife_model).as_str();
+        }
+    }
+    if interpolation.rife_gpu_index >= 0 {
+        args += format!(", {}={}", gpu, interpolation.rife_gpu_index).as_str();
+    }
+    args
+}
+
+/// A python string literal of a path or name pasted into the script, so quotes and backslashes
+/// in it can't end the string early
+pub fn python_string(value: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => literal.push_str("\\\\"),
+            '"' => literal.push_str("\\\""),
+            c if c.is_control() => literal.push_str(&format!("\\x{:02x}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Indents a part of the script to go inside a function
+fn indent(script: &str) -> String {
+    script
+        .lines()
+        .map(|line| format!("    {}\n", line))
+        .collect()
+}
+
+/// Scales the result to the output resolution, filling the rest of the frame when the aspect
+/// ratio differs
+fn output(output: &Output) -> String {
+    let (width, height) = match output.size() {
+        Some(size) => size,
+        None => return String::new(),
+    };
+    let pad = output.pad.to_lowercase();
+    if pad == "stretch" {
+        return format!(
+            "video = core.resize.Bicubic(video, width={}, height={})\n",
+            width, height
+        );
+    }
+
+    let mut script = format!("target_width, target_height = {}, {}\n", width, height);
+    // the largest size fitting inside the target, kept even for 4:2:0
+    script += "fit = min(target_width / video.width, target_height / video.height)\n";
+    script += "fit_width, fit_height = int(video.width * fit) // 2 * 2, int(video.height * fit) // 2 * 2\n";
+    script +=
+        "left, top = (target_width - fit_width) // 4 * 2, (target_height - fit_height) // 4 * 2\n";
+    script += "borders = dict(left=left, right=target_width - fit_width - left, top=top, bottom=target_height - fit_height - top)\n";
+    script += "video = core.resize.Bicubic(video, width=fit_width, height=fit_height)\n";
+    if pad == "blur" {
+        // the video scaled to cover the frame and blurred heavily behind it
+        script += "cover = max(target_width / video.width, target_height / video.height)\n";
+        script += "background = core.resize.Bicubic(video, width=int(video.width * cover) // 2 * 2 + 2, height=int(video.height * cover) // 2 * 2 + 2)\n";
+        script += "background = core.std.CropAbs(background, width=target_width, height=target_height, left=(background.width - target_width) // 4 * 2, top=(background.height - target_height) // 4 * 2)\n";
+        script += "background = core.resize.Bilinear(background, width=target_width // 16 * 2, height=target_height // 16 * 2)\n";
+        script += "background = core.std.BoxBlur(background, hradius=4, vradius=4, hpasses=3, vpasses=3)\n";
+        script += "background = core.resize.Bilinear(background, width=target_width, height=target_height)\n";
+        script += "placed = core.std.BlankClip(video, format=vs.GRAY8, color=255)\n";
+        script += "placed = core.std.AddBorders(placed, color=0, **borders)\n";
+        script += "video = core.std.MaskedMerge(background, core.std.AddBorders(video, **borders), placed, first_plane=True)\n";
+    } else {
+        script += "video = core.std.AddBorders(video, color=[16, 128, 128], **borders)\n";
+    }
+    script
+}
+
+/// Composites the watermark over a corner or the center of the result
+fn overlay(overlay: &Overlay) -> String {
+    if overlay.image_path.is_empty() {
+        return String::new();
+    }
+    let mut script = format!(
+        "logo = core.imwri.Read({}, alpha=True)\n",
+        python_string(
+            Path::new(&overlay.image_path)
+                .canonicalize()
+                .unwrap()
+                .to_str()
+                .unwrap()
+        )
+    );
+    // older vapoursynth returns the alpha as a second clip, newer attaches it to the frames
+    script += "if isinstance(logo, list):\n";
+    script += "    logo, logo_alpha = logo[0], logo[1]\n";
+    script += "elif \"_Alpha\" in logo.get_frame(0).props:\n";
+    script += "    logo_alpha = core.std.PropToClip(logo, \"_Alpha\")\n";
+    script += "else:\n";
+    script += "    logo_alpha = None\n";
+    script += "if logo_alpha is None:\n";
+    script += "    logo_alpha = core.std.BlankClip(logo, format=vs.GRAY8, color=255)\n";
+    script += "logo_width, logo_height = min(logo.width, video.width) // 2 * 2, min(logo.height, video.height) // 2 * 2\n";
+    script += "logo = core.resize.Bicubic(logo, width=logo_width, height=logo_height, format=video.format.id, matrix_s=\"709\")\n";
+    script += "logo_alpha = core.resize.Bicubic(logo_alpha, width=logo_width, height=logo_height, format=vs.GRAY8)\n";
+    script += format!(
+        "logo_alpha = core.std.Expr(logo_alpha, \"x {} *\")\n",
+        overlay.opacity
+    )
+    .as_str();
+
+    let (horizontal, vertical) = match overlay.position.to_lowercase().as_str() {
+        "top-left" => ("margin", "margin"),
+        "top-right" => ("video.width - logo_width - margin", "margin"),
+        "bottom-left" => ("margin", "video.height - logo_height - margin"),
+        "center" => (
+            "(video.width - logo_width) // 4 * 2",
+            "(video.height - logo_height) // 4 * 2",
+        ),
+        _ => (
+            "video.width - logo_width - margin",
+            "video.height - logo_height - margin",
+        ),
+    };
+    script += "margin = min(video.height // 40 * 2, (video.width - logo_width) // 4 * 2, (video.height - logo_height) // 4 * 2)\n";
+    script += format!("left, top = {}, {}\n", horizontal, vertical).as_str();
+    script += "borders = dict(left=left, right=video.width - logo_width - left, top=top, bottom=video.height - logo_height - top)\n";
+    script += "logo = core.std.Loop(core.std.AddBorders(logo, **borders), video.num_frames)\n";
+    script += "logo_alpha = core.std.Loop(core.std.AddBorders(logo_alpha, color=0, **borders), video.num_frames)\n";
+    script += "video = core.std.MaskedMerge(video, logo, logo_alpha, first_plane=True)\n";
+    script
+}
+
+/// Crops, rotates, flips and scales the source
+fn transform(transform: &Transform) -> String {
+    let mut script = String::new();
+    if let [x, y, width, height] = transform.crop[..] {
+        script += format!(
+            "video = core.std.CropAbs(video, width={}, height={}, left={}, top={})\n",
+            width, height, x, y
+        )
+        .as_str();
+    }
+
+    script += match transform.rotate {
+        90 => "video = core.std.FlipHorizontal(core.std.Transpose(video))\n",
+        180 => "video = core.std.Turn180(video)\n",
+        270 => "video = core.std.FlipVertical(core.std.Transpose(video))\n",
+        _ => "",
+    };
+
+    let flip = transform.flip.to_lowercase();
+    if flip == "horizontal" || flip == "both" {
+        script += "video = core.std.FlipHorizontal(video)\n";
+    }
+    if flip == "vertical" || flip == "both" {
+        script += "video = core.std.FlipVertical(video)\n";
+    }
+
+    if let Some((width, height)) = transform.scale_size() {
+        let resizer = match transform.resizer.to_lowercase().as_str() {
+            "bilinear" => "Bilinear",
+            "lanczos" => "Lanczos",
+            "spline36" => "Spline36",
+            "point" => "Point",
+            _ => "Bicubic",
+        };
+        script += format!(
+            "video = core.resize.{}(video, width={}, height={})\n",
+            resizer, width, height
+        )
+        .as_str();
+    }
+    script
+}
+
+/// Converts the clip to 8-bit BT.709 limited range, which the output is tagged as, with the
+/// chroma subsampling of the output
+fn convert(name: &str, source: &Source, chroma: &str) -> String {
+    let range = if source.full_range { "full" } else { "limited" };
+    let mut script = format!(
+        "{0} = core.resize.Bicubic({0}, format=vs.YUV444P16, matrix_in_s=\"{1}\", matrix_s=\"709\", range_in_s=\"{2}\", range_s=\"limited\")\n",
+        name, source.matrix, range
+    );
+    if chroma == "420" {
+        script += format!("{0} = core.fmtc.resample(clip={0}, css=\"420\")\n", name).as_str();
+    }
+    script += format!("{0} = core.fmtc.bitdepth(clip={0}, bits=8)\n", name).as_str();
+    script
+}
+
+/// Source filter call loading the clip
+fn load(source: &Source, alpha: bool) -> String {
+    if let Some(first_frame) = source.first_frame {
+        return load_sequence(source, first_frame, alpha);
+    }
+    let video_path = python_string(source.video_path.canonicalize().unwrap().to_str().unwrap());
+
+    let extentension = source.video_path.extension().unwrap().to_str().unwrap();
+    if extentension != ".avi" {
+        // ffms2 picks frames by their timestamps when given a frame rate
+        let cfr_args = match source.cfr_rate {
+            Some((fpsnum, fpsden)) => format!(", fpsnum={}, fpsden={}", fpsnum, fpsden),
+            None => String::new(),
+        };
+        format!(
+            "core.ffms2.Source(source={}, cachefile={}{}{})",
+            video_path,
+            python_string(source.index_path.to_str().unwrap()),
+            cfr_args,
+            if alpha { ", alpha=True" } else { "" }
+        )
+    } else {
+        format!("core.avisource.AVISource({})", video_path)
+    }
+}
+
+/// Reads the images of a sequence, which have no frame rate of their own so they get the one
+/// from vfr.sequence_fps
+fn load_sequence(source: &Source, first_frame: u64, alpha: bool) -> String {
+    // the pattern itself doesn't exist, only the folder it is in
+    let folder = match source.video_path.parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new("."),
+    };
+    let pattern = python_string(
+        folder
+            .canonicalize()
+            .unwrap()
+            .join(source.video_path.file_name().unwrap())
+            .to_str()
+            .unwrap(),
+    );
+    let (fpsnum, fpsden) = source.cfr_rate.unwrap_or((60, 1));
+    let read = format!(
+        "core.imwri.Read({}, firstnum={}{})",
+        pattern,
+        first_frame,
+        if alpha { ", alpha=True" } else { "" }
+    );
+    if alpha {
+        // the picture and its alpha come back as a list
+        format!(
+            "[core.std.AssumeFPS(clip, fpsnum={}, fpsden={}) for clip in {}]",
+            fpsnum, fpsden, read
+        )
+    } else {
+        format!(
+            "core.std.AssumeFPS({}, fpsnum={}, fpsden={})",
+            read, fpsnum, fpsden
+        )
+    }
+}
+
+/// Merges the processed video over the original where the mask is white
+fn mask(mask: &Mask, static_threshold: f32, adaptive: bool) -> String {
+    let mut script = String::new();
+    // each part makes a clip of where to blur, the darkest of them wins
+    let mut masks = vec![];
+    if static_threshold > 0.0 {
+        // pixels that barely change over the surrounding frames are static, like a HUD
+        script += "luma = core.std.ShufflePlanes(original, 0, vs.GRAY)\n";
+        script += "motion = core.std.Expr([luma, luma[0] + luma[:-1]], \"x y - abs\")\n";
+        script += "motion = core.std.AverageFrames(motion, [1] * 15)\n";
+        script += format!(
+            "motion = core.std.Expr(motion, \"x {} > 255 0\")\n",
+            static_threshold
+        )
+        .as_str();
+        script += "motion = core.std.BoxBlur(core.std.Maximum(motion), hradius=2, vradius=2)\n";
+        script += "motion = haf.ChangeFPS(motion, video.fps_num, video.fps_den)\n";
+        masks.push("motion");
+    }
+    if adaptive {
+        // the length of mvtools' motion vectors between source frames, so fast motion gets the
+        // full blur and slow motion less
+        script += "speed_super = core.mv.Super(original, pel=2, sharp=1)\n";
+        script +=
+            "speed_vectors = core.mv.Analyse(speed_super, isb=False, blksize=16, overlap=8)\n";
+        script += "speed = core.mv.Mask(original, speed_vectors, kind=0, ml=100)\n";
+        script += "speed = core.std.ShufflePlanes(speed, 0, vs.GRAY)\n";
+        script += "speed = core.std.BoxBlur(speed, hradius=4, vradius=4)\n";
+        script += "speed = haf.ChangeFPS(speed, video.fps_num, video.fps_den)\n";
+        masks.push("speed");
+    }
+    script += "original = haf.ChangeFPS(original, video.fps_num, video.fps_den)\n";
+
+    if mask.is_enabled() {
+        if !mask.image.is_empty() {
+            script += format!(
+                "mask = core.imwri.Read({})\n",
+                python_string(
+                    Path::new(&mask.image)
+                        .canonicalize()
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                )
+            )
+            .as_str();
+            script += "mask = core.resize.Bilinear(mask, width=video.width, height=video.height, format=vs.GRAY8, matrix_s=\"709\")\n";
+        } else {
+            let (x, y, width, height) = (mask.rect[0], mask.rect[1], mask.rect[2], mask.rect[3]);
+            script += format!(
+                "mask = core.std.BlankClip(width={}, height={}, format=vs.GRAY8, color=255, length=1)\n",
+                width, height
+            )
+            .as_str();
+            script += format!(
+                "mask = core.std.AddBorders(mask, left={}, top={}, right=video.width - {}, bottom=video.height - {}, color=0)\n",
+                x,
+                y,
+                x + width,
+                y + height
+            )
+            .as_str();
+        }
+        if mask.invert {
+            script += "mask = core.std.Invert(mask)\n";
+        }
+        script += "mask = core.std.Loop(mask, video.num_frames)\n";
+        masks.push("mask");
+    }
+    if let Some((first, rest)) = masks.split_first() {
+        if *first != "mask" {
+            script += format!("mask = {}\n", first).as_str();
+        }
+        for other in rest {
+            script += format!("mask = core.std.Expr([mask, {}], \"x y min\")\n", other).as_str();
+        }
+    }
+    script += "video = core.std.MaskedMerge(original, video, mask, first_plane=True)\n";
+    script
 }
@@ -0,0 +1,187 @@
+use crate::config::Config;
+use crate::helpers::{self, exec};
+use crate::presets;
+use crate::rendering::{Render, Rendering};
+use crate::teres::load_config;
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Test clip made by ffmpeg's own test source, a second of moving patterns and a timer
+const CLIP: &str = "testsrc2=size=320x180:rate=60:duration=1";
+
+/// Frame hashes of a preset from a render known to be right
+#[derive(Debug, Serialize, Deserialize)]
+struct Golden {
+    version: String,
+    frames: Vec<String>,
+}
+
+pub fn path() -> PathBuf {
+    Config::global_path().with_file_name("selftest.json")
+}
+
+/// Renders the test clip with the default settings and every builtin preset, comparing the
+/// decoded frames to the goldens recorded before. Presets without goldens, or all of them with
+/// `record`, get them recorded.
+///
+/// The goldens are machine-local: they only hold for the ffmpeg, VapourSynth and plugin builds
+/// installed here, so none ship with teres and the first run only records them
+pub fn run(record: bool) {
+    // sets up the paths of ffmpeg and vspipe
+    let local = load_config(&[], None);
+    let folder = std::env::temp_dir().join(format!("teres-selftest-{}", rand::random::<u16>()));
+    if let Err(error) = std::fs::create_dir_all(&folder) {
+        error!("Could not create {}: {}", folder.display(), error);
+        helpers::exit(exitcode::CANTCREAT);
+        return;
+    }
+    let clip = folder.join("clip.mkv");
+    if let Err(error) = make_clip(&clip) {
+        error!("Could not make the test clip: {}", error);
+        let _ = std::fs::remove_dir_all(&folder);
+        helpers::exit(exitcode::UNAVAILABLE);
+        return;
+    }
+
+    let mut goldens = load();
+    let mut failed = 0;
+    let presets = std::iter::once("default").chain(presets::BUILTIN.iter().map(|(name, _)| *name));
+    for preset in presets {
+        let frames = match settings(preset, &local).and_then(|settings| render(&clip, settings)) {
+            Ok(frames) => frames,
+            Err(error) => {
+                error!("{}: could not render, {}", preset, error);
+                failed += 1;
+                continue;
+            }
+        };
+        match goldens.get(preset) {
+            Some(golden) if !record => match compare(&golden.frames, &frames) {
+                None => info!("{}: matches", preset),
+                Some(difference) => {
+                    error!(
+                        "{}: {} (goldens recorded with teres {})",
+                        preset, difference, golden.version
+                    );
+                    failed += 1;
+                }
+            },
+            _ => {
+                info!(
+                    "{}: recorded {} frames as this machine's goldens",
+                    preset,
+                    frames.len()
+                );
+                goldens.insert(
+                    preset.to_string(),
+                    Golden {
+                        version: env!("CARGO_PKG_VERSION").to_string(),
+                        frames,
+                    },
+                );
+            }
+        }
+    }
+    let _ = std::fs::remove_dir_all(&folder);
+    save(&goldens);
+
+    if failed > 0 {
+        error!(
+            "{} preset(s) rendered differently, a plugin or ffmpeg update may have changed how videos are blurred. run teres selftest --record once the new results look right",
+            failed
+        );
+        helpers::exit(exitcode::SOFTWARE);
+    }
+}
+
+/// Settings of a preset that render the same way on every machine
+fn settings(preset: &str, local: &Config) -> Result<Config, String> {
+    let mut settings = Config::builtin(Some(preset).filter(|preset| *preset != "default"))?;
+    settings.audio.enabled = false;
+    settings.encoding.container = String::from("mkv");
+    settings.output.embed_settings = false;
+    settings.output.sidecar = String::from("off");
+    settings.advanced.ffmpeg_path = local.advanced.ffmpeg_path.clone();
+    settings.advanced.ffprobe_path = local.advanced.ffprobe_path.clone();
+    settings.advanced.vspipe_path = local.advanced.vspipe_path.clone();
+    settings.advanced.deterministic = true;
+    settings.deterministic();
+    Ok(settings)
+}
+
+fn make_clip(clip: &Path) -> Result<(), String> {
+    let output = Command::new(crate::binaries::ffmpeg())
+        .args(["-v", "error", "-y", "-f", "lavfi", "-i", CLIP])
+        .args(["-c:v", "ffv1", "-fflags", "+bitexact"])
+        .arg(clip)
+        .output()
+        .map_err(|error| error.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(())
+}
+
+/// Renders the clip, returning the md5 of each decoded frame of the output
+fn render(clip: &Path, settings: Config) -> Result<Vec<String>, String> {
+    let render = Render::new(clip.to_path_buf(), settings)
+        .ok_or_else(|| String::from("the clip could not be read"))?;
+    let command = Rendering::build_ffmpeg_command(&render, false, None, None)
+        .map_err(|error| error.to_string())?;
+    let output = PathBuf::from(&command.output_filename);
+    let status = exec(command, ProgressBar::hidden());
+    render.clean();
+    if !status.success() {
+        return Err(String::from("vapoursynth or ffmpeg failed"));
+    }
+
+    let hashes = Command::new(crate::binaries::ffmpeg())
+        .args(["-v", "error", "-i"])
+        .arg(&output)
+        .args(["-map", "0:v", "-f", "framemd5", "-"])
+        .output()
+        .map_err(|error| error.to_string())?;
+    if !hashes.status.success() {
+        return Err(String::from_utf8_lossy(&hashes.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&hashes.stdout)
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.rsplit(',').next())
+        .map(|hash| hash.trim().to_string())
+        .collect())
+}
+
+/// Describes where the frames first differ from the goldens
+fn compare(golden: &[String], frames: &[String]) -> Option<String> {
+    if let Some(frame) = golden.iter().zip(frames).position(|(a, b)| a != b) {
+        return Some(format!("frame {} differs", frame));
+    }
+    if golden.len() != frames.len() {
+        return Some(format!(
+            "rendered {} frames instead of {}",
+            frames.len(),
+            golden.len()
+        ));
+    }
+    None
+}
+
+fn load() -> BTreeMap<String, Golden> {
+    std::fs::read_to_string(path())
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save(goldens: &BTreeMap<String, Golden>) {
+    let result = serde_json::to_string_pretty(goldens)
+        .map_err(std::io::Error::from)
+        .and_then(|contents| std::fs::write(path(), contents));
+    if let Err(error) = result {
+        warn!("Could not save the selftest goldens: {}", error);
+    }
+}
@@ -0,0 +1,126 @@
+use std::path::Path;
+
+/// A numbered image sequence input like `frames_%05d.png`, split around the printf style
+/// number
+struct Pattern {
+    prefix: String,
+    /// Digits of zero padded numbers, `%05d` is 5
+    width: Option<usize>,
+    suffix: String,
+}
+
+impl Pattern {
+    fn parse(path: &Path) -> Option<Pattern> {
+        let filename = path.file_name()?.to_str()?;
+        let (prefix, rest) = filename.split_once('%')?;
+        let (spec, suffix) = rest.split_once('d')?;
+        let width = if spec.is_empty() {
+            None
+        } else if spec.starts_with('0') && spec.len() > 1 {
+            Some(spec.parse().ok()?)
+        } else {
+            return None;
+        };
+        Some(Pattern {
+            prefix: prefix.to_string(),
+            width,
+            suffix: suffix.to_string(),
+        })
+    }
+
+    /// Frame number of a file in the sequence
+    fn number(&self, filename: &str) -> Option<u64> {
+        let digits = filename
+            .strip_prefix(&self.prefix)?
+            .strip_suffix(&self.suffix)?;
+        if digits.is_empty()
+            || !digits.chars().all(|char| char.is_ascii_digit())
+            || self.width.is_some_and(|width| digits.len() != width)
+        {
+            return None;
+        }
+        digits.parse().ok()
+    }
+}
+
+pub fn is_sequence(path: &Path) -> bool {
+    Pattern::parse(path).is_some()
+}
+
+/// Numbers of the frames of the sequence on disk, in order
+pub fn numbers(path: &Path) -> Vec<u64> {
+    let Some(pattern) = Pattern::parse(path) else {
+        return vec![];
+    };
+    let folder = match path.parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new("."),
+    };
+    let mut numbers: Vec<u64> = std::fs::read_dir(folder)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| pattern.number(entry.file_name().to_str()?))
+        .collect();
+    numbers.sort_unstable();
+    numbers
+}
+
+/// Number of the first frame, none when the sequence has no frames
+pub fn first(path: &Path) -> Option<u64> {
+    numbers(path).first().copied()
+}
+
+/// Name of the sequence without the number, `frames_%05d.png` is `frames`. Falls back to the
+/// folder name for sequences named only by their number
+pub fn name(path: &Path) -> Option<String> {
+    let pattern = Pattern::parse(path)?;
+    let name = pattern.prefix.trim_end_matches(['_', '-', '.', ' ']);
+    if !name.is_empty() {
+        return Some(name.to_string());
+    }
+    let folder = path.parent()?.canonicalize().ok()?;
+    Some(folder.file_name()?.to_str()?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_reads_zero_padded_numbers() {
+        let pattern = Pattern::parse(Path::new("renders/frames_%05d.png")).unwrap();
+        assert_eq!(pattern.prefix, "frames_");
+        assert_eq!(pattern.width, Some(5));
+        assert_eq!(pattern.suffix, ".png");
+        assert_eq!(pattern.number("frames_00042.png"), Some(42));
+        assert_eq!(pattern.number("frames_042.png"), None);
+        assert_eq!(pattern.number("frames_0004x.png"), None);
+        assert_eq!(pattern.number("other_00042.png"), None);
+    }
+
+    #[test]
+    fn pattern_reads_unpadded_numbers() {
+        let pattern = Pattern::parse(Path::new("%d.png")).unwrap();
+        assert_eq!(pattern.width, None);
+        assert_eq!(pattern.number("7.png"), Some(7));
+        assert_eq!(pattern.number("0120.png"), Some(120));
+        assert_eq!(pattern.number(".png"), None);
+    }
+
+    #[test]
+    fn pattern_refuses_other_names() {
+        assert!(Pattern::parse(Path::new("clip.mp4")).is_none());
+        // spaces instead of zeros aren't how image sequences are numbered
+        assert!(Pattern::parse(Path::new("frames_%5d.png")).is_none());
+        assert!(!is_sequence(Path::new("frames_05.png")));
+    }
+
+    #[test]
+    fn name_leaves_out_the_number() {
+        assert_eq!(
+            name(Path::new("frames_%05d.png")).as_deref(),
+            Some("frames")
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use crate::config::{self, Config};
+use crate::control;
+use crate::distributed::same_token;
+use crate::helpers;
+use crate::rendering::{Render, Rendering};
+use indicatif::ProgressBar;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Serialize, Clone)]
+struct ApiJob {
+    id: u64,
+    input: PathBuf,
+    output: Option<PathBuf>,
+    /// queued/rendering/finished/failed/cancelled
+    status: String,
+    /// Percent of the frames rendered
+    progress: u64,
+    #[serde(skip)]
+    settings: Config,
+    #[serde(skip)]
+    bar: ProgressBar,
+}
+
+#[derive(Default)]
+struct State {
+    jobs: Vec<ApiJob>,
+    next_id: u64,
+}
+
+type Shared = Arc<Mutex<State>>;
+
+/// Largest request body read, queueing a job only takes a path and a few settings
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Longest request and header line read
+const MAX_LINE_SIZE: u64 = 8 * 1024;
+
+/// How long a connection may go without sending before it is dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Environment variable holding the bearer token every request has to send
+const TOKEN_VAR: &str = "TERES_API_TOKEN";
+
+/// Serves the REST API on `[address:]port` (localhost unless an address is given) and renders
+/// the jobs added through it in order
+pub fn serve(listen: &str, settings: Config) {
+    let Some(token) = std::env::var(TOKEN_VAR)
+        .ok()
+        .filter(|token| !token.is_empty())
+    else {
+        error!(
+            "Set {} to a secret the api clients send as a bearer token before serving",
+            TOKEN_VAR
+        );
+        helpers::exit(exitcode::CONFIG);
+        return;
+    };
+    if settings.advanced.api_folders.is_empty() {
+        warn!("advanced.api_folders is empty, so no videos can be added through the api");
+    }
+    let address = if listen.contains(':') {
+        listen.to_string()
+    } else {
+        format!("127.0.0.1:{}", listen)
+    };
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!("Could not listen on {}: {}", address, error);
+            helpers::exit(exitcode::UNAVAILABLE);
+            return;
+        }
+    };
+    info!("Serving the api on http://{}", address);
+    let host = address
+        .rsplit_once(':')
+        .map_or(address.as_str(), |(host, _)| host)
+        .to_string();
+
+    let state = Shared::default();
+    let renderer = Arc::clone(&state);
+    std::thread::spawn(move || render_jobs(renderer));
+
+    for stream in listener.incoming().flatten() {
+        let state = Arc::clone(&state);
+        let settings = settings.clone();
+        let host = host.clone();
+        let token = token.clone();
+        std::thread::spawn(move || {
+            let result = stream
+                .set_read_timeout(Some(READ_TIMEOUT))
+                .and_then(|()| handle(stream, &state, &settings, &host, &token));
+            if let Err(error) = result {
+                debug!("Could not answer api request: {}", error);
+            }
+        });
+    }
+}
+
+fn render_jobs(state: Shared) {
+    loop {
+        let next = {
+            let mut state = state.lock().unwrap();
+            let job = state.jobs.iter_mut().find(|job| job.status == "queued");
+            job.map(|job| {
+                job.status = String::from("rendering");
+                job.clone()
+            })
+        };
+        let Some(job) = next else {
+            std::thread::sleep(Duration::from_secs(1));
+            continue;
+        };
+
+        let (status, output) = match Render::new(job.input.clone(), job.settings.clone()) {
+            Some(render) => match Rendering::render_video(&render, false, true, job.bar.clone()) {
+                Ok(result) => (result.status, Some(result.output)),
+                Err(error) => {
+                    error!("Could not render {}: {}", job.input.display(), error);
+                    (String::from("failed"), None)
+                }
+            },
+            None => (String::from("failed"), None),
+        };
+
+        let mut state = state.lock().unwrap();
+        if let Some(entry) = state.jobs.iter_mut().find(|entry| entry.id == job.id) {
+            // cancelling kills the processes so the render reports failing
+            if entry.status != "cancelled" {
+                entry.status = status;
+            }
+            entry.output = output;
+        }
+    }
+}
+
+fn handle(
+    stream: TcpStream,
+    state: &Shared,
+    settings: &Config,
+    listen_host: &str,
+    token: &str,
+) -> Result<(), std::io::Error> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader
+        .by_ref()
+        .take(MAX_LINE_SIZE)
+        .read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    let mut content_type = String::new();
+    let mut host = String::new();
+    let mut authorization = String::new();
+    loop {
+        let mut header = String::new();
+        reader.by_ref().take(MAX_LINE_SIZE).read_line(&mut header)?;
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let name = name.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("content-type") {
+                content_type = value.trim().to_lowercase();
+            } else if name.eq_ignore_ascii_case("host") {
+                host = value.trim().to_lowercase();
+            } else if name.eq_ignore_ascii_case("authorization") {
+                authorization = value.trim().to_string();
+            }
+        }
+    }
+    debug!("Api request {} {}", method, path);
+
+    if !allowed_host(&host, listen_host) {
+        return respond(stream, 403, &json!({ "error": "unknown host" }));
+    }
+    let bearer = authorization
+        .split_once(' ')
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, bearer)| bearer.trim());
+    if !bearer.is_some_and(|bearer| same_token(bearer, token)) {
+        return respond(stream, 401, &json!({ "error": "missing or wrong token" }));
+    }
+    if content_length > MAX_BODY_SIZE {
+        return respond(stream, 413, &json!({ "error": "request too large" }));
+    }
+    if method == "POST" && content_type.split(';').next() != Some("application/json") {
+        return respond(
+            stream,
+            415,
+            &json!({ "error": "expected content-type application/json" }),
+        );
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let (code, response) = match (method.as_str(), segments.as_slice()) {
+        ("GET", ["queue"]) => (200, json!(jobs(state))),
+        ("POST", ["queue"]) => add(state, settings, &body),
+        ("GET", ["queue", id]) => match find(state, id) {
+            Some(job) => (200, json!(job)),
+            None => not_found(id),
+        },
+        ("DELETE", ["queue", id]) => cancel(state, id),
+        _ => (404, json!({ "error": "unknown endpoint" })),
+    };
+    respond(stream, code, &response)
+}
+
+/// Whether the host header is an address or the name the api was served on, pages on other
+/// sites reach it through a domain name of their own
+fn allowed_host(host: &str, listen_host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name == "localhost" || name == listen_host || name.parse::<std::net::IpAddr>().is_ok()
+}
+
+fn jobs(state: &Shared) -> Vec<ApiJob> {
+    let state = state.lock().unwrap();
+    state.jobs.iter().map(with_progress).collect()
+}
+
+fn find(state: &Shared, id: &str) -> Option<ApiJob> {
+    let id: u64 = id.parse().ok()?;
+    let state = state.lock().unwrap();
+    state
+        .jobs
+        .iter()
+        .find(|job| job.id == id)
+        .map(with_progress)
+}
+
+fn with_progress(job: &ApiJob) -> ApiJob {
+    let mut job = job.clone();
+    job.progress = match job.status.as_str() {
+        "finished" => 100,
+        "rendering" => job.bar.position() * 100 / job.bar.length().unwrap_or(1).max(1),
+        _ => 0,
+    };
+    job
+}
+
+/// Queues `{"input": "path", "config": {...}}`, the config holding any of the portable values
+/// to change from the server's configuration
+fn add(state: &Shared, settings: &Config, body: &[u8]) -> (u16, Value) {
+    let request: Value = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(error) => return (400, json!({ "error": error.to_string() })),
+    };
+    let Some(input) = request.get("input").and_then(Value::as_str) else {
+        return (400, json!({ "error": "missing input" }));
+    };
+    let input = PathBuf::from(input);
+    if !input.is_file() {
+        return (
+            400,
+            json!({ "error": format!("{} does not exist", input.display()) }),
+        );
+    }
+    if !in_folders(&input, &settings.advanced.api_folders) {
+        return (
+            403,
+            json!({ "error": format!("{} is not in advanced.api_folders", input.display()) }),
+        );
+    }
+
+    let overrides = match request.get("config").map(toml::Value::try_from) {
+        Some(Ok(config)) => {
+            let (overrides, rejected) = config::portable_overrides(&config);
+            if !rejected.is_empty() {
+                return (
+                    400,
+                    json!({ "error": format!("only the server's config may set {}", rejected.join(", ")) }),
+                );
+            }
+            overrides
+        }
+        Some(Err(error)) => return (400, json!({ "error": error.to_string() })),
+        None => vec![],
+    };
+    let job_settings = match settings.with_overrides(&overrides) {
+        Ok(job_settings) => job_settings,
+        Err(error) => return (400, json!({ "error": error })),
+    };
+    if let Some((key, message)) = job_settings.validate().into_iter().next() {
+        return (400, json!({ "error": format!("{}: {}", key, message) }));
+    }
+
+    let mut state = state.lock().unwrap();
+    state.next_id += 1;
+    let id = state.next_id;
+    state.jobs.push(ApiJob {
+        id,
+        input,
+        output: None,
+        status: String::from("queued"),
+        progress: 0,
+        settings: job_settings,
+        bar: ProgressBar::hidden(),
+    });
+    (201, json!({ "id": id }))
+}
+
+/// Whether a file is inside one of the folders, after following links and `..` so neither leads
+/// out of them
+fn in_folders(input: &Path, folders: &[String]) -> bool {
+    let Ok(input) = input.canonicalize() else {
+        return false;
+    };
+    folders
+        .iter()
+        .filter_map(|folder| Path::new(folder).canonicalize().ok())
+        .any(|folder| input.starts_with(folder))
+}
+
+fn cancel(state: &Shared, id: &str) -> (u16, Value) {
+    let mut state = state.lock().unwrap();
+    let job = id
+        .parse::<u64>()
+        .ok()
+        .and_then(|id| state.jobs.iter_mut().find(|job| job.id == id));
+    let Some(job) = job else {
+        return not_found(id);
+    };
+    match job.status.as_str() {
+        "queued" => job.status = String::from("cancelled"),
+        "rendering" => {
+            job.status = String::from("cancelled");
+            control::cancel();
+        }
+        _ => {
+            return (
+                409,
+                json!({ "error": format!("job already {}", job.status) }),
+            )
+        }
+    }
+    (200, json!(with_progress(job)))
+}
+
+fn not_found(id: &str) -> (u16, Value) {
+    (404, json!({ "error": format!("no job {}", id) }))
+}
+
+fn respond(mut stream: TcpStream, code: u16, body: &Value) -> Result<(), std::io::Error> {
+    let reason = match code {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        _ => "Error",
+    };
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
@@ -0,0 +1,198 @@
+use crate::helpers;
+use std::path::Path;
+use std::process::Command;
+
+const NAME: &str = "teres-watch";
+
+/// Registers `teres watch <folder>` to start on login and restart when it crashes
+pub fn install(folder: &Path, interval: u64) {
+    let folder = match folder.canonicalize() {
+        Ok(folder) => folder,
+        Err(error) => {
+            error!("Could not find {}: {}", folder.display(), error);
+            helpers::exit(exitcode::NOINPUT);
+            return;
+        }
+    };
+    let exe = std::env::current_exe().expect("Could not find the teres executable");
+    platform::install(&exe, &folder, interval);
+}
+
+pub fn uninstall() {
+    platform::uninstall();
+}
+
+pub fn start() {
+    platform::start();
+}
+
+pub fn stop() {
+    platform::stop();
+}
+
+#[cfg(not(target_os = "macos"))]
+fn run(program: &str, args: &[&str]) -> bool {
+    debug!("Running {} {}", program, args.join(" "));
+    match Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => true,
+        Ok(output) => {
+            error!(
+                "{} {} failed: {}",
+                program,
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            false
+        }
+        Err(error) => {
+            error!("Could not run {}: {}", program, error);
+            false
+        }
+    }
+}
+
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+mod platform {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn unit_path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap()
+            .join("systemd/user")
+            .join(format!("{}.service", NAME))
+    }
+
+    pub fn install(exe: &Path, folder: &Path, interval: u64) {
+        let unit = format!(
+            "[Unit]
+Description=Teres watch folder renderer
+After=default.target
+
+[Service]
+ExecStart=\"{}\" watch \"{}\" --interval {}
+Restart=on-failure
+RestartSec=10
+
+[Install]
+WantedBy=default.target
+",
+            exe.display(),
+            folder.display(),
+            interval
+        );
+        let path = unit_path();
+        if let Err(error) = std::fs::create_dir_all(path.parent().unwrap())
+            .and_then(|_| std::fs::write(&path, unit))
+        {
+            error!("Could not write {}: {}", path.display(), error);
+            return;
+        }
+        if run("systemctl", &["--user", "daemon-reload"])
+            && run("systemctl", &["--user", "enable", "--now", NAME])
+        {
+            info!(
+                "Installed and started {} watching {}",
+                path.display(),
+                folder.display()
+            );
+        }
+    }
+
+    pub fn uninstall() {
+        run("systemctl", &["--user", "disable", "--now", NAME]);
+        let path = unit_path();
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                run("systemctl", &["--user", "daemon-reload"]);
+                info!("Removed {}", path.display());
+            }
+            Err(error) => error!("Could not remove {}: {}", path.display(), error),
+        }
+    }
+
+    pub fn start() {
+        if run("systemctl", &["--user", "start", NAME]) {
+            info!("Started {}", NAME);
+        }
+    }
+
+    pub fn stop() {
+        if run("systemctl", &["--user", "stop", NAME]) {
+            info!("Stopped {}", NAME);
+        }
+    }
+}
+
+/// Registered as a scheduled task that runs at logon rather than a service, as services need
+/// the executable to speak the service control protocol and run without the user's session
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+
+    pub fn install(exe: &Path, folder: &Path, interval: u64) {
+        let command = format!(
+            "\"{}\" watch \"{}\" --interval {}",
+            exe.display(),
+            folder.display(),
+            interval
+        );
+        if run(
+            "schtasks",
+            &[
+                "/Create", "/F", "/SC", "ONLOGON", "/RL", "LIMITED", "/TN", NAME, "/TR", &command,
+            ],
+        ) && run("schtasks", &["/Run", "/TN", NAME])
+        {
+            info!(
+                "Installed and started task {} watching {}",
+                NAME,
+                folder.display()
+            );
+        }
+    }
+
+    pub fn uninstall() {
+        stop();
+        if run("schtasks", &["/Delete", "/F", "/TN", NAME]) {
+            info!("Removed task {}", NAME);
+        }
+    }
+
+    pub fn start() {
+        if run("schtasks", &["/Run", "/TN", NAME]) {
+            info!("Started {}", NAME);
+        }
+    }
+
+    pub fn stop() {
+        if run("schtasks", &["/End", "/TN", NAME]) {
+            info!("Stopped {}", NAME);
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+
+    pub fn install(_exe: &Path, _folder: &Path, _interval: u64) {
+        unsupported();
+    }
+
+    pub fn uninstall() {
+        unsupported();
+    }
+
+    pub fn start() {
+        unsupported();
+    }
+
+    pub fn stop() {
+        unsupported();
+    }
+
+    fn unsupported() {
+        error!("Installing the watcher as a service is not supported on macOS yet");
+    }
+}
@@ -0,0 +1,74 @@
+use crate::config::Config;
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Formats of the `output.sidecar` setting
+const FORMATS: [&str; 2] = ["toml", "json"];
+
+/// Metadata written next to each rendered video describing how it was made
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Sidecar {
+    pub version: String,
+    pub input: PathBuf,
+    pub settings: Config,
+}
+
+fn path_with(output_path: &Path, format: &str) -> PathBuf {
+    let mut filename = output_path.file_name().unwrap_or_default().to_owned();
+    filename.push(format!(".teres.{}", format));
+    output_path.with_file_name(filename)
+}
+
+/// The sidecar of an output in whichever format it was written, toml when there is none
+pub fn path_for(output_path: &Path) -> PathBuf {
+    FORMATS
+        .iter()
+        .map(|format| path_with(output_path, format))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| path_with(output_path, FORMATS[0]))
+}
+
+/// Whether a file is a sidecar rather than a video
+pub fn is_sidecar(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    FORMATS
+        .iter()
+        .any(|format| path.ends_with(&format!(".teres.{}", format)))
+}
+
+/// Moves the sidecar along with its renamed output, keeping its format
+pub fn rename(output_path: &Path, new_path: &Path) -> Result<(), Error> {
+    let sidecar = path_for(output_path);
+    let format = sidecar.extension().unwrap_or_default().to_string_lossy();
+    std::fs::rename(&sidecar, path_with(new_path, &format))
+}
+
+pub fn write(output_path: &Path, input_path: &Path, settings: &Config) -> Result<(), Error> {
+    let format = settings.output.sidecar.to_lowercase();
+    if format == "off" {
+        return Ok(());
+    }
+    let sidecar = Sidecar {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        input: input_path.to_path_buf(),
+        settings: settings.shareable(),
+    };
+    let contents = if format == "json" {
+        serde_json::to_string_pretty(&sidecar)
+            .map_err(|error| Error::new(ErrorKind::InvalidData, error))?
+    } else {
+        toml::to_string(&sidecar).map_err(|error| Error::new(ErrorKind::InvalidData, error))?
+    };
+    std::fs::write(path_with(output_path, &format), contents)
+}
+
+pub fn read(output_path: &Path) -> Result<Sidecar, Error> {
+    let path = path_for(output_path);
+    let contents = std::fs::read_to_string(&path)?;
+    if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&contents).map_err(|error| Error::new(ErrorKind::InvalidData, error))
+    } else {
+        toml::from_str(&contents).map_err(|error| Error::new(ErrorKind::InvalidData, error))
+    }
+}
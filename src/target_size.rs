@@ -0,0 +1,186 @@
+use crate::config::Config;
+use crate::container::Container;
+use crate::probe;
+use crate::rendering::{pixel_format_args, vp9_cpu_used, Render, COLOR_TAGS};
+use std::path::PathBuf;
+
+/// Share of the target kept free for the container and the bitrate the encoder overshoots by
+const OVERHEAD: f64 = 0.96;
+
+/// Video bitrate in kbit/s that fits a video of `duration` seconds with its audio in
+/// `target_mb` megabytes, none when the audio alone takes it all
+pub fn video_bitrate(target_mb: f32, duration: f64, audio_kbps: f64) -> Option<u32> {
+    let total_kbps = target_mb as f64 * 8000.0 * OVERHEAD / duration;
+    let video_kbps = total_kbps - audio_kbps;
+    (video_kbps >= 1.0).then_some(video_kbps as u32)
+}
+
+/// Video bitrate in kbit/s fitting the render in encoding.target_size_mb, from the length the
+/// output will have and the audio bitrate of its container
+pub fn bitrate(render: &Render) -> Result<u32, String> {
+    let settings = &render.settings;
+    let duration =
+        duration(render).ok_or_else(|| String::from("the length of the video is unknown"))?;
+    let has_audio = matches!(&render.media_info, Some(info) if info.audio.is_some())
+        || !settings.audio.music_path.is_empty();
+    let audio_kbps = if settings.audio.enabled && has_audio {
+        // the untouched audio is kept in a second track at the same bitrate
+        let tracks = 1 + u32::from(settings.audio.keep_original && render.joined.is_empty());
+        let container = Container::of(&render.output_filepath);
+        container
+            .audio_bitrate()
+            .trim_end_matches('k')
+            .parse::<f64>()
+            .unwrap_or(320.0)
+            * tracks as f64
+    } else {
+        0.0
+    };
+    video_bitrate(settings.encoding.target_size_mb, duration, audio_kbps).ok_or_else(|| {
+        format!(
+            "{:.0} seconds with {:.0} kbit/s audio don't fit, leave out the audio or shorten the video",
+            duration, audio_kbps
+        )
+    })
+}
+
+/// Length of the output in seconds, the inputs retimed the way their audio is plus the intro
+/// and outro
+fn duration(render: &Render) -> Option<f64> {
+    let settings = &render.settings;
+    let mut duration = render.media_info.as_ref()?.duration;
+    for source in &render.joined {
+        duration += probe::probe(&source.video_path)?.duration;
+    }
+    duration *= settings.timescale.input as f64;
+    if settings.transform.pingpong {
+        duration *= 2.0;
+    }
+    let segments = settings.timescale.segments();
+    if !segments.is_empty() {
+        duration = segments
+            .iter()
+            .filter(|(start, _, _)| (*start as f64) < duration)
+            .map(|(start, end, speed)| {
+                let end = end.map_or(duration, |end| duration.min(end as f64));
+                (end - *start as f64) / *speed as f64
+            })
+            .sum();
+    }
+    duration /= settings.timescale.output as f64;
+    for (_, _, info) in &render.stitched {
+        duration += info.as_ref()?.duration;
+    }
+    (duration > 0.0).then_some(duration)
+}
+
+/// A pass of a two pass encode at the bitrate fitting encoding.target_size_mb
+pub struct Pass {
+    /// The first pass only gathers the stats the second one is encoded with
+    pub number: u32,
+    /// kbit/s
+    pub bitrate: u32,
+    /// Stats file of both passes, the encoders add their own extensions to it
+    pub stats: PathBuf,
+}
+
+impl Pass {
+    /// Both passes of a render, with the stats kept next to its script
+    pub fn both(render: &Render, bitrate: u32) -> [Pass; 2] {
+        [1, 2].map(|number| Pass {
+            number,
+            bitrate,
+            stats: render.script_path.with_extension("passlog"),
+        })
+    }
+
+    /// Encoder arguments for the video stream, always in software as the gpu encoders don't
+    /// take the stats of an earlier pass
+    pub fn codec_args(&self, settings: &Config) -> Vec<String> {
+        let (bitrate, bufsize) = (
+            format!("{}k", self.bitrate),
+            format!("{}k", self.bitrate * 2),
+        );
+        let stats = self.stats.display().to_string();
+        let number = self.number.to_string();
+        let hevc = settings.encoding.codec.eq_ignore_ascii_case("hevc");
+        let cpu_used = vp9_cpu_used(&settings.encoding.preset_or("veryfast")).to_string();
+        // a slower preset than for quality based encoding, the size is what matters here
+        let preset = settings.encoding.preset_or("medium");
+        let x265_params = format!("pass={}:stats={}.log", self.number, stats);
+        let mut args: Vec<&str> = if settings.encoding.vp9() {
+            vec![
+                "-c:v",
+                "libvpx-vp9",
+                "-row-mt",
+                "1",
+                "-cpu-used",
+                &cpu_used,
+                "-pass",
+                &number,
+                "-passlogfile",
+                &stats,
+            ]
+        } else if hevc {
+            vec![
+                "-c:v",
+                "libx265",
+                "-preset",
+                &preset,
+                "-x265-params",
+                &x265_params,
+            ]
+        } else {
+            vec![
+                "-c:v",
+                "libx264",
+                "-preset",
+                &preset,
+                "-pass",
+                &number,
+                "-passlogfile",
+                &stats,
+            ]
+        };
+        args.extend(["-b:v", &bitrate, "-maxrate", &bitrate, "-bufsize", &bufsize]);
+        args.extend(pixel_format_args(settings));
+        // quicktime and apple devices only play hevc tagged as hvc1
+        if hevc
+            && matches!(
+                Container::named(&settings.encoding.container),
+                Container::Mp4 | Container::Mov
+            )
+        {
+            args.extend(["-tag:v", "hvc1"]);
+        }
+        args.iter()
+            .chain(COLOR_TAGS.iter())
+            .map(|arg| arg.to_string())
+            .collect()
+    }
+
+    /// Removes the stats files the encoders left
+    pub fn clean(&self) {
+        for suffix in ["-0.log", "-0.log.mbtree", ".log", ".log.cutree"] {
+            let mut stats = self.stats.as_os_str().to_owned();
+            stats.push(suffix);
+            let _ = std::fs::remove_file(stats);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn video_bitrate_leaves_room_for_the_audio() {
+        assert_eq!(video_bitrate(10.0, 60.0, 128.0), Some(1152));
+        assert_eq!(video_bitrate(8.0, 100.0, 0.0), Some(614));
+    }
+
+    #[test]
+    fn video_bitrate_refuses_sizes_the_audio_fills() {
+        assert_eq!(video_bitrate(1.0, 600.0, 320.0), None);
+    }
+}
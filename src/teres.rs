@@ -1,13 +1,157 @@
-use crate::cli::Cli;
+use crate::binaries;
+use crate::cli::{Cli, Commands, ConfigCommands, QueueCommands, ServiceCommands};
+use crate::config::Config;
+use crate::control;
+use crate::distributed;
+use crate::doctor;
+use crate::events;
 use crate::helpers;
 use crate::helpers::exit;
+use crate::history;
+use crate::matrix;
+use crate::paths;
+use crate::presets;
+use crate::probe;
+use crate::queue;
+use crate::rename;
 use crate::rendering;
+use crate::reproduce;
+use crate::selftest;
+use crate::sequence;
+use crate::server;
+use crate::service;
+use crate::update;
+use crate::watch;
+use crate::when_done::Action;
+use crate::wizard;
+use clap::CommandFactory;
 use dirs::home_dir;
+use is_terminal::IsTerminal;
 use rfd::FileDialog;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process::{self, Command};
 use std::vec;
 
 pub fn run(cli_args: Cli) -> Option<()> {
+    if let Some(command) = cli_args.command {
+        match command {
+            Commands::Rename {
+                template,
+                paths,
+                dry_run,
+            } => rename::run(&template, &paths, dry_run),
+            Commands::Reproduce { blurred, input } => {
+                check_dependencies();
+                reproduce::run(&blurred, &input);
+            }
+            Commands::Pause => control::pause(),
+            Commands::Resume => control::resume(),
+            Commands::Config { command } => match command {
+                ConfigCommands::Init { force } => wizard::init(force),
+            },
+            Commands::Queue { command } => match command {
+                QueueCommands::List => queue::list(),
+                QueueCommands::Add {
+                    paths,
+                    overrides,
+                    preset,
+                } => queue::add(&paths, &overrides, preset.as_deref()),
+                QueueCommands::Remove { position } => queue::remove(position),
+                QueueCommands::Clear => queue::clear(),
+                QueueCommands::Move { from, to } => queue::move_entry(from, to),
+                QueueCommands::Run { keep_going } => {
+                    check_dependencies();
+                    queue::run(keep_going);
+                }
+                QueueCommands::Export { csv, output } => history::export(csv, output.as_deref()),
+            },
+            Commands::Watch {
+                folder,
+                interval,
+                preset,
+            } => {
+                check_dependencies();
+                let settings = load_config(&[], preset.as_deref());
+                watch::run(folder, interval, settings);
+            }
+            Commands::Service { command } => match command {
+                ServiceCommands::Install { folder, interval } => {
+                    service::install(&folder, interval)
+                }
+                ServiceCommands::Uninstall => service::uninstall(),
+                ServiceCommands::Start => service::start(),
+                ServiceCommands::Stop => service::stop(),
+            },
+            Commands::Worker { port } => {
+                check_dependencies();
+                distributed::worker(port, load_config(&[], None));
+            }
+            Commands::Doctor => doctor::run(),
+            Commands::Update { check } => update::run(check),
+            Commands::Selftest { record } => {
+                check_dependencies();
+                selftest::run(record);
+            }
+            Commands::Completions { shell } => {
+                clap_complete::generate(shell, &mut Cli::command(), "teres", &mut io::stdout())
+            }
+        }
+        return Some(());
+    }
+
+    let mut overrides = cli_args.overrides.clone();
+    // before the other overrides so --set still changes what the platform picks
+    if let Some(overrides_for) = cli_args.export_for.as_deref().and_then(presets::export) {
+        overrides.splice(0..0, overrides_for.iter().map(|item| item.to_string()));
+    }
+    if let Some(layout) = &cli_args.compare {
+        overrides.insert(0, format!("debug.compare={}", layout));
+    }
+    if cli_args.pause {
+        // also covers exiting before the config is loaded
+        helpers::set_pause_on_exit("always");
+        overrides.insert(0, String::from("advanced.pause_on_exit=always"));
+    }
+    if let Some(args) = &cli_args.ffmpeg_args {
+        match helpers::split_args(args) {
+            Some(args) => {
+                let args = toml::Value::Array(args.into_iter().map(toml::Value::String).collect());
+                overrides.insert(0, format!("encoding.extra_args={}", args));
+            }
+            None => {
+                error!("--ffmpeg-args has a quote that isn't closed");
+                exit(exitcode::USAGE);
+            }
+        }
+    }
+    if cli_args.no_audio {
+        overrides.insert(0, String::from("audio.enabled=false"));
+    }
+    if !cli_args.metrics.is_empty() {
+        let metrics = toml::Value::Array(
+            cli_args
+                .metrics
+                .iter()
+                .cloned()
+                .map(toml::Value::String)
+                .collect(),
+        );
+        overrides.insert(0, format!("output.metrics={}", metrics));
+    }
+    if cli_args.deterministic {
+        overrides.insert(0, String::from("advanced.deterministic=true"));
+    }
+
+    if cli_args.print_config {
+        print!(
+            "{}",
+            Config::annotated(&overrides, cli_args.preset.as_deref())
+        );
+        return Some(());
+    }
+
     let using_ui = !cli_args.noui;
 
     let art = vec![
@@ -18,44 +162,93 @@ pub fn run(cli_args: Cli) -> Option<()> {
         "       ██║   ███████╗██║  ██║███████╗███████║",
         "       ╚═╝   ╚══════╝╚═╝  ╚═╝╚══════╝╚══════╝",
     ];
-    eprintln!();
-    for (_, line) in art.iter().enumerate() {
-        eprintln!("{}", line);
+    if cli_args.verbose.log_level_filter() >= log::LevelFilter::Info {
+        eprintln!();
+        for (_, line) in art.iter().enumerate() {
+            eprintln!("{}", line);
+        }
+        eprintln!();
     }
-    eprintln!();
 
-    if cli_args.input.is_empty() && !using_ui {
-        error!("No video(s) inputted");
-        exit(exitcode::NOINPUT);
+    // the first load writes the default config, so the onboarding has to come before it. without
+    // the ui it is left to write the defaults
+    if using_ui && !Config::global_path().exists() && std::io::stdin().is_terminal() {
+        wizard::onboard();
     }
 
-    if (!used_installer().unwrap() && cfg!(target_os = "windows")) || cfg!(target_family = "unix") {
-        let ffmepg = Command::new("ffmpeg").arg("-v").output();
+    let when_done = match &cli_args.when_done {
+        Some(value) => match Action::parse(value) {
+            Some(action) => Some(action),
+            None => {
+                error!(
+                    "Invalid --when-done action {}, expected shutdown, sleep or command:<command>",
+                    value
+                );
+                exit(exitcode::USAGE);
+                None
+            }
+        },
+        None => None,
+    };
 
-        let python = Command::new("python3").arg("-v").output();
+    let variants = match matrix::variants(&cli_args.matrix) {
+        Ok(variants) => variants,
+        Err(arg) => {
+            error!("Invalid --matrix {}, expected key=value,value,...", arg);
+            exit(exitcode::USAGE);
+            vec![]
+        }
+    };
+    if cli_args.safe_mode {
+        warn!("Safe mode enabled, ignoring advanced settings");
+    }
+    let variants: Vec<Config> = variants
+        .into_iter()
+        .map(|variant| {
+            let overrides: Vec<String> =
+                overrides.iter().cloned().chain(variant.overrides).collect();
+            let mut settings = load_config(&overrides, cli_args.preset.as_deref());
+            if cli_args.background && settings.advanced.deterministic {
+                warn!("Not rendering in the background, advanced.deterministic pins the threads");
+            } else if cli_args.background {
+                settings.background();
+            }
+            if cli_args.safe_mode {
+                settings.safe_mode();
+            }
+            settings.encoding.filename_template += &variant.suffix;
+            settings
+        })
+        .collect();
 
-        let vspipe = Command::new("vspipe").arg("-v").output();
+    if let Some(listen) = &cli_args.serve {
+        check_dependencies();
+        let settings = variants.into_iter().next()?;
+        server::serve(listen, settings);
+        return Some(());
+    }
 
-        if ffmepg.is_err() {
-            error!("FFmpeg is not installed");
-            exit(exitcode::UNAVAILABLE)
-        }
-        if python.is_err() {
-            error!("Python is not installed");
-            exit(exitcode::UNAVAILABLE)
-        }
-        if vspipe.is_err() {
-            error!("VapourSynth is not installed");
-            exit(exitcode::UNAVAILABLE)
-        }
+    if cli_args.input.is_empty() && !using_ui {
+        error!("No video(s) inputted");
+        exit(exitcode::NOINPUT);
+    }
+
+    // workers need the dependencies instead when rendering remotely
+    if cli_args.workers.is_empty() {
+        check_dependencies();
     }
 
     let mut rendering = rendering::Rendering {
         queue: vec![],
         renders_queued: false,
+        remux_only: cli_args.remux_only,
+        keep_going: cli_args.keep_going,
+        failed: vec![],
+        jobs: vec![],
     };
 
-    let files = if cli_args.input.is_empty() {
+    let picked = cli_args.input.is_empty();
+    let files = if picked {
         eprintln!("Select input video(s)");
         let diag_files = FileDialog::new()
             .add_filter("Video", &["mp4", "mov", "mkv", "avi"])
@@ -67,35 +260,239 @@ pub fn run(cli_args: Cli) -> Option<()> {
         }
         diag_files?
     } else {
-        let input = cli_args.input;
-        input
-            .iter()
-            .map(|file| std::path::Path::new(file).to_path_buf())
-            .collect()
+        split_commas(cli_args.input)
     };
+    // shortcuts and urls dropped onto teres are followed to the video they point to
+    let files: Vec<PathBuf> = files
+        .into_iter()
+        .map(|video| match paths::resolve(&video) {
+            Ok(resolved) => resolved,
+            Err(error) => {
+                error!("Could not read {}, {}", video.display(), error);
+                exit(exitcode::NOINPUT);
+                video
+            }
+        })
+        .collect();
 
-    for video in files {
-        if !video.exists() {
+    if cli_args.info {
+        for video in files {
+            if !video.exists() && sequence::first(&video).is_none() {
+                error!("Video {} does not exist", video.display());
+                exit(exitcode::NOINPUT);
+            }
+            match probe::probe(&video) {
+                Some(media_info) => eprintln!("{}\n{}", video.display(), media_info),
+                None => error!("Could not probe {}", video.display()),
+            }
+        }
+        return Some(());
+    }
+
+    for video in &files {
+        if !video.exists() && sequence::first(video).is_none() {
             error!("Video {} does not exist", video.display());
             exit(exitcode::NOINPUT);
         }
-        let render = rendering::Render::new(video);
-        rendering.queue_render(render?)
     }
 
-    let clone = rendering.clone().queue;
+    if cli_args.concat && files.len() < 2 {
+        error!("--concat needs at least two videos to join");
+        exit(exitcode::USAGE);
+    }
 
-    ctrlc::set_handler(move || {
-        helpers::clean_temp(clone.to_vec());
-        process::exit(exitcode::OK)
-    })
-    .expect("Error setting Ctrl-C handler");
+    // videos picked in the file dialog can each get their own blur, unless a matrix renders them
+    // with several settings the answers would overwrite
+    let custom: HashMap<PathBuf, wizard::FileSettings> =
+        if picked && !cli_args.concat && variants.len() == 1 && io::stdin().is_terminal() {
+            files
+                .iter()
+                .filter_map(|video| Some((video.clone(), wizard::per_file(video, &variants[0])?)))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+    // inputs that can't be rendered, like audio only or corrupt files
+    let mut skipped = vec![];
+    if cli_args.workers.is_empty() {
+        if cli_args.concat {
+            for settings in &variants {
+                let render = rendering::Render::with_joined(
+                    files[0].clone(),
+                    files[1..].to_vec(),
+                    settings.clone(),
+                );
+                match render {
+                    Some(render) => rendering.queue_render(render),
+                    None => skipped.push(format!("{} (joined)", files[0].display())),
+                }
+            }
+        } else {
+            for video in files {
+                for settings in &variants {
+                    let mut settings = auto_settings(&video, settings, cli_args.auto_settings);
+                    if let Some(custom) = custom.get(&video) {
+                        custom.apply(&mut settings);
+                    }
+                    // the reason was logged, the rest of the queue still renders
+                    match rendering::Render::new(video.clone(), settings) {
+                        Some(render) => rendering.queue_render(render),
+                        None => skipped.push(video.display().to_string()),
+                    }
+                }
+            }
+        }
 
-    debug!("Queued renders");
-    rendering.render_videos();
+        let clone = rendering.clone().queue;
+
+        ctrlc::set_handler(move || {
+            helpers::clean_temp(clone.to_vec());
+            helpers::restore_title();
+            events::close();
+            process::exit(exitcode::OK)
+        })
+        .expect("Error setting Ctrl-C handler");
+
+        debug!("Queued renders");
+        rendering.render_videos();
+        if !skipped.is_empty() {
+            skipped.dedup();
+            error!("Skipped {}", skipped.join(", "));
+            rendering.failed.append(&mut skipped);
+        }
+    } else {
+        let videos = files
+            .iter()
+            .flat_map(|video| {
+                variants.iter().map(|settings| {
+                    let mut settings = auto_settings(video, settings, cli_args.auto_settings);
+                    if let Some(custom) = custom.get(video) {
+                        custom.apply(&mut settings);
+                    }
+                    (video.clone(), settings)
+                })
+            })
+            .collect();
+        rendering.jobs = distributed::coordinate(&cli_args.workers, videos);
+        rendering.failed = rendering
+            .jobs
+            .iter()
+            .filter(|job| job.status != "finished")
+            .map(|job| job.input.display().to_string())
+            .collect();
+    }
+
+    if let Some(report) = &cli_args.report {
+        history::write_report(&rendering.jobs, report);
+    }
+
+    if let Some(action) = when_done {
+        action.run();
+    }
+    if !rendering.failed.is_empty() {
+        exit(exitcode::SOFTWARE);
+    }
     Some(())
 }
 
+/// Splits inputs like `a.mp4,b.mp4` from scripts written for the old comma separated input
+/// into their paths, unless the whole thing is a path with commas in its name
+fn split_commas(input: Vec<PathBuf>) -> Vec<PathBuf> {
+    input
+        .into_iter()
+        .flat_map(|path| {
+            let joined = path.to_string_lossy().to_string();
+            let parts: Vec<PathBuf> = joined.split(',').map(PathBuf::from).collect();
+            if !path.exists() && parts.len() > 1 && parts.iter().all(|part| part.exists()) {
+                parts
+            } else {
+                vec![path]
+            }
+        })
+        .collect()
+}
+
+/// The settings for a video, with `--auto-settings` picking the blur for its frame rate
+fn auto_settings(video: &Path, settings: &Config, auto: bool) -> Config {
+    let mut settings = settings.clone();
+    if !auto {
+        return settings;
+    }
+    match probe::probe(video).and_then(|info| info.fps_f64()) {
+        Some(fps) => {
+            let source = presets::auto(&mut settings, fps);
+            info!(
+                "{} is {:.0} fps, using the {} fps settings: interpolated to {} fps, blended to {} fps with an amount of {}",
+                video.display(),
+                fps,
+                source,
+                settings.interpolation.fps,
+                settings.blending.output_fps,
+                settings.blending.amount
+            );
+        }
+        None => warn!(
+            "Could not read the frame rate of {}, keeping the configured settings",
+            video.display()
+        ),
+    }
+    settings
+}
+
+/// Loads the configuration, exiting with every problem found in it before anything is rendered
+pub fn load_config(overrides: &[String], preset: Option<&str>) -> Config {
+    let (loaded, provenance) = Config::try_load(overrides, preset);
+    // a value of the wrong type is reported the same way as one that fails validation
+    let errors = match &loaded {
+        Ok(settings) => settings
+            .validate()
+            .into_iter()
+            .map(|(key, message)| (key.to_string(), message))
+            .collect(),
+        Err(error) => vec![error.clone()],
+    };
+    if let (Ok(mut settings), true) = (loaded, errors.is_empty()) {
+        if settings.advanced.deterministic {
+            settings.deterministic();
+        }
+        return settings;
+    }
+    for (key, message) in errors {
+        error!(
+            "Invalid config value {} (from {}): {}",
+            key,
+            provenance.get(&key).map_or("default", |source| source),
+            message
+        );
+    }
+    exit(exitcode::CONFIG);
+    unreachable!()
+}
+
+pub fn check_dependencies() {
+    if !used_installer().unwrap_or(false) {
+        let ffmepg = Command::new(binaries::ffmpeg()).arg("-v").output();
+
+        let python = Command::new("python3").arg("-v").output();
+
+        let vspipe = Command::new(binaries::vspipe()).arg("-v").output();
+
+        if ffmepg.is_err() {
+            error!("FFmpeg is not installed, run teres doctor to check what is missing");
+            exit(exitcode::UNAVAILABLE)
+        }
+        if python.is_err() {
+            error!("Python is not installed, run teres doctor to check what is missing");
+            exit(exitcode::UNAVAILABLE)
+        }
+        if vspipe.is_err() {
+            error!("VapourSynth is not installed, run teres doctor to check what is missing");
+            exit(exitcode::UNAVAILABLE)
+        }
+    }
+}
+
 pub fn create_temp_path(
     video_path: std::path::PathBuf,
 ) -> Result<std::path::PathBuf, std::io::Error> {
@@ -108,17 +505,9 @@ pub fn create_temp_path(
     Ok(temp_path)
 }
 
-#[cfg(target_os = "windows")]
-pub fn used_installer() -> Result<bool, std::io::Error> {
-    let path = std::env::current_exe()?;
-    let parent_path = path.parent().unwrap();
-    Ok((parent_path.join("lib/vapoursynth/VSPipe.exe").exists()
-        && parent_path.join("lib/ffmpeg/ffmpeg.exe").exists())
-        || (parent_path.join("lib/vapoursynth/vspipe").exists()
-            && parent_path.join("lib/ffmpeg/ffmpeg").exists()))
-}
-
-#[cfg(target_family = "unix")]
+/// Whether teres runs from the windows installer or a portable copy, with ffmpeg and
+/// VapourSynth in the lib folder next to it
 pub fn used_installer() -> Result<bool, std::io::Error> {
-    Ok(false)
+    Ok(binaries::bundled("ffmpeg", "ffmpeg").is_some()
+        && binaries::bundled("vspipe", "vapoursynth").is_some())
 }
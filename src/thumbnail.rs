@@ -0,0 +1,46 @@
+use crate::config::Output;
+use crate::probe;
+use crate::sequence;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Poster frame written next to the output, `<output name>.<thumbnail_format>`. For png
+/// sequences it goes next to the folder of the frames, named after the sequence
+pub fn path_for(output_path: &Path, settings: &Output) -> PathBuf {
+    let extension = settings.thumbnail_format.to_lowercase();
+    let folder = output_path.parent().and_then(Path::parent);
+    match (sequence::name(output_path), folder) {
+        (Some(name), Some(folder)) => folder.join(format!("{}.{}", name, extension)),
+        _ => output_path.with_extension(extension),
+    }
+}
+
+/// Extracts the frame at `output.thumbnail`, either `middle` or a timestamp in seconds, from
+/// the rendered video
+pub fn write(ffmpeg: &str, output_path: &Path, settings: &Output) -> Result<PathBuf, String> {
+    let seconds = if settings.thumbnail.eq_ignore_ascii_case("middle") {
+        probe::probe(output_path)
+            .map(|info| info.duration / 2.0)
+            .ok_or_else(|| format!("Could not probe {}", output_path.display()))?
+    } else {
+        settings
+            .thumbnail
+            .parse::<f64>()
+            .map_err(|_| format!("{} is not a timestamp", settings.thumbnail))?
+    };
+
+    let thumbnail = path_for(output_path, settings);
+    let output = Command::new(ffmpeg)
+        .args(["-loglevel", "error", "-hide_banner", "-nostats", "-y"])
+        .args(["-ss", &seconds.to_string(), "-i"])
+        .arg(output_path)
+        // mjpeg takes 2 to 31, 2 being the best
+        .args(["-frames:v", "1", "-q:v", "2", "-update", "1"])
+        .arg(&thumbnail)
+        .output()
+        .map_err(|error| error.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(thumbnail)
+}
@@ -0,0 +1,159 @@
+use crate::bootstrap::{download, for_platform, latest_release, unpack};
+use crate::helpers::exit;
+use minisign_verify::{PublicKey, Signature};
+use serde_json::Value;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::Duration;
+
+const REPO: &str = "animafps/teres";
+
+/// minisign public key releases are signed with, set when building a release so copies built
+/// from source without it never replace themselves
+const PUBLIC_KEY: Option<&str> = option_env!("TERES_UPDATE_PUBLIC_KEY");
+
+/// Replaces teres with the latest release when it is newer, or with `check` only tells whether
+/// there is one
+pub fn run(check: bool) {
+    if let Err(error) = update(check) {
+        error!("Could not update teres: {}", error);
+        exit(exitcode::UNAVAILABLE);
+    }
+}
+
+fn update(check: bool) -> Result<(), String> {
+    let release = latest_release(REPO)?;
+    let latest = release["tag_name"]
+        .as_str()
+        .ok_or("the release has no version")?
+        .trim_start_matches('v');
+    let current = env!("CARGO_PKG_VERSION");
+    if version(latest) <= version(current) {
+        info!("teres {} is the latest version", current);
+        return Ok(());
+    }
+    info!("teres {} is available, this is {}", latest, current);
+    if check {
+        return Ok(());
+    }
+    let public_key = PUBLIC_KEY
+        .and_then(|key| PublicKey::from_base64(key.trim()).ok())
+        .ok_or_else(|| {
+            format!(
+                "this build can't check the signatures of releases, download it from https://github.com/{}/releases/latest instead",
+                REPO
+            )
+        })?;
+
+    let assets = release["assets"]
+        .as_array()
+        .ok_or("the release has no downloads")?;
+    let asset = assets
+        .iter()
+        .map(|asset| asset["name"].as_str().unwrap_or_default())
+        .find(|name| for_platform(name) && !is_extra(name))
+        .ok_or("the release has no build for this platform")?;
+    let url = download_url(assets, asset).ok_or("the release has no download address")?;
+    let signature = signature(assets, asset)?;
+
+    let folder = std::env::temp_dir().join("teres-update");
+    std::fs::create_dir_all(&folder).map_err(|error| error.to_string())?;
+    let result = (|| {
+        let downloaded = folder.join(asset);
+        info!("Downloading {}", asset);
+        download(url, &downloaded)?;
+        let contents = std::fs::read(&downloaded).map_err(|error| error.to_string())?;
+        // the trusted comment is signed too, it names the file so a different signed build
+        // can't be passed off as this one
+        if public_key.verify(&contents, &signature, false).is_err()
+            || !signature.trusted_comment().contains(asset)
+        {
+            return Err(format!(
+                "the signature of {} does not match, it was not installed",
+                asset
+            ));
+        }
+        let exe = format!("teres{}", std::env::consts::EXE_SUFFIX);
+        let binary = unpack(&downloaded, &folder, &|path: &Path| {
+            path.file_name() == Some(OsStr::new(&exe))
+        })?;
+        replace(&binary)
+    })();
+    let _ = std::fs::remove_dir_all(&folder);
+    result?;
+    info!("Updated teres to {}", latest);
+    Ok(())
+}
+
+/// `1.2.3` as numbers to compare, without a pre-release suffix
+fn version(version: &str) -> Vec<u64> {
+    version
+        .split('-')
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+/// Release assets that are not the portable build: installers, packages and checksums
+fn is_extra(name: &str) -> bool {
+    let name = name.to_lowercase();
+    [
+        ".msi", ".deb", ".rpm", ".dmg", ".pkg", ".sha256", ".sig", ".asc", "setup", "sums",
+    ]
+    .iter()
+    .any(|extra| name.contains(extra))
+}
+
+fn download_url<'a>(assets: &'a [Value], name: &str) -> Option<&'a str> {
+    assets
+        .iter()
+        .find(|asset| asset["name"].as_str() == Some(name))
+        .and_then(|asset| asset["browser_download_url"].as_str())
+}
+
+/// The minisign signature of an asset, published as `<asset>.minisig` in the release
+fn signature(assets: &[Value], name: &str) -> Result<Signature, String> {
+    let url = download_url(assets, &format!("{}.minisig", name)).ok_or_else(|| {
+        format!(
+            "the release has no signature for {}, download it from https://github.com/{}/releases/latest instead",
+            name, REPO
+        )
+    })?;
+    let contents = ureq::get(url)
+        .set("User-Agent", "teres")
+        .timeout(Duration::from_secs(30))
+        .call()
+        .map_err(|error| error.to_string())?
+        .into_string()
+        .map_err(|error| error.to_string())?;
+    Signature::decode(&contents).map_err(|error| format!("invalid signature: {}", error))
+}
+
+/// Swaps the running executable for a new one. Windows can't overwrite a running program but
+/// can rename it, so the old one is moved aside and removed on the next update
+fn replace(binary: &Path) -> Result<(), String> {
+    let current = std::env::current_exe().map_err(|error| error.to_string())?;
+    let old = current.with_extension("old");
+    let _ = std::fs::remove_file(&old);
+    // staged next to teres so the final rename stays on the same drive
+    let staged = current.with_extension("new");
+    std::fs::copy(binary, &staged).map_err(|error| {
+        format!(
+            "could not write to {}, it may need to be updated as an administrator or with your package manager: {}",
+            current.parent().unwrap_or(&current).display(),
+            error
+        )
+    })?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))
+            .map_err(|error| error.to_string())?;
+    }
+    if cfg!(target_os = "windows") {
+        std::fs::rename(&current, &old).map_err(|error| error.to_string())?;
+    }
+    std::fs::rename(&staged, &current).map_err(|error| error.to_string())
+}
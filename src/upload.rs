@@ -0,0 +1,126 @@
+use crate::config::{Credentials, Upload};
+use serde_json::{json, Value};
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+/// Uploads a finished render with `upload.provider`, returning where it can be found
+pub fn upload(settings: &Upload, output_path: &Path) -> Result<String, String> {
+    match settings.provider.to_lowercase().as_str() {
+        "youtube" => youtube(settings, output_path),
+        "s3" => s3(settings, output_path),
+        "sftp" => sftp(settings, output_path),
+        provider => Err(format!("Unknown upload provider {}", provider)),
+    }
+}
+
+/// Link to an uploaded file under `upload.public_url`, or `fallback` without one
+fn public_link(settings: &Upload, key: &str, fallback: String) -> String {
+    if settings.public_url.is_empty() {
+        fallback
+    } else {
+        format!("{}/{}", settings.public_url.trim_end_matches('/'), key)
+    }
+}
+
+fn file_name(output_path: &Path) -> String {
+    output_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Copies the render to a bucket with the aws cli, which brings its own credentials
+fn s3(settings: &Upload, output_path: &Path) -> Result<String, String> {
+    let key = match settings.s3_prefix.trim_matches('/') {
+        "" => file_name(output_path),
+        prefix => format!("{}/{}", prefix, file_name(output_path)),
+    };
+    let destination = format!("s3://{}/{}", settings.s3_bucket, key);
+    let mut command = Command::new("aws");
+    command.args(["s3", "cp", "--only-show-errors"]);
+    command.arg(output_path).arg(&destination);
+    if !settings.s3_endpoint.is_empty() {
+        command.args(["--endpoint-url", &settings.s3_endpoint]);
+    }
+    run(command, "aws")?;
+    Ok(public_link(settings, &key, destination))
+}
+
+/// Copies the render over ssh with scp, using the keys and hosts set up for ssh
+fn sftp(settings: &Upload, output_path: &Path) -> Result<String, String> {
+    let destination = settings.sftp_destination.trim_end_matches('/');
+    let mut command = Command::new("scp");
+    command.args(["-q", "-B"]).arg(output_path);
+    command.arg(format!("{}/", destination));
+    run(command, "scp")?;
+    let name = file_name(output_path);
+    let fallback = format!("{}/{}", destination, name);
+    Ok(public_link(settings, &name, fallback))
+}
+
+fn run(mut command: Command, program: &str) -> Result<(), String> {
+    let output = command
+        .output()
+        .map_err(|error| format!("Could not run {}: {}", program, error))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Uploads to YouTube with the resumable upload API, authorized by an OAuth refresh token
+fn youtube(settings: &Upload, output_path: &Path) -> Result<String, String> {
+    let credentials = Credentials::load();
+    let token: Value = ureq::post("https://oauth2.googleapis.com/token")
+        .timeout(Duration::from_secs(30))
+        .send_form(&[
+            ("client_id", settings.youtube_client_id.as_str()),
+            ("client_secret", credentials.youtube_client_secret.as_str()),
+            ("refresh_token", credentials.youtube_refresh_token.as_str()),
+            ("grant_type", "refresh_token"),
+        ])
+        .map_err(|error| format!("Could not authorize with YouTube: {}", error))?
+        .into_json()
+        .map_err(|error| error.to_string())?;
+    let access_token = token["access_token"]
+        .as_str()
+        .ok_or("YouTube did not return an access token")?;
+    let authorization = format!("Bearer {}", access_token);
+
+    let title = output_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let session = ureq::post(
+        "https://www.googleapis.com/upload/youtube/v3/videos?uploadType=resumable&part=snippet,status",
+    )
+    .timeout(Duration::from_secs(30))
+    .set("Authorization", &authorization)
+    .set("X-Upload-Content-Type", "video/*")
+    .send_json(json!({
+        "snippet": { "title": title },
+        "status": { "privacyStatus": settings.youtube_privacy.to_lowercase() },
+    }))
+    .map_err(|error| format!("Could not start the YouTube upload: {}", error))?;
+    let location = session
+        .header("Location")
+        .ok_or("YouTube did not return an upload address")?
+        .to_string();
+
+    let file = std::fs::File::open(output_path).map_err(|error| error.to_string())?;
+    let video: Value = ureq::put(&location)
+        .set("Authorization", &authorization)
+        .set("Content-Type", "video/*")
+        .send(file)
+        .map_err(|error| format!("Could not upload to YouTube: {}", error))?
+        .into_json()
+        .map_err(|error| error.to_string())?;
+    let id = video["id"]
+        .as_str()
+        .ok_or("YouTube did not return a video id")?;
+    Ok(format!("https://youtu.be/{}", id))
+}
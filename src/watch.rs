@@ -0,0 +1,253 @@
+use crate::config::Config;
+use crate::events;
+use crate::helpers;
+use crate::history::format_timestamp;
+use crate::rendering::{Render, Rendering};
+use crate::schedule::{self, Guard};
+use crate::sidecar;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const VIDEO_EXTENSIONS: [&str; 4] = ["mp4", "mov", "mkv", "avi"];
+/// Times an interrupted render is retried before it is given up on
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Persisted so a restarted watcher can pick up where it crashed
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct State {
+    /// Set while watching, so finding it set on startup means the last run didn't exit cleanly
+    running: bool,
+    in_flight: Option<InFlight>,
+    processed: Vec<PathBuf>,
+    failed: Vec<PathBuf>,
+    /// Videos the watcher rendered, which are never picked up as new ones even without sidecars
+    #[serde(default)]
+    outputs: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct InFlight {
+    path: PathBuf,
+    attempts: u32,
+}
+
+impl State {
+    fn path() -> PathBuf {
+        Config::global_path().with_file_name("watch_state.json")
+    }
+
+    fn load() -> State {
+        std::fs::read_to_string(State::path())
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::from)
+            .and_then(|contents| std::fs::write(State::path(), contents));
+        if let Err(error) = result {
+            warn!("Could not save watch state: {}", error);
+        }
+    }
+}
+
+/// Renders every new video that appears in the folder until stopped
+pub fn run(folder: PathBuf, interval: u64, settings: Config) {
+    if !folder.is_dir() {
+        error!("{} is not a folder", folder.display());
+        helpers::exit(exitcode::NOINPUT);
+    }
+
+    let state = Arc::new(Mutex::new(State::load()));
+    {
+        let mut state = state.lock().unwrap();
+        if state.running {
+            incident("Watcher did not shut down cleanly last time");
+        }
+        state.running = true;
+        state.save();
+    }
+
+    let current: Arc<Mutex<Option<Render>>> = Arc::new(Mutex::new(None));
+    let handler_state = state.clone();
+    let handler_current = current.clone();
+    ctrlc::set_handler(move || {
+        if let Some(render) = handler_current.lock().unwrap().take() {
+            helpers::clean_temp(vec![render]);
+        }
+        // the interrupted render stays in flight and is requeued on the next start
+        let mut state = handler_state.lock().unwrap();
+        state.running = false;
+        state.save();
+        helpers::restore_title();
+        events::close();
+        std::process::exit(exitcode::OK)
+    })
+    .expect("Error setting Ctrl-C handler");
+
+    info!("Watching {} for new videos", folder.display());
+    let mut sizes: HashMap<PathBuf, u64> = HashMap::new();
+    loop {
+        let next = {
+            let mut state = state.lock().unwrap();
+            match state.in_flight.clone() {
+                Some(mut in_flight) => {
+                    if in_flight.attempts >= MAX_ATTEMPTS {
+                        incident(&format!(
+                            "Giving up on {} after {} interrupted attempts",
+                            in_flight.path.display(),
+                            in_flight.attempts
+                        ));
+                        state.failed.push(in_flight.path);
+                        state.in_flight = None;
+                        state.save();
+                        None
+                    } else {
+                        if in_flight.attempts > 0 {
+                            incident(&format!(
+                                "Requeueing interrupted render of {}",
+                                in_flight.path.display()
+                            ));
+                        }
+                        in_flight.attempts += 1;
+                        state.in_flight = Some(in_flight.clone());
+                        state.save();
+                        Some(in_flight.path)
+                    }
+                }
+                None => {
+                    let video = find_new_video(&folder, &state, &mut sizes);
+                    if let Some(video) = &video {
+                        state.in_flight = Some(InFlight {
+                            path: video.clone(),
+                            attempts: 1,
+                        });
+                        state.save();
+                    }
+                    video
+                }
+            }
+        };
+
+        match next {
+            Some(video) => {
+                let (mut outputs, finished) = render(&video, &settings, &current);
+                let mut state = state.lock().unwrap();
+                state.in_flight = None;
+                if finished {
+                    state.processed.push(video);
+                } else {
+                    incident(&format!("Failed to render {}", video.display()));
+                    state.failed.push(video);
+                }
+                state.outputs.append(&mut outputs);
+                state.save();
+            }
+            None => std::thread::sleep(Duration::from_secs(interval)),
+        }
+    }
+}
+
+/// Renders a video, returning the outputs it produced, corrupt ones included, and whether it
+/// finished. A failure leaves the watcher running for the next video
+fn render(
+    video: &Path,
+    settings: &Config,
+    current: &Arc<Mutex<Option<Render>>>,
+) -> (Vec<PathBuf>, bool) {
+    schedule::wait(&settings.schedule);
+    let render = match Render::new(video.to_path_buf(), settings.clone()) {
+        Some(render) => render,
+        None => {
+            error!("Could not queue {}", video.display());
+            return (vec![], false);
+        }
+    };
+    *current.lock().unwrap() = Some(render.clone());
+    let guard = Guard::start(&settings.schedule);
+
+    let mut rendering = Rendering {
+        queue: vec![],
+        renders_queued: false,
+        remux_only: false,
+        keep_going: true,
+        failed: vec![],
+        jobs: vec![],
+    };
+    rendering.queue_render(render);
+    rendering.render_videos();
+    if let Some(guard) = guard {
+        guard.finish();
+    }
+    *current.lock().unwrap() = None;
+    let outputs = rendering
+        .jobs
+        .into_iter()
+        .map(|job| job.output)
+        .filter(|output| !output.as_os_str().is_empty())
+        .collect();
+    (outputs, rendering.failed.is_empty())
+}
+
+/// Finds a video that hasn't been processed yet and has finished being written, which is
+/// assumed once its size stays the same between two scans
+fn find_new_video(
+    folder: &Path,
+    state: &State,
+    sizes: &mut HashMap<PathBuf, u64>,
+) -> Option<PathBuf> {
+    let mut candidates: Vec<PathBuf> = folder
+        .read_dir()
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| VIDEO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+                    .unwrap_or(false)
+                // outputs of teres itself
+                && !sidecar::path_for(path).exists()
+                && !state.outputs.contains(path)
+                && !state.processed.contains(path)
+                && !state.failed.contains(path)
+        })
+        .collect();
+    candidates.sort();
+
+    for path in candidates {
+        let size = std::fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+        let previous = sizes.insert(path.clone(), size);
+        if size > 0 && previous == Some(size) {
+            sizes.remove(&path);
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Logs something unexpected to stderr and the watch log file
+fn incident(message: &str) {
+    warn!("{}", message);
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let log_file = Config::global_path().with_file_name("watch.log");
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .and_then(|mut file| writeln!(file, "{} {}", format_timestamp(timestamp), message));
+    if let Err(error) = result {
+        debug!("Could not write to watch log: {}", error);
+    }
+}
@@ -0,0 +1,95 @@
+use std::process::Command;
+
+/// Marks a `--when-done` value as a command to run
+const PREFIX: &str = "command:";
+
+/// What to do once every queued render has finished
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Shutdown,
+    Sleep,
+    Command(String),
+}
+
+impl Action {
+    pub fn parse(value: &str) -> Option<Action> {
+        match value.to_lowercase().as_str() {
+            "shutdown" => Some(Action::Shutdown),
+            "sleep" => Some(Action::Sleep),
+            // the prefix in any case, the command itself as written
+            _ => value
+                .get(..PREFIX.len())
+                .filter(|prefix| prefix.eq_ignore_ascii_case(PREFIX))
+                .map(|_| value[PREFIX.len()..].trim())
+                .filter(|command| !command.is_empty())
+                .map(|command| Action::Command(command.to_string())),
+        }
+    }
+
+    pub fn run(&self) {
+        let (program, args) = match self {
+            Action::Shutdown => {
+                info!("Shutting down in a minute");
+                shutdown_command()
+            }
+            Action::Sleep => {
+                info!("Going to sleep");
+                sleep_command()
+            }
+            Action::Command(command) => {
+                info!("Running {}", command);
+                shell_command(command)
+            }
+        };
+
+        match Command::new(program).args(&args).status() {
+            Ok(status) if status.success() => (),
+            Ok(status) => error!("{} {} failed with {}", program, args.join(" "), status),
+            Err(error) => error!("Could not run {}: {}", program, error),
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn shutdown_command() -> (&'static str, Vec<String>) {
+    (
+        "shutdown",
+        vec!["/s".to_string(), "/t".to_string(), "60".to_string()],
+    )
+}
+
+#[cfg(target_family = "unix")]
+fn shutdown_command() -> (&'static str, Vec<String>) {
+    ("shutdown", vec!["-h".to_string(), "+1".to_string()])
+}
+
+#[cfg(target_os = "windows")]
+fn sleep_command() -> (&'static str, Vec<String>) {
+    (
+        "rundll32.exe",
+        vec![
+            "powrprof.dll,SetSuspendState".to_string(),
+            "0,1,0".to_string(),
+        ],
+    )
+}
+
+#[cfg(target_os = "macos")]
+fn sleep_command() -> (&'static str, Vec<String>) {
+    ("pmset", vec!["sleepnow".to_string()])
+}
+
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+fn sleep_command() -> (&'static str, Vec<String>) {
+    ("systemctl", vec!["suspend".to_string()])
+}
+
+#[cfg(target_os = "windows")]
+fn shell_command(command: &str) -> (&'static str, Vec<String>) {
+    ("cmd", vec!["/C".to_string(), command.to_string()])
+}
+
+#[cfg(target_family = "unix")]
+fn shell_command(command: &str) -> (&'static str, Vec<String>) {
+    ("sh", vec!["-c".to_string(), command.to_string()])
+}
@@ -0,0 +1,409 @@
+use crate::binaries;
+use crate::config::{Config, DEFAULT_CONFIG};
+use crate::helpers;
+use crate::presets;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Asks for the most common settings and writes them to the global config file
+pub fn init(force: bool) {
+    let config_file = Config::global_path();
+    if config_file.exists()
+        && !force
+        && !confirm(
+            &format!("{} already exists, overwrite it?", config_file.display()),
+            false,
+        )
+    {
+        return;
+    }
+
+    let mut config = DEFAULT_CONFIG.to_string();
+
+    let gpu = confirm("Use GPU accelerated encoding?", false);
+    config = set_value(&config, "advanced.encoding", "gpu", &gpu.to_string());
+    if gpu {
+        let gpu_type = loop {
+            let default = if cfg!(target_os = "macos") {
+                "apple"
+            } else {
+                "nvidia"
+            };
+            let gpu_type = ask("GPU type (nvidia/amd/intel/apple/vaapi)", default).to_lowercase();
+            let encoder = match gpu_type.as_str() {
+                "nvidia" => "h264_nvenc",
+                "amd" => "h264_amf",
+                "intel" => "h264_qsv",
+                "apple" => "h264_videotoolbox",
+                "vaapi" => "h264_vaapi",
+                _ => {
+                    eprintln!("Expected nvidia, amd, intel, apple or vaapi");
+                    continue;
+                }
+            };
+            if encoder_available(encoder)
+                || confirm(
+                    &format!(
+                        "Your ffmpeg does not support {} so rendering will fail, use it anyway?",
+                        encoder
+                    ),
+                    false,
+                )
+            {
+                break gpu_type;
+            }
+        };
+        config = set_value(
+            &config,
+            "advanced.encoding",
+            "gpu_type",
+            &format!("\"{}\"", gpu_type),
+        );
+    }
+
+    let interpolated_fps = ask_number("Interpolated fps", 480.0, |fps| {
+        (fps > 0.0).then_some(()).ok_or("must be above 0")
+    });
+    config = set_value(
+        &config,
+        "interpolation",
+        "fps",
+        &format!("{:?}", interpolated_fps),
+    );
+
+    let output_fps = ask_number("Output fps", 60.0, |fps| {
+        if fps <= 0.0 || fps.fract() != 0.0 {
+            Err("must be a whole number above 0")
+        } else if fps > interpolated_fps {
+            Err("can't be higher than the interpolated fps")
+        } else {
+            Ok(())
+        }
+    });
+    config = set_value(
+        &config,
+        "blending",
+        "output_fps",
+        &(output_fps as i32).to_string(),
+    );
+
+    let amount = ask_number(
+        "Blur amount (0 is none, 1 blends every frame)",
+        1.0,
+        |amount| (amount >= 0.0).then_some(()).ok_or("can't be negative"),
+    );
+    config = set_value(&config, "blending", "amount", &format!("{:?}", amount));
+
+    let quality = ask_number("Quality (crf, lower is better)", 18.0, |quality| {
+        if (0.0..=51.0).contains(&quality) && quality.fract() == 0.0 {
+            Ok(())
+        } else {
+            Err("must be a whole number from 0 to 51")
+        }
+    });
+    config = set_value(
+        &config,
+        "encoding",
+        "quality",
+        &(quality as i32).to_string(),
+    );
+
+    write(&config_file, &config);
+}
+
+/// Blur of a single video picked in the ui, over the loaded settings
+pub struct FileSettings {
+    interpolated_fps: f32,
+    output_fps: i32,
+    amount: f32,
+}
+
+impl FileSettings {
+    pub fn apply(&self, settings: &mut Config) {
+        settings.interpolation.fps = self.interpolated_fps;
+        settings.blending.output_fps = self.output_fps;
+        settings.blending.amount = self.amount;
+        settings.blending.shutter_angle = 0.0;
+    }
+}
+
+/// Asks whether a video keeps the loaded settings or gets its own fps and blur amount, defaulting
+/// to the loaded values
+pub fn per_file(video: &Path, settings: &Config) -> Option<FileSettings> {
+    let name = video.file_name().unwrap_or_default().to_string_lossy();
+    if confirm(&format!("Use the configured settings for {}?", name), true) {
+        return None;
+    }
+    let interpolated_fps = ask_number("Interpolated fps", settings.interpolation.fps, |fps| {
+        (fps > 0.0).then_some(()).ok_or("must be above 0")
+    });
+    let output_fps = ask_number("Output fps", settings.blending.output_fps as f32, |fps| {
+        if fps <= 0.0 || fps.fract() != 0.0 {
+            Err("must be a whole number above 0")
+        } else if fps > interpolated_fps {
+            Err("can't be higher than the interpolated fps")
+        } else {
+            Ok(())
+        }
+    });
+    let amount = ask_number(
+        "Blur amount (0 is none, 1 blends every frame)",
+        settings.blending.effective_amount(),
+        |amount| (amount >= 0.0).then_some(()).ok_or("can't be negative"),
+    );
+    Some(FileSettings {
+        interpolated_fps,
+        output_fps: output_fps as i32,
+        amount,
+    })
+}
+
+/// Sets teres up on its first launch: picks a preset, detects the GPU encoder and renders a short
+/// test clip to check that ffmpeg, VapourSynth and the plugins work together before real footage
+pub fn onboard() {
+    let config_file = Config::global_path();
+    eprintln!(
+        "Welcome to teres, there is no config at {} yet",
+        config_file.display()
+    );
+    if !confirm("Set up teres now?", true) {
+        write(&config_file, DEFAULT_CONFIG);
+        info!("Using the default settings, run teres config init to change them");
+        return;
+    }
+
+    let names: Vec<&str> = presets::BUILTIN.iter().map(|(name, _)| *name).collect();
+    for (name, template) in presets::BUILTIN {
+        let description = template.lines().next().unwrap_or_default();
+        eprintln!("  {}: {}", name, description.trim_start_matches("# "));
+    }
+    let preset = loop {
+        let name = ask(&format!("Preset ({})", names.join("/")), names[0]).to_lowercase();
+        match presets::builtin(&name) {
+            Some(preset) => break preset,
+            None => eprintln!("Expected {}", names.join(", ")),
+        }
+    };
+    let mut config = apply_preset(DEFAULT_CONFIG, &preset, "");
+
+    eprintln!("Checking for GPU encoders...");
+    let mut gpu = false;
+    match detect_gpu() {
+        Some(gpu_type)
+            if confirm(
+                &format!("Found a working {} encoder, use it?", gpu_type),
+                true,
+            ) =>
+        {
+            gpu = true;
+            config = set_value(&config, "advanced.encoding", "gpu", "true");
+            config = set_value(
+                &config,
+                "advanced.encoding",
+                "gpu_type",
+                &format!("\"{}\"", gpu_type),
+            );
+        }
+        Some(_) => {}
+        None => eprintln!("No GPU encoder works with your ffmpeg, rendering on the CPU"),
+    }
+    write(&config_file, &config);
+
+    if !confirm(
+        "Render a 5 second test clip to check everything works?",
+        true,
+    ) {
+        return;
+    }
+    if test_render() {
+        info!("The test render worked, teres is ready");
+        return;
+    }
+    if gpu && confirm("The test render failed, try again without the GPU?", true) {
+        config = set_value(&config, "advanced.encoding", "gpu", "false");
+        write(&config_file, &config);
+        if test_render() {
+            info!("The test render worked on the CPU, teres is ready");
+            return;
+        }
+    }
+    warn!("The test render failed, run teres doctor to check what is missing");
+}
+
+/// Writes every value of a preset into the config template
+fn apply_preset(template: &str, preset: &toml::Value, section: &str) -> String {
+    let Some(table) = preset.as_table() else {
+        return template.to_string();
+    };
+    let mut config = template.to_string();
+    for (key, value) in table {
+        config = match value {
+            toml::Value::Table(_) if section.is_empty() => apply_preset(&config, value, key),
+            toml::Value::Table(_) => apply_preset(&config, value, &format!("{}.{}", section, key)),
+            _ => set_value(&config, section, key, &value.to_string()),
+        };
+    }
+    config
+}
+
+/// The first GPU type whose encoder works on this machine, found by encoding a few blank frames
+/// since ffmpeg lists encoders it was built with even without the hardware for them
+fn detect_gpu() -> Option<&'static str> {
+    let candidates: &[(&str, &str)] = if cfg!(target_os = "macos") {
+        &[("apple", "h264_videotoolbox")]
+    } else {
+        &[
+            ("nvidia", "h264_nvenc"),
+            ("amd", "h264_amf"),
+            ("intel", "h264_qsv"),
+            ("vaapi", "h264_vaapi"),
+        ]
+    };
+    candidates
+        .iter()
+        .find(|(_, encoder)| encoder_works(encoder))
+        .map(|(gpu_type, _)| *gpu_type)
+}
+
+fn encoder_works(encoder: &str) -> bool {
+    let mut command = Command::new(binaries::ffmpeg());
+    command.args(["-hide_banner", "-v", "error"]);
+    if encoder == "h264_vaapi" {
+        command.args(["-vaapi_device", "/dev/dri/renderD128"]);
+    }
+    command.args(["-f", "lavfi", "-i", "color=size=256x256:duration=0.1"]);
+    if encoder == "h264_vaapi" {
+        command.args(["-vf", "format=nv12,hwupload"]);
+    }
+    command
+        .args(["-c:v", encoder, "-f", "null", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Renders a generated 5 second clip with the saved config by running teres on it like on real
+/// footage, so the whole pipeline is checked
+fn test_render() -> bool {
+    let folder = std::env::temp_dir().join(format!("teres-test-{}", rand::random::<u16>()));
+    let clip = folder.join("test.mp4");
+    let rendered = std::fs::create_dir_all(&folder).is_ok()
+        && Command::new(binaries::ffmpeg())
+            .args(["-hide_banner", "-v", "error", "-y"])
+            .args([
+                "-f",
+                "lavfi",
+                "-i",
+                "testsrc2=size=1280x720:rate=60:duration=5",
+            ])
+            .args(["-f", "lavfi", "-i", "sine=frequency=440:duration=5"])
+            .args([
+                "-c:v",
+                "libx264",
+                "-pix_fmt",
+                "yuv420p",
+                "-c:a",
+                "aac",
+                "-shortest",
+            ])
+            .arg(&clip)
+            .stdin(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+        && std::env::current_exe()
+            .and_then(|teres| {
+                Command::new(teres)
+                    .arg("-n")
+                    .arg(&clip)
+                    // without a terminal the test render doesn't wait for enter when it's done
+                    .stdin(Stdio::null())
+                    .status()
+            })
+            .map(|status| status.success())
+            .unwrap_or(false);
+    let _ = std::fs::remove_dir_all(&folder);
+    rendered
+}
+
+fn write(config_file: &Path, config: &str) {
+    if let Err(error) = std::fs::create_dir_all(config_file.parent().unwrap())
+        .and_then(|_| std::fs::write(config_file, config))
+    {
+        error!("Could not write {}: {}", config_file.display(), error);
+        helpers::exit(exitcode::CANTCREAT);
+    }
+    info!("Wrote {}", config_file.display());
+}
+
+/// Replaces the value of `key` in `[section]` of a config template, keeping its comment
+pub fn set_value(template: &str, section: &str, key: &str, value: &str) -> String {
+    let mut current_section = String::new();
+    let mut lines = vec![];
+    for line in template.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            current_section = trimmed[1..trimmed.len() - 1].to_string();
+        }
+        let line_key = trimmed.split('=').next().unwrap_or_default().trim();
+        if current_section == section && line_key == key && trimmed.contains('=') {
+            let comment = line.find(" #").map(|index| &line[index..]).unwrap_or("");
+            lines.push(format!("{} = {}{}", key, value, comment));
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    lines.join("\n")
+}
+
+fn encoder_available(encoder: &str) -> bool {
+    Command::new(binaries::ffmpeg())
+        .args(["-hide_banner", "-encoders"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains(encoder))
+        .unwrap_or(false)
+}
+
+fn ask(question: &str, default: &str) -> String {
+    eprint!("{} [{}]: ", question, default);
+    io::stderr().flush().unwrap();
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).unwrap();
+    let answer = answer.trim();
+    if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer.to_string()
+    }
+}
+
+fn confirm(question: &str, default: bool) -> bool {
+    loop {
+        let answer = ask(question, if default { "Y/n" } else { "y/N" }).to_lowercase();
+        match answer.as_str() {
+            "y/n" => return default,
+            "y" | "yes" => return true,
+            "n" | "no" => return false,
+            _ => eprintln!("Expected y or n"),
+        }
+    }
+}
+
+fn ask_number(
+    question: &str,
+    default: f32,
+    validate: impl Fn(f32) -> Result<(), &'static str>,
+) -> f32 {
+    loop {
+        let answer = ask(question, &default.to_string());
+        match answer.parse::<f32>() {
+            Ok(number) => match validate(number) {
+                Ok(()) => return number,
+                Err(reason) => eprintln!("{} {}", question, reason),
+            },
+            Err(_) => eprintln!("{} is not a number", answer),
+        }
+    }
+}
@@ -13,3 +13,91 @@ fn file_doesnt_exist() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn rename_path_doesnt_exist() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("teres")?;
+
+    cmd.args(["rename", "--template", "{name}", "test/file/doesnt/exist"]);
+    cmd.assert().success().stderr(predicate::str::contains(
+        "test/file/doesnt/exist does not exist",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn print_config_overrides() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("teres")?;
+
+    cmd.env("HOME", std::env::temp_dir().join("teres-test-home"))
+        .args(["--print-config", "--set", "blending.amount=0.25"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("amount = 0.25 # command line"));
+
+    Ok(())
+}
+
+#[test]
+fn ffmpeg_args_override() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("teres")?;
+
+    cmd.env("HOME", std::env::temp_dir().join("teres-test-home"))
+        .args(["--print-config", "--ffmpeg-args=-tune film"]);
+    cmd.assert().success().stdout(predicate::str::contains(
+        "extra_args = [\"-tune\", \"film\"] # command line",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn invalid_when_done_action() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("teres")?;
+
+    cmd.args(["--when-done", "explode", "-n", "video.mp4"]);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Invalid --when-done action explode",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn invalid_config_value() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("teres")?;
+
+    cmd.env("HOME", std::env::temp_dir().join("teres-test-home"))
+        .args(["--set", "blending.output_fps=1000", "-n", "video.mp4"]);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Invalid config value blending.output_fps (from command line)",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn unknown_preset() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("teres")?;
+
+    cmd.env("HOME", std::env::temp_dir().join("teres-test-home"))
+        .args(["--print-config", "--preset", "missing"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown preset missing"));
+
+    Ok(())
+}
+
+#[test]
+fn invalid_matrix() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("teres")?;
+
+    cmd.args(["--matrix", "blur", "-n", "video.mp4"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid --matrix blur"));
+
+    Ok(())
+}